@@ -6,11 +6,13 @@ class Settings:
 	showWarning	= True
 	breakError	= False
 	putNormal	= True
+	packNormal	= False
 	putTangent	= False
 	putQuat		= False
 	putUv		= True
 	putColor	= True
 	compressUv	= True
+	halfUv		= False
 	doQuatInt	= False
 	fakeQuat	= 'Auto'
 	logInfo		= True
@@ -24,7 +26,7 @@ class Writer:
 		self.pos = []
 	def size_of(self, tip):
 		import struct
-		return struct.calcsize(tip)
+		return struct.calcsize('<'+tip)
 	def pack(self, tip, *args):
 		import struct
 		self.fx.write(struct.pack('<'+tip, *args))
@@ -47,6 +47,15 @@ def fix_convert(type, valist):
 		fun = lambda x: int(min(1,max(0,x))*scale)
 	return [fun(x) for x in valist]
 
+def pack_10_10_10_2(valist):
+	# signed normalized components, see FLAG_PACKED of the loader
+	result = 0
+	for i,(x,bits) in enumerate(zip(valist, (10,10,10,2))):
+		scale = (1 << (bits-1)) - 1
+		v = int(round(min(1,max(-1,x)) * scale))
+		result |= (v & ((1 << bits) - 1)) << (10*i)
+	return result
+
 
 
 class Vertex:
@@ -99,6 +108,7 @@ class Attribute:
 	# 0: not fixed, leave as is (e.g. positions)
 	# 1: fixed, already converted (e.g. bone weights)
 	# 2: fixed, needs conversion (e.g. normals)
+	# 3: fixed, needs 10:10:10:2 packing (e.g. compact normals)
 	__slots__ = 'name', 'type', 'fixed', 'data', 'interpolate'
 	def __init__(self, name, type, fixed=0):
 		self.name = name
@@ -146,8 +156,13 @@ def save_mesh(out, ob, log): # -> (Mesh, bounds, faces_per_mat)
 			size = out.size_of(a.type)
 			if (size&3) != 0:
 				log.log(2, 'w', 'Attrib %d has has non-aligned type: %d' % (a.name,tip))
-			d2 = (d if a.fixed<=1 else fix_convert(tip,d))
-			out.array(tip, d2)
+			if a.fixed == 3:
+				out.pack('L', pack_10_10_10_2(d))
+			elif tip == 'e':	# not supported by array
+				out.pack('%d%s' % (len(d),tip), *d)
+			else:
+				d2 = (d if a.fixed<=1 else fix_convert(tip,d))
+				out.array(tip, d2)
 	assert out.tell() == seqStart + km.nv*stride
 	# extra info per attribute
 	for a in km.attribs:
@@ -155,6 +170,7 @@ def save_mesh(out, ob, log): # -> (Mesh, bounds, faces_per_mat)
 		flag = 0
 		if a.fixed: flag |= 1
 		if a.interpolate: flag |= 2
+		if a.fixed == 3: flag |= 4
 		out.pack('B', flag)
 	out.end()
 	# indices
@@ -394,6 +410,8 @@ def collect_attributes(mesh, armature, groups, no_output,log):
 	if Settings.putNormal:
 		#vat = Attribute('Normal', '3f', 0)
 		vat = Attribute('Normal', '4h', 2)
+		if Settings.packNormal:
+			vat = Attribute('Normal', '1l', 3)
 		# WebGL only accept multiples of 4 for the attribute size
 		km.attribs.append(vat)
 		for v in ar_vert:
@@ -427,6 +445,8 @@ def collect_attributes(mesh, armature, groups, no_output,log):
 					vat = Attribute(name, '2H', 2)
 				elif lo > -1-threshold and hi < 1.0 + threshold:
 					vat = Attribute(name, '2h', 2)
+				elif Settings.halfUv:
+					vat = Attribute(name, '2e', 0)
 				log.log(2,'i', 'UV[%d] bounds: [%.1f,%.1f], format: %s' % (i, lo, hi, vat.type))
 			km.attribs.append(vat)
 			for v in ar_vert:
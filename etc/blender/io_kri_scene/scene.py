@@ -76,10 +76,15 @@ def cook_node(ob, log):
 	}
 
 def cook_camera(cam, log):
-	return {	#todo: ortho
+	if cam.type not in ('PERSP', 'ORTHO'):
+		log.log(2, 'w', 'Camera type %s is exported as perspective' % (cam.type))
+	return {
 		'name'	: cam.name,
+		'kind'	: 'ORTHO' if cam.type == 'ORTHO' else 'PERSP',
 		'angle'	: [cam.angle_x, cam.angle_y],
 		'range'	: [cam.clip_start, cam.clip_end],
+		'ortho_scale'	: cam.ortho_scale,
+		'shift'	: [cam.shift_x, cam.shift_y],
 		'actions' : [],
 	}
 
@@ -226,6 +231,7 @@ def save_scene(filepath, context, export_meshes, export_actions, precision):
 	nodes		= []
 	cameras		= []
 	lights		= []
+	armatures	= []
 	entities	= []
 	# ready...
 	import os
@@ -303,8 +309,9 @@ def save_scene(filepath, context, export_meshes, export_actions, precision):
 				})
 				offset += fn
 		elif ob.type == 'ARMATURE':
-			arm = cook_armature(ob.data, log)
+			current = cook_armature(ob.data, log)
 			current['node'] = ob.name
+			armatures.append(current)
 			name = ob.data.name
 			ani_path = (None, '%s/%s' % (filepath,name))[export_actions]
 			anims = save_actions_ext(ani_path, ob, 'pose', log)
@@ -333,6 +340,7 @@ def save_scene(filepath, context, export_meshes, export_actions, precision):
 		'nodes'		: nodes,
 		'cameras'	: cameras,
 		'lights'	: lights,
+		'armatures'	: armatures,
 		'entities'	: entities,
 	}
 	num_format = '%' + ('.%df' % precision)
@@ -127,15 +127,26 @@ fn main() {
         json::decode(&s).unwrap()
     };
 
-    println!("Loading asset palette...");
-    let mut scene = claymore_load::Context::new(&mut factory, root)
-                                           .load_scene(&config.palette.scene)
-                                           .unwrap();
-    scene.world.update();
-
-    if config.generate {
-        let gen = generate::Gen::new(&config.palette, &scene);
-        gen.populate(&config.palette.model, &mut scene);
+    let mut scene = {
+        println!("Loading asset palette...");
+        let mut context = claymore_load::Context::new(&mut factory, root);
+        let mut scene = context.load_scene(&config.palette.scene).unwrap();
+        scene.world.update();
+
+        if config.generate {
+            let gen = generate::Gen::new(&config.palette, &scene);
+            gen.populate(&config.palette.model, &mut scene);
+            if let Some(ref path) = config.save {
+                println!("Saving the scene...");
+                context.save_scene(&scene, path).unwrap();
+            }
+        }
+        scene
+    };
+    if config.instancing.unwrap_or(false) {
+        for group in scene.instances.iter_mut() {
+            group.upload_instances(&mut factory);
+        }
     }
 
     println!("Initializing the graphics...");
@@ -143,13 +154,11 @@ fn main() {
                                                        .unwrap();
     pipeline.background = Some([0.2, 0.3, 0.4, 1.0]);
 
-    let mut camera = match scene.cameras.first() {
-        Some(cam) => cam.clone(),
-        None => {
-            println!("No cameras found!");
-            return;
-        }
-    };
+    if scene.cameras.is_empty() {
+        println!("No cameras found, framing the scene");
+        scene.add_default_camera();
+    }
+    let mut camera = scene.cameras[0].clone();
 
     let mut last_moment = clock_ticks::precise_time_ns();
     let mut avg_time = 0;
@@ -198,7 +207,7 @@ fn main() {
 
         scene.world.update();
 
-        camera.projection.aspect = stream.get_aspect_ratio();
+        camera.projection.set_aspect(stream.get_aspect_ratio());
         let report = pipeline.render(&scene, &camera, &mut stream).unwrap();
 
         {
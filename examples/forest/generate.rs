@@ -96,19 +96,23 @@ impl ops::BitAnd for DirectionSet {
 
 
 struct Drawable<R: gfx::Resources> {
+    name: String,
     node: claymore_scene::NodeId<f32>,
     mesh: gfx::Mesh<R>,
     bound: cgmath::Aabb3<f32>,
     fragments: Vec<claymore_scene::Fragment<R>>,
+    source: Option<claymore_scene::Source>,
 }
 
 impl<R: gfx::Resources> Drawable<R> {
     pub fn new(ent: &claymore_scene::Entity<R, f32>) -> Drawable<R> {
         Drawable {
+            name: ent.name.clone(),
             node: ent.node.clone(),
             mesh: ent.mesh.clone(),
             bound: ent.bound.clone(),
             fragments: ent.fragments.clone(),
+            source: ent.source.clone(),
         }
     }
 }
@@ -276,13 +280,16 @@ impl<R: gfx::Resources> Gen<R> {
             skeleton: None,
             bound: drawable.bound.clone(),
             fragments: drawable.fragments.clone(),
+            lods: Vec::new(),
+            source: drawable.source.clone(),
         }
     }
 
     fn make_prop(&self, base_node: claymore_scene::NodeId<f32>,
                  drawable: &Drawable<R>, position: (f32, f32), z: f32,
-                 world: &mut claymore_scene::World<f32>)
-                 -> claymore_scene::Entity<R, f32> {
+                 world: &claymore_scene::World<f32>,
+                 groups: &mut Vec<claymore_scene::Instances<R, f32>>,
+                 group_node: claymore_scene::NodeId<f32>) {
         use cgmath::{Aabb, Point, Rotation, Transform};
         let rotation = world.get_node(drawable.node)
                             .local.rot.clone();
@@ -296,23 +303,23 @@ impl<R: gfx::Resources> Gen<R> {
         let translation = world.get_node(base_node)
                                .local.transform_point(&offset);
         debug!("Found spot {:?}, ended up at pos {:?}", position, translation);
-        let node = world.add_node(
-            String::new(),
-            claymore_scene::space::Parent::None,
-            cgmath::Decomposed {
-                scale: 1.0,
-                rot: rotation,
-                disp: translation.sub_p(&bound_center),
-            });
-        claymore_scene::Entity {
-            name: String::new(),
-            visible: true,
-            mesh: drawable.mesh.clone(),
-            node: node,
-            skeleton: None,
-            bound: drawable.bound.clone(),
-            fragments: drawable.fragments.clone(),
-        }
+        let group_id = match groups.iter().position(|g| g.name == drawable.name) {
+            Some(id) => id,
+            None => {
+                let mut group = claymore_scene::Instances::new(
+                    drawable.mesh.clone(), group_node, drawable.bound.clone());
+                group.name = drawable.name.clone();
+                group.fragments = drawable.fragments.clone();
+                group.source = drawable.source.clone();
+                groups.push(group);
+                groups.len() - 1
+            },
+        };
+        groups[group_id].add_instance(cgmath::Decomposed {
+            scale: 1.0,
+            rot: rotation,
+            disp: translation.sub_p(&bound_center),
+        });
     }
 
     pub fn populate(&self, model: &reflect::Model,
@@ -320,6 +327,7 @@ impl<R: gfx::Resources> Gen<R> {
         use std::collections::HashMap;
         type Position = (i32, i32);
         scene.entities.clear();
+        scene.instances.clear();
         println!("Generating content...");
         let mut rng = rand::thread_rng();
         let mut tile_map: HashMap<Position, Tile> = HashMap::new();
@@ -385,7 +393,12 @@ impl<R: gfx::Resources> Gen<R> {
                 }
             }
         }
-        // place props
+        // place props, grouping the instances of each drawable
+        let group_node = scene.world.add_node(
+            "Props".to_string(),
+            claymore_scene::space::Parent::None,
+            cgmath::Transform::identity());
+        let groups = &mut scene.instances;
         for (&(x, y), tile) in tile_map.iter() {
             use rand::Rng;
             let river_mask = self.proto_tiles[tile.proto_id].river_mask;
@@ -396,21 +409,18 @@ impl<R: gfx::Resources> Gen<R> {
                     plant_type, x, y, river_mask);
                 let spots = self.get_water_spots(river_mask);
                 let position = spots[rng.gen_range(0, spots.len())];
-                let entity = self.make_prop(tile.node, &self.water_plants[plant_type],
-                    position, model.water_height, &mut scene.world);
-                scene.entities.push(entity);
+                self.make_prop(tile.node, &self.water_plants[plant_type],
+                    position, model.water_height, &scene.world, groups, group_node);
             }
             // tents
             let mut has_tent = false;
             if river_mask == SET_EMPTY && rng.next_f32() < model.tent_chance {
                 let tent_type = rng.gen_range(0, self.tents.len());
-                let tent_entity = self.make_prop(tile.node, &self.tents[tent_type],
-                    (0.5, 0.5), model.ground_height, &mut scene.world);
-                scene.entities.push(tent_entity);
+                self.make_prop(tile.node, &self.tents[tent_type],
+                    (0.5, 0.5), model.ground_height, &scene.world, groups, group_node);
                 let fire_type = rng.gen_range(0, self.camp_fires.len());
-                let fire_entity = self.make_prop(tile.node, &self.camp_fires[fire_type],
-                    (0.5, 1.1), model.ground_height, &mut scene.world);
-                scene.entities.push(fire_entity);
+                self.make_prop(tile.node, &self.camp_fires[fire_type],
+                    (0.5, 1.1), model.ground_height, &scene.world, groups, group_node);
                 debug!("Generated tent type {} with fire type {} on tile ({}, {})",
                     tent_type, fire_type, x, y);
                 has_tent = true;
@@ -431,9 +441,8 @@ impl<R: gfx::Resources> Gen<R> {
                     plant_type, x, y, river_mask);
                 let spot_id = rng.gen_range(0, spots.len());
                 let position = spots.swap_remove(spot_id);
-                let entity = self.make_prop(tile.node, &self.plants[plant_type],
-                    position, model.ground_height, &mut scene.world);
-                scene.entities.push(entity);
+                self.make_prop(tile.node, &self.plants[plant_type],
+                    position, model.ground_height, &scene.world, groups, group_node);
             }
         }
     }
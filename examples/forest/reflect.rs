@@ -4,6 +4,11 @@ use std::collections::HashMap;
 pub struct Demo {
     pub name: String,
     pub generate: bool,
+    /// Path to save the generated scene to.
+    pub save: Option<String>,
+    /// Draw each group of props with instanced calls. The technique
+    /// has to place the instances with the per-instance matrices.
+    pub instancing: Option<bool>,
     pub control: Control,
     pub debug: Debug,
     pub palette: Palette,
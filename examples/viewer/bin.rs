@@ -1,3 +1,4 @@
+extern crate clock_ticks;
 extern crate env_logger;
 extern crate cgmath;
 extern crate glutin;
@@ -11,9 +12,17 @@ extern crate claymore_scene;
 
 mod control;
 
+/// Animation clips sharing the same target node and skeleton.
+struct Track {
+    target: (String, Option<String>),
+    clips: Vec<usize>,
+    current: usize,
+    player: claymore_scene::anim::Player<f32>,
+}
+
 fn main() {
     use std::env;
-    use cgmath::{vec3, FixedArray, Matrix, Transform};
+    use cgmath::{vec3, FixedArray, Transform};
     use gfx::traits::*;
     use gfx_pipeline::Pipeline;
 
@@ -48,28 +57,67 @@ fn main() {
                                                        .unwrap();
     pipeline.background = Some([0.2, 0.3, 0.4, 1.0]);
 
-    let mut camera = match scene.cameras.first() {
-        Some(cam) => cam.clone(),
-        None => {
-            println!("No cameras found in any of the scenes. Usage:");
-            println!("viewer <path_to_scene1> <path_to_scene2> ...");
-            return;
-        }
-    };
+    if scene.entities.is_empty() {
+        println!("No entities found in any of the scenes. Usage:");
+        println!("viewer <path_to_scene1> <path_to_scene2> ...");
+        return;
+    }
+    if scene.cameras.is_empty() {
+        println!("No cameras found, framing the scene");
+        scene.add_default_camera();
+    }
+    let mut camera_id = 0;
+    let mut camera = scene.cameras[camera_id].clone();
     let mut control = {
         let target_node = scene.entities[0].node;
         control::Control::new(0.005, 0.01, 0.5,
             scene.world.get_node(target_node).world.clone())
     };
 
+    // group the animations by target, playing one clip of each group at a time
+    let mut tracks: Vec<Track> = Vec::new();
+    for (i, anim) in scene.animations.iter().enumerate() {
+        let target = (
+            scene.world.get_node(anim.node).name.clone(),
+            anim.skeleton.map(|s| scene.world.get_skeleton(s).name.clone()),
+        );
+        match tracks.iter().position(|t| t.target == target) {
+            Some(t) => tracks[t].clips.push(i),
+            None => tracks.push(Track {
+                target: target,
+                clips: vec![i],
+                current: 0,
+                player: claymore_scene::anim::Player::new(true),
+            }),
+        }
+    }
+    let mut last_moment = clock_ticks::precise_time_ns();
+
     println!("Rendering...");
     'main: loop {
+        let delta = clock_ticks::precise_time_ns() - last_moment;
+        last_moment += delta;
+        let seconds = (delta/1000000) as f32 / 1000.0;
+
         for event in stream.out.window.poll_events() {
             use glutin::{Event, ElementState, MouseButton, VirtualKeyCode};
             match event {
                 Event::KeyboardInput(_, _, Some(VirtualKeyCode::Escape)) =>
                     break 'main,
                 Event::Closed => break 'main,
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VirtualKeyCode::C)) => {
+                    camera_id = (camera_id + 1) % scene.cameras.len();
+                    camera = scene.cameras[camera_id].clone();
+                    println!("Switching to camera {}", camera.name);
+                },
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VirtualKeyCode::A)) => {
+                    for track in tracks.iter_mut() {
+                        track.current = (track.current + 1) % track.clips.len();
+                        track.player = claymore_scene::anim::Player::new(true);
+                        let anim = &scene.animations[track.clips[track.current]];
+                        println!("Playing {} on {}", anim.clip.name, track.target.0);
+                    }
+                },
                 Event::MouseInput(ElementState::Pressed, MouseButton::Left) =>
                     control.rot_capture(&scene.world.get_node(camera.node).local),
                 Event::MouseInput(ElementState::Released, MouseButton::Left) =>
@@ -86,6 +134,10 @@ fn main() {
             }
         }
 
+        for track in tracks.iter_mut() {
+            let anim = &scene.animations[track.clips[track.current]];
+            track.player.update(anim, seconds, &mut scene.world);
+        }
         scene.world.update();
         let len = 0.1f32;
 
@@ -99,17 +151,11 @@ fn main() {
             debug_renderer.draw_line(r, z, [0.0, 0.0, 1.0, 0.5]);
         }
 
-        camera.projection.aspect = stream.get_aspect_ratio();
+        camera.projection.set_aspect(stream.get_aspect_ratio());
         pipeline.render(&scene, &camera, &mut stream).unwrap();
 
-        {
-            use cgmath::FixedArray;
-            //use claymore_scene::base::Camera;
-            //let proj_mx = camera.get_view_projection(&scene.world).into_fixed(); //TODO
-            let cam_inv = scene.world.get_node(camera.node).world.invert().unwrap();
-            let temp: cgmath::Matrix4<f32> = camera.projection.clone().into();
-            let proj_mx = temp.mul_m(&cam_inv.into()).into_fixed();
-            debug_renderer.render(&mut stream, proj_mx).unwrap();
+        if let Some(proj_mx) = camera.get_view_projection(&scene.world) {
+            debug_renderer.render(&mut stream, proj_mx.into_fixed()).unwrap();
         }
 
         stream.present(&mut device);
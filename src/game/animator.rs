@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use scene;
+use scene::anim::{self, Animation, Player};
+use load::Scalar;
+use reflect;
+
+type Samples = Vec<(String, anim::Sample<Scalar>)>;
+
+/// Playing animation, referenced by its index in the scene.
+struct Track {
+    anim: usize,
+    player: Player<Scalar>,
+}
+
+impl Track {
+    fn new(anim: usize, looped: bool) -> Track {
+        Track {
+            anim: anim,
+            player: Player::new(looped),
+        }
+    }
+
+    fn advance(&mut self, delta: Scalar, animations: &[Animation<Scalar>]) {
+        self.player.advance(delta, animations[self.anim].clip.duration);
+    }
+
+    fn is_done(&self, animations: &[Animation<Scalar>]) -> bool {
+        self.player.is_done(animations[self.anim].clip.duration)
+    }
+
+    fn sample(&self, animations: &[Animation<Scalar>]) -> Samples {
+        animations[self.anim].clip.sample(self.player.time)
+    }
+}
+
+/// State machine of a character, crossfading between the clips
+/// of its states and adding the layers on top.
+pub struct Animator {
+    desc: reflect::Animation,
+    clips: HashMap<String, usize>,
+    state: String,
+    current: Option<Track>,
+    /// Previous state tracks, oldest first, with the time since
+    /// the transition away from each of them. Switching during
+    /// a crossfade keeps the tracks it was blending.
+    fading: Vec<(Track, Scalar)>,
+    layers: Vec<(Track, Scalar)>,
+}
+
+impl Animator {
+    /// Create the state machine, using the scene animations
+    /// starting from `first` as the clips.
+    pub fn new(desc: reflect::Animation, animations: &[Animation<Scalar>], first: usize)
+               -> Animator {
+        let mut clips = HashMap::new();
+        for (i, a) in animations.iter().enumerate().skip(first) {
+            clips.insert(a.clip.name.clone(), i);
+        }
+        let layers = desc.layers.iter().filter_map(|l| match clips.get(&l.clip) {
+            Some(&i) => Some((Track::new(i, true), l.weight)),
+            None => {
+                error!("Layer clip {} is not found", l.clip);
+                None
+            },
+        }).collect();
+        let initial = desc.initial.clone();
+        let mut animator = Animator {
+            desc: desc,
+            clips: clips,
+            state: String::new(),
+            current: None,
+            fading: Vec::new(),
+            layers: layers,
+        };
+        animator.switch(&initial);
+        animator
+    }
+
+    fn switch(&mut self, name: &str) {
+        let state = match self.desc.states.get(name) {
+            Some(s) => s,
+            None => {
+                error!("Animation state {} is not found", name);
+                return
+            },
+        };
+        debug!("Animation state {} -> {}", self.state, name);
+        let track = match self.clips.get(&state.clip) {
+            Some(&i) => Some(Track::new(i, state.looped)),
+            None => {
+                error!("Clip {} of state {} is not found", state.clip, name);
+                None
+            },
+        };
+        if let Some(t) = self.current.take() {
+            self.fading.push((t, 0.0));
+        }
+        self.current = track;
+        self.state = name.to_string();
+    }
+
+    /// Handle an event of the current state, returning true
+    /// if it caused a transition.
+    pub fn trigger(&mut self, event: &str) -> bool {
+        let target = match self.desc.states.get(&self.state).and_then(|s| s.events.get(event)) {
+            Some(t) => t.clone(),
+            None => return false,
+        };
+        self.switch(&target);
+        true
+    }
+
+    /// Advance the clips and write the blended result into the world.
+    pub fn update(&mut self, delta: Scalar, animations: &[Animation<Scalar>],
+                  world: &mut scene::World<Scalar>) {
+        let fade = self.desc.fade;
+        for &mut (ref mut track, ref mut time) in self.fading.iter_mut() {
+            track.advance(delta, animations);
+            *time = *time + delta;
+        }
+        let mut finished = false;
+        if let Some(ref mut track) = self.current {
+            track.advance(delta, animations);
+            finished = track.is_done(animations);
+        }
+        if finished {
+            if let Some(next) = self.desc.states[&self.state].next.clone() {
+                self.switch(&next);
+            }
+        }
+        // tracks preceding a finished transition are not visible any more
+        if let Some(i) = self.fading.iter().rposition(|&(_, time)| time >= fade) {
+            for _ in 0.. i + 1 {
+                self.fading.remove(0);
+            }
+        }
+        // each track fades in over the pose blended from the previous ones
+        let mut samples = Vec::new();
+        let mut weight = None;
+        for &(ref track, time) in self.fading.iter() {
+            let next = track.sample(animations);
+            samples = match weight {
+                Some(w) => anim::blend(&samples, &next, w),
+                None => next,
+            };
+            weight = Some(time / fade);
+        }
+        let next = match self.current {
+            Some(ref track) => track.sample(animations),
+            None => Vec::new(),
+        };
+        samples = match weight {
+            Some(w) => anim::blend(&samples, &next, w),
+            None => next,
+        };
+        for &mut (ref mut track, weight) in self.layers.iter_mut() {
+            track.advance(delta, animations);
+            samples = anim::add(&samples, &track.sample(animations), weight);
+        }
+        // all the clips of a character animate the same targets
+        let target = self.current.as_ref()
+            .or(self.fading.last().map(|f| &f.0))
+            .or(self.layers.first().map(|l| &l.0));
+        if let Some(track) = target {
+            animations[track.anim].apply_samples(&samples, world);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use cgmath::{Decomposed, Quaternion, Vector3};
+    use scene;
+    use scene::anim::{Animation, AnimationClip, Channel, Curve, Key, Property};
+    use load::Scalar;
+    use reflect;
+    use super::*;
+
+    /// Clip holding the node at the given X position.
+    fn make_clip(name: &str, x: Scalar, node: scene::NodeId<Scalar>) -> Animation<Scalar> {
+        Animation {
+            clip: AnimationClip {
+                name: name.to_string(),
+                duration: 10.0,
+                channels: vec![Channel {
+                    bone: String::new(),
+                    property: Property::Position,
+                    curve: Curve {
+                        keys: vec![Key {
+                            time: 0.0,
+                            value: vec![x, 0.0, 0.0],
+                            handles: None,
+                        }],
+                        extrapolate: false,
+                    },
+                }],
+            },
+            node: node,
+            skeleton: None,
+            source: None,
+        }
+    }
+
+    fn make_state(clip: &str, event: &str, target: &str) -> reflect::AnimState {
+        let mut events = HashMap::new();
+        events.insert(event.to_string(), target.to_string());
+        reflect::AnimState {
+            clip: clip.to_string(),
+            looped: true,
+            next: None,
+            events: events,
+        }
+    }
+
+    #[test]
+    fn switch_during_fade() {
+        let mut world = scene::World::new();
+        let node = world.add_node("node".to_string(), scene::space::Parent::None, Decomposed {
+            scale: 1.0,
+            rot: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            disp: Vector3::new(0.0, 0.0, 0.0),
+        });
+        let animations = vec![make_clip("a", 0.0, node), make_clip("b", 1.0, node),
+                              make_clip("c", 2.0, node)];
+        let mut states = HashMap::new();
+        states.insert("a".to_string(), make_state("a", "go", "b"));
+        states.insert("b".to_string(), make_state("b", "go", "c"));
+        states.insert("c".to_string(), make_state("c", "go", "a"));
+        let desc = reflect::Animation {
+            initial: "a".to_string(),
+            fade: 1.0,
+            states: states,
+            layers: Vec::new(),
+        };
+        let mut animator = Animator::new(desc, &animations, 0);
+        let position = |world: &scene::World<Scalar>| world.get_node(node).local.disp.x;
+        animator.update(0.5, &animations, &mut world);
+        assert_eq!(position(&world), 0.0);
+        assert!(animator.trigger("go"));
+        animator.update(0.5, &animations, &mut world);
+        assert!((position(&world) - 0.5).abs() < 1e-5);
+        // the second transition starts from the blended pose
+        assert!(animator.trigger("go"));
+        animator.update(0.25, &animations, &mut world);
+        assert!((position(&world) - 1.0625).abs() < 1e-5, "{}", position(&world));
+        animator.update(1.0, &animations, &mut world);
+        assert!((position(&world) - 2.0).abs() < 1e-5);
+        assert!(animator.fading.is_empty());
+    }
+}
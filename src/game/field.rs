@@ -99,11 +99,11 @@ impl<R: gfx::Resources> Field<R> {
     }
 
     pub fn update_params(&mut self, camera: &scene::Camera<f32>, world: &scene::World<f32>) {
-        use cgmath::{Matrix, Transform};
-        let mx_proj: cgmath::Matrix4<f32> = camera.projection.clone().into();
-        let model_view = world.get_node(camera.node).world.invert().unwrap()
-                              .concat(&world.get_node(self.node).world);
-        self.batch.params.mvp = mx_proj.mul_m(&model_view.into()).into_fixed();
+        use cgmath::Matrix;
+        let model: cgmath::Matrix4<f32> = world.get_node(self.node).world.into();
+        if let Some(view_proj) = camera.get_view_projection(world) {
+            self.batch.params.mvp = view_proj.mul_m(&model).into_fixed();
+        }
     }
 
     pub fn draw<S: gfx::Stream<R>>(&self, stream: &mut S) {
@@ -13,6 +13,7 @@ use std::fs::File;
 use rustc_serialize::json;
 use gfx_pipeline::forward::Pipeline;
 
+mod animator;
 mod field;
 mod reflect;
 
@@ -33,6 +34,7 @@ struct Character {
     team: u8,
     cell: field::Coordinate,
     node: scene::NodeId<load::Scalar>,
+    animator: Option<animator::Animator>,
 }
 
 pub struct App<R: gfx::Resources> {
@@ -79,7 +81,11 @@ impl<R: gfx::Resources> App<R> {
                         use grid::Grid2;
                         use cgmath::Point;
                         context.alpha_test = Some(desc.alpha_test);
+                        let first_anim = scene.animations.len();
                         let nid = context.extend_scene(&mut scene, &desc.scene).unwrap();
+                        let animator = desc.animation.as_ref().map(|a|
+                            animator::Animator::new(a.clone(), &scene.animations, first_anim)
+                        );
                         let node = scene.world.mut_node(nid);
                         let angle = field.grid.get_angle(convert_dir(desc.direction), cur_dir);
                         node.parent = scene::space::Parent::Domestic(field.node);
@@ -96,6 +102,7 @@ impl<R: gfx::Resources> App<R> {
                             team: ch.team,
                             cell: coord,
                             node: nid,
+                            animator: animator,
                         });
                     },
                     None => {
@@ -108,6 +115,9 @@ impl<R: gfx::Resources> App<R> {
         let mut pipeline = Pipeline::new(factory).unwrap();
         pipeline.background = Some([0.2, 0.3, 0.4, 1.0]);
         // done
+        if scene.cameras.is_empty() {
+            scene.add_default_camera();
+        }
         let camera = scene.cameras[0].clone();
         App {
             scene: scene,
@@ -119,18 +129,11 @@ impl<R: gfx::Resources> App<R> {
     }
 
     fn mouse_cast(&self, x: f32, y: f32) -> field::Coordinate {
-        use cgmath::{EuclideanVector, Matrix, Point, Transform};
-        let end_proj = cgmath::Point3::new(x*2.0 - 1.0, 1.0 - y*2.0, 0.0);
-        let mx_proj: cgmath::Matrix4<f32> = self.camera.projection.clone().into();
-        let inv_proj = mx_proj.invert().unwrap();
-        let end_cam = cgmath::Point3::from_homogeneous(
-            &inv_proj.mul_v(&end_proj.to_homogeneous())
-        );
-        let ray = cgmath::Ray3::new(cgmath::Point3::new(0.0, 0.0, 0.0),
-                                    end_cam.to_vec().normalize());
+        use cgmath::Transform;
+        let ray = self.camera.ray_from_screen(x, y, &self.scene.world)
+                             .expect("Camera transform is degenerate");
         let transform = self.scene.world.get_node(self.field.node).world
-                            .invert().unwrap()
-                            .concat(&self.scene.world.get_node(self.camera.node).world);
+                            .invert().unwrap();
         let ray_grid = transform.transform_ray(&ray);
         self.field.cast_ray(&ray_grid)
     }
@@ -156,10 +159,16 @@ impl<R: gfx::Resources> App<R> {
             },
             Some(_team) => {
                 info!("[click] attack");
+                if let Some(ref mut anim) = player.animator {
+                    anim.trigger("attack");
+                }
             },
             None => { //move
                 use cgmath::Point;
                 info!("[click] move");
+                if let Some(ref mut anim) = player.animator {
+                    anim.trigger("move");
+                }
                 player.cell = cell;
                 let node = self.scene.world.mut_node(player.node);
                 node.local.disp = self.field.get_center(cell).to_vec();
@@ -181,10 +190,19 @@ impl<R: gfx::Resources> App<R> {
         *transform = rotation.concat(transform);
     }
 
+    /// Advance the character animations by `delta` seconds.
+    pub fn update(&mut self, delta: f32) {
+        for ch in self.characters.iter_mut() {
+            if let Some(ref mut anim) = ch.animator {
+                anim.update(delta, &self.scene.animations, &mut self.scene.world);
+            }
+        }
+    }
+
     pub fn render<S: gfx::Stream<R>>(&mut self, stream: &mut S) {
         use gfx_pipeline::Pipeline;
         self.scene.world.update();
-        self.camera.projection.aspect = stream.get_aspect_ratio();
+        self.camera.projection.set_aspect(stream.get_aspect_ratio());
         self.pipeline.render(&self.scene, &self.camera, stream).unwrap();
         self.field.update_params(&self.camera, &self.scene.world);
         self.field.draw(stream);
@@ -25,6 +25,34 @@ pub struct GameChar {
     pub alpha_test: u8,
     pub direction: Direction,
     pub health: u32,
+    pub animation: Option<Animation>,
+}
+
+/// Animation state machine of a character.
+#[derive(Clone, RustcDecodable)]
+pub struct Animation {
+    pub initial: String,
+    /// Crossfade duration between the states, in seconds.
+    pub fade: f32,
+    pub states: HashMap<String, AnimState>,
+    /// Additive clips, always playing on top of the states.
+    pub layers: Vec<AnimLayer>,
+}
+
+#[derive(Clone, RustcDecodable)]
+pub struct AnimState {
+    pub clip: String,
+    pub looped: bool,
+    /// State to switch to once the clip is over.
+    pub next: Option<String>,
+    /// States to switch to on the named events.
+    pub events: HashMap<String, String>,
+}
+
+#[derive(Clone, RustcDecodable)]
+pub struct AnimLayer {
+    pub clip: String,
+    pub weight: f32,
 }
 
 #[derive(RustcDecodable)]
@@ -0,0 +1,202 @@
+extern crate claymore_load;
+
+use std::env;
+use std::io;
+use std::fs::File;
+use claymore_load::{chunk, mesh};
+
+
+/// Walk a single mesh, printing its layout if `verbose` is set.
+/// Returns the mesh name and the list of detected problems.
+fn inspect<I: io::Read>(reader: &mut chunk::Root<I>, verbose: bool)
+           -> Result<(String, Vec<String>), mesh::Error> {
+    let mut problems = Vec::new();
+    let mut cmesh = try!(reader.enter());
+    if cmesh.get_name() != "mesh" {
+        return Err(mesh::Error::Signature(cmesh.get_name().to_string()))
+    }
+    let name = try!(cmesh.read_str()).to_string();
+    let n_vert = try!(cmesh.read_u32());
+    let topology = try!(cmesh.read_str()).to_string();
+    if verbose {
+        println!("mesh ({} bytes): '{}', {} vertices", cmesh.get_size(), name, n_vert);
+    }
+    match mesh::parse_topology(&topology) {
+        Some(prim) if verbose => println!("\ttopology: {:?}", prim),
+        Some(_) => (),
+        None => problems.push(format!("unknown topology '{}'", topology)),
+    }
+    let mut has_index = false;
+    while cmesh.has_more() {
+        let mut cbuf = try!(cmesh.enter());
+        let chunk_name = cbuf.get_name().to_string();
+        if verbose {
+            println!("\t{} ({} bytes)", chunk_name, cbuf.get_size());
+        }
+        match &chunk_name[..] {
+            "buffer" => {
+                let buf = match mesh::read_buffer(&mut *cbuf, n_vert) {
+                    Ok(b) => b,
+                    Err(mesh::Error::Format(f)) => {
+                        problems.push(format!("bad buffer format '{}'", f));
+                        try!(cbuf.ignore());
+                        continue
+                    },
+                    Err(mesh::Error::Instanced(name)) => {
+                        problems.push(format!("attribute {} is marked instanced", name));
+                        try!(cbuf.ignore());
+                        continue
+                    },
+                    Err(e) => return Err(e),
+                };
+                try!(cbuf.leave());
+                if verbose {
+                    println!("\t\tstride: {}", buf.stride);
+                }
+                let mut offset = 0u32;
+                for at in buf.attributes.iter() {
+                    match mesh::get_element_type(at) {
+                        Ok((count, el_type)) => {
+                            if verbose {
+                                println!("\t\t{}: {}{} as {} x {:?}, offset {}, flags {}",
+                                    at.name, at.count, at.type_, count, el_type,
+                                    offset, at.flags);
+                            }
+                            offset += count as u32 * el_type.get_size() as u32;
+                        },
+                        Err(_) => problems.push(format!(
+                            "attribute {} has bad type '{}' with flags {}",
+                            at.name, at.type_, at.flags)),
+                    }
+                }
+                match mesh::get_formats(&buf) {
+                    Err(mesh::Error::Stride(stride, total)) => problems.push(format!(
+                        "buffer stride is {}, but the attributes take {} bytes",
+                        stride, total)),
+                    _ => (),
+                }
+            },
+            "instance" => {
+                let buf = match mesh::read_instances(&mut *cbuf) {
+                    Ok(b) => b,
+                    Err(mesh::Error::Format(f)) => {
+                        problems.push(format!("bad instance format '{}'", f));
+                        try!(cbuf.ignore());
+                        continue
+                    },
+                    Err(mesh::Error::Instanced(name)) => {
+                        problems.push(format!("instance attribute {} is not marked instanced", name));
+                        try!(cbuf.ignore());
+                        continue
+                    },
+                    Err(e) => return Err(e),
+                };
+                try!(cbuf.leave());
+                if verbose {
+                    println!("\t\tstride: {}, instances: {}", buf.stride,
+                        buf.data.len() / ::std::cmp::max(buf.stride as usize, 1));
+                    for at in buf.attributes.iter() {
+                        println!("\t\t{}: {}{}, flags {}", at.name, at.count, at.type_, at.flags);
+                    }
+                }
+                match mesh::get_formats(&buf) {
+                    Err(mesh::Error::Stride(stride, total)) => problems.push(format!(
+                        "instance stride is {}, but the attributes take {} bytes",
+                        stride, total)),
+                    Err(_) => problems.push("instance attributes have bad types".to_string()),
+                    Ok(_) => (),
+                }
+            },
+            "index" => {
+                if has_index {
+                    problems.push("extra index chunk".to_string());
+                }
+                has_index = true;
+                let indices = match mesh::read_indices(&mut *cbuf) {
+                    Ok(i) => i,
+                    Err(mesh::Error::IndexType(t)) => {
+                        problems.push(format!("bad index type '{}'", t));
+                        try!(cbuf.ignore());
+                        continue
+                    },
+                    Err(e) => return Err(e),
+                };
+                try!(cbuf.leave());
+                let (kind, count, max) = match indices {
+                    mesh::Indices::U8(ref d) =>
+                        ("u8", d.len(), d.iter().map(|&i| i as u32).max()),
+                    mesh::Indices::U16(ref d) =>
+                        ("u16", d.len(), d.iter().map(|&i| i as u32).max()),
+                    mesh::Indices::U32(ref d) =>
+                        ("u32", d.len(), d.iter().map(|&i| i).max()),
+                };
+                if verbose {
+                    println!("\t\t{} x {}", count, kind);
+                }
+                match max {
+                    Some(m) if m >= n_vert => problems.push(format!(
+                        "index {} is out of {} vertices", m, n_vert)),
+                    _ => (),
+                }
+            },
+            other => {
+                problems.push(format!("unknown chunk '{}'", other));
+                try!(cbuf.ignore());
+            },
+        }
+    }
+    Ok((name, problems))
+}
+
+fn main() {
+    let mut validate = false;
+    let mut paths = Vec::new();
+    for arg in env::args().skip(1) {
+        match &arg[..] {
+            "--validate" => validate = true,
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        println!("Usage: k3inspect [--validate] <path.k3mesh> ...");
+        return;
+    }
+
+    let (mut num_meshes, mut num_problems) = (0, 0);
+    for path in paths.iter() {
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) => {
+                println!("{}: unable to open: {}", path, e);
+                num_problems += 1;
+                continue
+            },
+        };
+        let size = file.metadata().map(|m| m.len() as u32).unwrap_or(0);
+        if !validate {
+            println!("{} ({} bytes)", path, size);
+        }
+        let mut reader = chunk::Root::new(path.clone(), io::BufReader::new(file));
+        while reader.get_pos() < size {
+            match inspect(&mut reader, !validate) {
+                Ok((name, problems)) => {
+                    num_meshes += 1;
+                    num_problems += problems.len();
+                    for p in problems.iter() {
+                        println!("{}: mesh '{}': {}", path, name, p);
+                    }
+                },
+                Err(e) => {
+                    // the stream is out of sync, nothing else can be read
+                    println!("{}: error at {}: {:?}", path, reader.get_pos(), e);
+                    num_problems += 1;
+                    break
+                },
+            }
+        }
+    }
+    println!("{} meshes inspected, {} problems found", num_meshes, num_problems);
+    if validate && num_problems != 0 {
+        std::process::exit(1);
+    }
+}
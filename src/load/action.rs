@@ -0,0 +1,187 @@
+use std::io;
+use claymore_scene::anim;
+use ::chunk;
+use ::chunk::Root;
+use super::Scalar;
+
+pub type Clip = anim::AnimationClip<Scalar>;
+
+#[derive(Debug)]
+pub enum Error {
+    Path(io::Error),
+    Read(chunk::Error),
+    NameNotInCollection,
+    Chunk(String),
+    Signature(String),
+}
+
+impl From<chunk::Error> for Error {
+    fn from(e: chunk::Error) -> Error {
+        Error::Read(e)
+    }
+}
+
+/// Split a data path, like `pose.bones["Arm"].location`,
+/// into the bone name and the animated property.
+fn parse_data_path(path: &str) -> (String, Option<anim::Property>) {
+    let (bone, attrib) = if path.starts_with("pose.bones[\"") {
+        let rest = &path["pose.bones[\"".len()..];
+        match rest.find("\"].") {
+            Some(p) => (&rest[..p], &rest[p+3..]),
+            None => return (String::new(), None),
+        }
+    } else {
+        ("", path)
+    };
+    let property = match attrib {
+        "location" => Some(anim::Property::Position),
+        "rotation_quaternion" => Some(anim::Property::Rotation),
+        "rotation_euler" => Some(anim::Property::RotationEuler),
+        "scale" => Some(anim::Property::Scale),
+        _ => None,
+    };
+    (bone.to_string(), property)
+}
+
+fn read_floats<I: io::Read>(reader: &mut Root<I>, count: u8) -> Result<Vec<Scalar>, Error> {
+    let mut values = Vec::with_capacity(count as usize);
+    for _ in 0.. count {
+        values.push(try!(reader.read_f32()));
+    }
+    Ok(values)
+}
+
+/// Read the contents of a `curve` chunk, holding `count` components per key.
+pub fn read_curve<I: io::Read>(ccurve: &mut Root<I>, count: u8)
+                  -> Result<anim::Curve<Scalar>, Error> {
+    let num_keys = try!(ccurve.read_u16());
+    let mut curve = anim::Curve {
+        keys: Vec::with_capacity(num_keys as usize),
+        extrapolate: false,
+    };
+    if num_keys == 0 {
+        return Ok(curve)
+    }
+    curve.extrapolate = try!(ccurve.read_bool());
+    let bezier = try!(ccurve.read_bool());
+    debug!("\t\tkeys: {}, extrapolate: {}, bezier: {}",
+        num_keys, curve.extrapolate, bezier);
+    for _ in 0.. num_keys {
+        let time = try!(ccurve.read_f32());
+        let value = try!(read_floats(ccurve, count));
+        let handles = if bezier {
+            let left = try!(read_floats(ccurve, count));
+            let right = try!(read_floats(ccurve, count));
+            Some((left, right))
+        } else {
+            None
+        };
+        curve.keys.push(anim::Key {
+            time: time,
+            value: value,
+            handles: handles,
+        });
+    }
+    Ok(curve)
+}
+
+/// Read the contents of an `action` chunk.
+fn read_action<I: io::Read>(caction: &mut Root<I>) -> Result<Clip, Error> {
+    let name = try!(caction.read_str()).to_string();
+    let duration = try!(caction.read_f32());
+    info!("\tname: {}, duration: {}", name, duration);
+    let mut clip = anim::AnimationClip {
+        name: name,
+        duration: duration,
+        channels: Vec::new(),
+    };
+    while caction.has_more() {
+        let mut ccurve = try!(caction.enter());
+        if ccurve.get_name() != "curve" {
+            return Err(Error::Chunk(ccurve.get_name().to_string()))
+        }
+        let data_path = try!(ccurve.read_str()).to_string();
+        let count = try!(ccurve.read_u8());
+        debug!("\tcurve: {}, components: {}", data_path, count);
+        let curve = try!(read_curve(&mut *ccurve, count));
+        try!(ccurve.leave());
+        let (bone, property) = parse_data_path(&data_path);
+        let expected = match property {
+            Some(anim::Property::Rotation) => 4,
+            Some(_) => 3,
+            None => {
+                warn!("Ignoring unknown curve {} of action {}", data_path, clip.name);
+                continue
+            },
+        };
+        if count != expected {
+            warn!("Ignoring curve {} with {} components", data_path, count);
+            continue
+        }
+        clip.channels.push(anim::Channel {
+            bone: bone,
+            property: property.unwrap(),
+            curve: curve,
+        });
+    }
+    Ok(clip)
+}
+
+/// Parse the next `action` chunk into a clip.
+pub fn parse<I: io::Read>(reader: &mut Root<I>) -> Result<Clip, Error> {
+    let mut caction = try!(reader.enter());
+    if caction.get_name() != "action" {
+        return Err(Error::Signature(caction.get_name().to_string()))
+    }
+    let clip = try!(read_action(&mut *caction));
+    try!(caction.leave());
+    Ok(clip)
+}
+
+/// Parse all the actions of a collection. The exporter writes them
+/// either in sequence, or wrapped into a single `*action` chunk.
+pub fn parse_collection<I: io::Read>(reader: &mut Root<I>, size: u32)
+                        -> Result<Vec<Clip>, Error> {
+    let mut clips = Vec::new();
+    while reader.get_pos() < size {
+        let mut chunk = try!(reader.enter());
+        let name = chunk.get_name().to_string();
+        match &name[..] {
+            "action" => clips.push(try!(read_action(&mut *chunk))),
+            "*action" => while chunk.has_more() {
+                clips.push(try!(parse(&mut *chunk)));
+            },
+            other => return Err(Error::Signature(other.to_string())),
+        }
+        try!(chunk.leave());
+    }
+    Ok(clips)
+}
+
+#[cfg(test)]
+mod test {
+    use claymore_scene::anim::Property;
+    use super::parse_data_path;
+
+    #[test]
+    fn node_paths() {
+        assert_eq!(parse_data_path("location"), (String::new(), Some(Property::Position)));
+        assert_eq!(parse_data_path("rotation_quaternion"),
+                   (String::new(), Some(Property::Rotation)));
+        assert_eq!(parse_data_path("rotation_euler"),
+                   (String::new(), Some(Property::RotationEuler)));
+        assert_eq!(parse_data_path("scale"), (String::new(), Some(Property::Scale)));
+        assert_eq!(parse_data_path("data.lens"), (String::new(), None));
+    }
+
+    #[test]
+    fn bone_paths() {
+        assert_eq!(parse_data_path("pose.bones[\"x\"].location"),
+                   ("x".to_string(), Some(Property::Position)));
+        assert_eq!(parse_data_path("pose.bones[\"Arm.L\"].rotation_quaternion"),
+                   ("Arm.L".to_string(), Some(Property::Rotation)));
+        assert_eq!(parse_data_path("pose.bones[\"x\"].bbone_in"),
+                   ("x".to_string(), None));
+        assert_eq!(parse_data_path("pose.bones[\"x"), (String::new(), None));
+    }
+}
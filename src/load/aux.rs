@@ -1,21 +1,27 @@
 use std::io;
 
 pub trait ReadExt {
-    fn read_u8(&mut self) -> Result<u8, io::Error>;
-    fn read_u32(&mut self) -> Result<u32, io::Error>;
+    /// Read until `buf` is full or the stream ends, returning the number
+    /// of bytes actually read.
+    fn read_full(&mut self, buf: &mut [u8]) -> Result<usize, io::Error>;
 }
 
 impl<R: io::Read> ReadExt for R {
-    fn read_u8(&mut self) -> Result<u8, io::Error> {
-        let mut res = [0u8; 1];
-        self.read(&mut res).map(|_| res[0])
+    fn read_full(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let mut total = 0;
+        while total < buf.len() {
+            match self.read(&mut buf[total..]) {
+                Ok(0) => break,
+                Ok(n) => total += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(total)
     }
+}
 
-    fn read_u32(&mut self) -> Result<u32, io::Error> {
-        let mut buf = [0u8; 4];
-        self.read(&mut buf).map(|_| {
-            ((buf[0] as u32) << 0) | ((buf[1] as u32) << 8) |
-            ((buf[2] as u32) << 16) | ((buf[3] as u32) << 24)
-        })
-    }
+pub fn decode_u32(buf: &[u8]) -> u32 {
+    ((buf[0] as u32) << 0) | ((buf[1] as u32) << 8) |
+    ((buf[2] as u32) << 16) | ((buf[3] as u32) << 24)
 }
@@ -0,0 +1,87 @@
+#![feature(test)]
+
+extern crate test;
+extern crate gfx;
+extern crate claymore_load;
+
+use std::io::Cursor;
+use claymore_load::chunk;
+use claymore_load::mesh;
+
+static NUM_MESHES: usize = 4;
+static NUM_VERTICES: u32 = 100000;
+
+/// Produce a collection of several position-only meshes, about 5Mb total.
+fn make_collection() -> Vec<u8> {
+    let mut writer = chunk::Writer::new(Cursor::new(Vec::new()));
+    for i in 0.. NUM_MESHES {
+        let data = mesh::MeshData {
+            name: format!("Mesh{}", i),
+            num_vertices: NUM_VERTICES,
+            topology: gfx::PrimitiveType::TriangleList,
+            buffers: vec![mesh::Buffer {
+                stride: 12,
+                attributes: vec![mesh::Attribute {
+                    name: "Position".to_string(),
+                    count: 3,
+                    type_: 'f',
+                    flags: 0,
+                }],
+                data: (0.. NUM_VERTICES * 12).map(|b| b as u8).collect(),
+            }],
+            instances: Vec::new(),
+            indices: Some(mesh::Indices::U32((0.. NUM_VERTICES).collect())),
+        };
+        mesh::save(&mut writer, &data).unwrap();
+    }
+    writer.into_inner().into_inner()
+}
+
+/// Walk the collection, reading all the payload with the given function.
+fn walk<F>(data: &[u8], mut read_payload: F) -> usize where
+    F: FnMut(&mut chunk::Root<Cursor<&[u8]>>, u32) -> usize
+{
+    let mut reader = chunk::Root::new(String::new(), Cursor::new(data));
+    let mut total = 0;
+    while (reader.get_pos() as usize) < data.len() {
+        let mut cmesh = reader.enter().unwrap();
+        cmesh.read_str().unwrap();
+        let n_vert = cmesh.read_u32().unwrap();
+        cmesh.read_str().unwrap();
+        while cmesh.has_more() {
+            let mut cbuf = cmesh.enter().unwrap();
+            let size = if cbuf.get_name() == "buffer" {
+                let stride = cbuf.read_u8().unwrap();
+                cbuf.read_str().unwrap();
+                n_vert * stride as u32
+            } else {
+                let n_ind = cbuf.read_u32().unwrap();
+                cbuf.read_u8().unwrap();
+                n_ind * 4
+            };
+            total += read_payload(&mut *cbuf, size);
+            cbuf.ignore().unwrap();
+        }
+    }
+    total
+}
+
+#[bench]
+fn read_bulk(bench: &mut test::Bencher) {
+    let data = make_collection();
+    bench.bytes = data.len() as u64;
+    bench.iter(|| walk(&data, |r, size| r.read_bytes(size).unwrap().len()))
+}
+
+#[bench]
+fn read_per_byte(bench: &mut test::Bencher) {
+    let data = make_collection();
+    bench.bytes = data.len() as u64;
+    bench.iter(|| walk(&data, |r, size| {
+        let mut buffer = Vec::new();
+        for _ in 0.. size {
+            buffer.push(r.read_u8().unwrap());
+        }
+        buffer.len()
+    }))
+}
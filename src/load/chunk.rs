@@ -1,20 +1,68 @@
 use std::io;
+use std::io::Read;
 use std::fmt;
 use std::ops::{Deref, DerefMut};
-use ::aux::ReadExt;
+use ::aux::{ReadExt, decode_u32};
 
 static NAME_LENGTH: u32 = 8;
+static MAX_STRING: usize = 255;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Underlying stream failure.
+    Io(io::Error),
+    /// The stream ended at the given position before the data was complete.
+    UnexpectedEof(u32),
+    /// Reading the given amount of bytes at the given position
+    /// would cross the end of the current chunk.
+    Overrun(u32, u32),
+    /// Chunk name is not a valid UTF-8 string.
+    Name(Vec<u8>),
+    /// Stored string is not a valid UTF-8 string.
+    Utf8(Vec<u8>),
+    /// Chunk with a given name ends at the first position,
+    /// which lies beyond the end of its parent (second position).
+    NestedSize(String, u32, u32),
+    /// Chunk with a given name was left with a number of bytes unread.
+    Leftover(String, u32),
+}
+
+fn read_into<R: io::Read>(input: &mut R, num: u32, pos: u32, output: &mut Vec<u8>)
+             -> Result<(), Error> {
+    // the output grows with the actual data,
+    // so a corrupted size can not trigger a huge allocation
+    match input.take(num as u64).read_to_end(output) {
+        Ok(n) if n == num as usize => Ok(()),
+        Ok(n) => Err(Error::UnexpectedEof(pos + n as u32)),
+        Err(e) => Err(Error::Io(e)),
+    }
+}
 
 pub struct Root<R> {
     pub name: String,
     input: R,
     buffer: Vec<u8>,
     position: u32,
+    limits: Vec<u32>,
 }
 
 impl<R: io::Seek> Root<R> {
-    pub fn tell(&mut self) -> u32 {
-        self.input.seek(io::SeekFrom::Current(0)).unwrap() as u32
+    pub fn tell(&mut self) -> Result<u32, Error> {
+        self.input.seek(io::SeekFrom::Current(0))
+                  .map(|p| p as u32)
+                  .map_err(|e| Error::Io(e))
+    }
+
+    /// Jump to an absolute position. Only valid outside of any chunk.
+    pub fn seek(&mut self, pos: u32) -> Result<(), Error> {
+        debug_assert!(self.limits.is_empty());
+        match self.input.seek(io::SeekFrom::Start(pos as u64)) {
+            Ok(_) => {
+                self.position = pos;
+                Ok(())
+            },
+            Err(e) => Err(Error::Io(e)),
+        }
     }
 }
 
@@ -25,6 +73,7 @@ impl<R: io::Read> Root<R> {
             input: input,
             buffer: Vec::new(),
             position: 0,
+            limits: Vec::new(),
         }
     }
 
@@ -32,61 +81,123 @@ impl<R: io::Read> Root<R> {
         self.position
     }
 
-    fn skip(&mut self, num: u32) {
-        self.read_bytes(num);
+    fn get_limit(&self) -> u32 {
+        match self.limits.last() {
+            Some(&end) => end,
+            None => !0,
+        }
     }
 
-    pub fn read_bytes(&mut self, num: u32) -> &[u8] {
-        self.position += num;
-        self.buffer.clear();
-        for _ in (0.. num) {
-            let b = self.input.read_u8().unwrap();
-            self.buffer.push(b);
+    /// Move the position forward by `num` bytes,
+    /// making sure it stays within the current chunk.
+    fn advance(&mut self, num: u32) -> Result<(), Error> {
+        match self.position.checked_add(num) {
+            Some(end) if end <= self.get_limit() => {
+                self.position = end;
+                Ok(())
+            },
+            _ => Err(Error::Overrun(self.position, num)),
         }
-        &self.buffer
     }
 
-    pub fn read_u8(&mut self) -> u8 {
-        self.position += 1;
-        self.input.read_u8().unwrap()
+    fn read_raw(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        let pos = self.position;
+        try!(self.advance(buf.len() as u32));
+        match self.input.read_full(buf) {
+            Ok(n) if n == buf.len() => Ok(()),
+            Ok(n) => Err(Error::UnexpectedEof(pos + n as u32)),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+
+    fn skip(&mut self, num: u32) -> Result<(), Error> {
+        let pos = self.position;
+        try!(self.advance(num));
+        let source = &mut self.input;
+        match io::copy(&mut source.take(num as u64), &mut io::sink()) {
+            Ok(n) if n == num as u64 => Ok(()),
+            Ok(n) => Err(Error::UnexpectedEof(pos + n as u32)),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+
+    pub fn read_bytes(&mut self, num: u32) -> Result<&[u8], Error> {
+        let pos = self.position;
+        try!(self.advance(num));
+        self.buffer.clear();
+        try!(read_into(&mut self.input, num, pos, &mut self.buffer));
+        Ok(&self.buffer)
+    }
+
+    /// Read `num` bytes into a new vector, bypassing the internal buffer.
+    pub fn read_vec(&mut self, num: u32) -> Result<Vec<u8>, Error> {
+        let pos = self.position;
+        try!(self.advance(num));
+        let mut data = Vec::new();
+        try!(read_into(&mut self.input, num, pos, &mut data));
+        Ok(data)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, Error> {
+        let mut buf = [0u8; 1];
+        try!(self.read_raw(&mut buf));
+        Ok(buf[0])
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, Error> {
+        let mut buf = [0u8; 4];
+        try!(self.read_raw(&mut buf));
+        Ok(decode_u32(&buf))
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, Error> {
+        let mut buf = [0u8; 2];
+        try!(self.read_raw(&mut buf));
+        Ok((buf[0] as u16) | ((buf[1] as u16) << 8))
     }
 
-    pub fn read_u32(&mut self) -> u32 {
-        self.position += 4;
-        self.input.read_u32().unwrap()
+    pub fn read_f32(&mut self) -> Result<f32, Error> {
+        self.read_u32().map(|v| unsafe { ::std::mem::transmute(v) })
     }
 
-    pub fn read_bool(&mut self) -> bool {
-        self.position += 1;
-        self.input.read_u8().unwrap() != 0
+    pub fn read_bool(&mut self) -> Result<bool, Error> {
+        self.read_u8().map(|b| b != 0)
     }
 
-    pub fn read_str(&mut self) -> &str {
+    pub fn read_str(&mut self) -> Result<&str, Error> {
         use std::str::from_utf8;
-        let size = self.input.read_u8().unwrap() as u32;
-        self.position += 1;
-        let buf = self.read_bytes(size);
-        from_utf8(buf).unwrap()
+        let size = try!(self.read_u8()) as u32;
+        let buf = try!(self.read_bytes(size));
+        from_utf8(buf).map_err(|_| Error::Utf8(buf.to_vec()))
     }
 
-    pub fn enter<'b>(&'b mut self) -> Chunk<'b, R> {
+    pub fn enter<'b>(&'b mut self) -> Result<Chunk<'b, R>, Error> {
         let name = {
-            let raw = self.read_bytes(NAME_LENGTH);
+            let raw = try!(self.read_bytes(NAME_LENGTH));
             let buf = match raw.iter().position(|b| *b == 0) {
                 Some(p) => &raw[..p],
                 None => raw,
             };
-            String::from_utf8_lossy(buf)
-                .into_owned()
+            match String::from_utf8(buf.to_vec()) {
+                Ok(s) => s,
+                Err(_) => return Err(Error::Name(buf.to_vec())),
+            }
         };
         debug!("Entering chunk {}", name);
-        let size = self.read_u32();
-        Chunk    {
+        let size = try!(self.read_u32());
+        let limit = self.get_limit();
+        let end_pos = match self.position.checked_add(size) {
+            Some(end) if end <= limit => end,
+            _ => return Err(Error::NestedSize(name,
+                self.position.wrapping_add(size), limit)),
+        };
+        self.limits.push(end_pos);
+        Ok(Chunk {
             name: name,
             size: size,
-            end_pos: self.position + size,
+            end_pos: end_pos,
             root: self,
-        }
+        })
     }
 }
 
@@ -108,20 +219,37 @@ impl<'a, R: io::Read> Chunk<'a, R> {
         &self.name
     }
 
+    pub fn get_size(&self) -> u32 {
+        self.size
+    }
+
     pub fn has_more(&self)-> bool {
         self.root.get_pos() < self.end_pos
     }
 
-    pub fn ignore(self) {
+    pub fn ignore(self) -> Result<(), Error> {
         let left = self.end_pos - self.root.get_pos();
         self.root.skip(left)
     }
+
+    /// Leave the chunk, making sure all of its contents were read.
+    pub fn leave(self) -> Result<(), Error> {
+        if self.has_more() {
+            let left = self.end_pos - self.root.get_pos();
+            Err(Error::Leftover(self.name.clone(), left))
+        } else {
+            Ok(())
+        }
+    }
 }
 
 impl<'a, R: io::Read> Drop for Chunk<'a, R> {
     fn drop(&mut self) {
         debug!("Leaving chunk");
-        assert!(!self.has_more())
+        if self.has_more() {
+            warn!("Chunk {} is left with unread data", self.name);
+        }
+        self.root.limits.pop();
     }
 }
 
@@ -137,3 +265,152 @@ impl<'a, R: io::Read> DerefMut for Chunk<'a, R> {
         self.root
     }
 }
+
+
+/// Chunked stream writer, compatible with `Root`
+/// and the `Writer` of the Blender exporter.
+pub struct Writer<W> {
+    output: W,
+    stack: Vec<u64>,
+}
+
+fn invalid_input(desc: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, desc)
+}
+
+impl<W: io::Write + io::Seek> Writer<W> {
+    pub fn new(output: W) -> Writer<W> {
+        Writer {
+            output: output,
+            stack: Vec::new(),
+        }
+    }
+
+    /// Finish writing, returning the output stream back.
+    pub fn into_inner(self) -> W {
+        assert!(self.stack.is_empty(), "Not all chunks are closed");
+        self.output
+    }
+
+    pub fn write_bytes(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        self.output.write_all(data)
+    }
+
+    pub fn write_u8(&mut self, value: u8) -> Result<(), io::Error> {
+        self.write_bytes(&[value])
+    }
+
+    pub fn write_u32(&mut self, value: u32) -> Result<(), io::Error> {
+        self.write_bytes(&[
+            (value >> 0) as u8, (value >> 8) as u8,
+            (value >> 16) as u8, (value >> 24) as u8,
+        ])
+    }
+
+    pub fn write_bool(&mut self, value: bool) -> Result<(), io::Error> {
+        self.write_u8(value as u8)
+    }
+
+    pub fn write_str(&mut self, value: &str) -> Result<(), io::Error> {
+        if value.len() > MAX_STRING {
+            return Err(invalid_input("String is too long"))
+        }
+        try!(self.write_u8(value.len() as u8));
+        self.write_bytes(value.as_bytes())
+    }
+
+    /// Start a new chunk. The size is written once the chunk is ended.
+    pub fn begin(&mut self, name: &str) -> Result<(), io::Error> {
+        if name.len() >= NAME_LENGTH as usize {
+            return Err(invalid_input("Chunk name is too long"))
+        }
+        let mut raw = [0u8; 8];
+        for (r, b) in raw.iter_mut().zip(name.bytes()) {
+            *r = b;
+        }
+        try!(self.write_bytes(&raw));
+        try!(self.write_u32(0));
+        let pos = try!(self.output.seek(io::SeekFrom::Current(0)));
+        self.stack.push(pos);
+        Ok(())
+    }
+
+    /// End the current chunk, patching its size.
+    pub fn end(&mut self) -> Result<(), io::Error> {
+        let start = match self.stack.pop() {
+            Some(p) => p,
+            None => return Err(invalid_input("No chunk to end")),
+        };
+        let pos = try!(self.output.seek(io::SeekFrom::Current(0)));
+        let size = pos - start;
+        if size > !0u32 as u64 {
+            return Err(invalid_input("Chunk is too large"))
+        }
+        try!(self.output.seek(io::SeekFrom::Start(start - 4)));
+        try!(self.write_u32(size as u32));
+        try!(self.output.seek(io::SeekFrom::Start(pos)));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use super::*;
+
+    fn header(name: &[u8], size: u32) -> Vec<u8> {
+        let mut data = name.to_vec();
+        data.resize(8, 0);
+        data.extend([size as u8, (size >> 8) as u8, (size >> 16) as u8, (size >> 24) as u8].iter());
+        data
+    }
+
+    #[test]
+    fn eof() {
+        let mut data = header(b"test", 10);
+        data.extend([1u8, 2].iter());
+        let mut root = Root::new("eof".to_string(), Cursor::new(data));
+        let mut chunk = root.enter().unwrap();
+        match chunk.read_bytes(10) {
+            Err(Error::UnexpectedEof(14)) => (),
+            other => panic!("Unexpected {:?}", other.map(|b| b.len())),
+        }
+    }
+
+    #[test]
+    fn overrun() {
+        let mut data = header(b"test", 2);
+        data.extend([1u8, 2, 3, 4].iter());
+        let mut root = Root::new("overrun".to_string(), Cursor::new(data));
+        let mut chunk = root.enter().unwrap();
+        match chunk.read_u32() {
+            Err(Error::Overrun(12, 4)) => (),
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bad_name() {
+        let data = header(&[0xFF, 0xFE, b'a'], 0);
+        let mut root = Root::new("name".to_string(), Cursor::new(data));
+        match root.enter() {
+            Err(Error::Name(ref raw)) if raw[..] == [0xFF, 0xFE, b'a'] => (),
+            Err(e) => panic!("Unexpected {:?}", e),
+            Ok(_) => panic!("Invalid name is accepted"),
+        }
+    }
+
+    #[test]
+    fn nested_size() {
+        let mut data = header(b"parent", 16);
+        data.extend(header(b"child", 100).into_iter());
+        data.extend([0u8; 4].iter());
+        let mut root = Root::new("nested".to_string(), Cursor::new(data));
+        let mut parent = root.enter().unwrap();
+        match parent.enter() {
+            Err(Error::NestedSize(ref name, 124, 28)) if name == "child" => (),
+            Err(e) => panic!("Unexpected {:?}", e),
+            Ok(_) => panic!("Oversized child is accepted"),
+        }
+    }
+}
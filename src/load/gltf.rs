@@ -0,0 +1,849 @@
+//! glTF 2.0 importer, supporting both the JSON (.gltf) and
+//! the binary (.glb) containers.
+//! Spec: https://github.com/KhronosGroup/glTF/tree/master/specification/2.0
+
+use std::io;
+use std::fs::File;
+use cgmath;
+use gfx;
+use rustc_serialize::json::Json;
+use claymore_scene as cs;
+use ::aux::decode_u32;
+use ::mesh;
+use super::Scalar;
+
+static GLB_MAGIC: &'static [u8] = b"glTF";
+static GLB_CHUNK_JSON: u32 = 0x4E4F534A;
+static GLB_CHUNK_BIN: u32 = 0x004E4942;
+
+#[derive(Debug)]
+pub enum Error {
+    Open(String, io::Error),
+    Read(io::Error),
+    Json(String),
+    /// Binary container is malformed.
+    Container(String),
+    /// Document does not follow the schema, with a description.
+    Format(String),
+    /// Feature of the format is not supported.
+    Unsupported(String),
+    Mesh(usize, mesh::Error),
+    Texture(String, super::TextureError),
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, Error> {
+    use std::io::Read;
+    let mut data = Vec::new();
+    match File::open(path) {
+        Ok(mut file) => match file.read_to_end(&mut data) {
+            Ok(_) => Ok(data),
+            Err(e) => Err(Error::Read(e)),
+        },
+        Err(e) => Err(Error::Open(path.to_string(), e)),
+    }
+}
+
+/// Split a binary container into the JSON and the binary chunks.
+fn parse_glb(data: &[u8]) -> Result<(Vec<u8>, Option<Vec<u8>>), Error> {
+    if data.len() < 20 || &data[..4] != GLB_MAGIC {
+        return Err(Error::Container("signature".to_string()))
+    }
+    let version = decode_u32(&data[4..]);
+    if version != 2 {
+        return Err(Error::Unsupported(format!("container version {}", version)))
+    }
+    let length = decode_u32(&data[8..]) as usize;
+    if length > data.len() {
+        return Err(Error::Container(format!("length {} > {}", length, data.len())))
+    }
+    let mut json = None;
+    let mut bin = None;
+    let mut pos = 12;
+    while pos + 8 <= length {
+        let size = decode_u32(&data[pos..]) as usize;
+        let kind = decode_u32(&data[pos+4..]);
+        let start = pos + 8;
+        if start + size > length {
+            return Err(Error::Container(format!("chunk size {} at {}", size, pos)))
+        }
+        let content = data[start.. start + size].to_vec();
+        if kind == GLB_CHUNK_JSON && json.is_none() {
+            json = Some(content);
+        } else if kind == GLB_CHUNK_BIN && bin.is_none() {
+            bin = Some(content);
+        } else {
+            debug!("Skipping GLB chunk {:x}", kind);
+        }
+        pos = start + size;
+    }
+    match json {
+        Some(j) => Ok((j, bin)),
+        None => Err(Error::Container("no JSON chunk".to_string())),
+    }
+}
+
+fn missing(what: &str, key: &str) -> Error {
+    Error::Format(format!("{} is missing '{}'", what, key))
+}
+
+fn get_array<'a>(obj: &'a Json, key: &str) -> &'a [Json] {
+    match obj.find(key).and_then(|j| j.as_array()) {
+        Some(a) => a,
+        None => &[],
+    }
+}
+
+fn get_index(obj: &Json, key: &str) -> Option<usize> {
+    obj.find(key).and_then(|j| j.as_u64()).map(|v| v as usize)
+}
+
+fn get_f32(obj: &Json, key: &str) -> Option<f32> {
+    obj.find(key).and_then(|j| j.as_f64()).map(|v| v as f32)
+}
+
+/// Read an array of numbers, expecting the given length.
+fn get_f32s(obj: &Json, key: &str, length: usize) -> Option<Vec<f32>> {
+    match obj.find(key).and_then(|j| j.as_array()) {
+        Some(a) if a.len() == length =>
+            Some(a.iter().map(|v| v.as_f64().unwrap_or(0.0) as f32).collect()),
+        Some(_) => {
+            warn!("Array '{}' is expected to have {} elements", key, length);
+            None
+        },
+        None => None,
+    }
+}
+
+fn get_item<'a>(root: &'a Json, key: &str, index: usize) -> Result<&'a Json, Error> {
+    match get_array(root, key).get(index) {
+        Some(item) => Ok(item),
+        None => Err(Error::Format(format!("{}[{}] is out of range", key, index))),
+    }
+}
+
+/// Decode the contents of a base64 data URI.
+fn decode_data_uri(uri: &str) -> Result<Vec<u8>, Error> {
+    use rustc_serialize::base64::FromBase64;
+    let encoded = match uri.find(";base64,") {
+        Some(p) => &uri[p + 8 ..],
+        None => return Err(Error::Unsupported("non-base64 data URI".to_string())),
+    };
+    encoded.from_base64().map_err(|e| Error::Format(format!("base64: {}", e)))
+}
+
+fn load_buffers(root: &Json, dir: &str, bin: Option<Vec<u8>>)
+                -> Result<Vec<Vec<u8>>, Error> {
+    let mut bin = bin;
+    let mut buffers = Vec::new();
+    for buf in get_array(root, "buffers").iter() {
+        let data = match buf.find("uri").and_then(|j| j.as_string()) {
+            Some(uri) if uri.starts_with("data:") => try!(decode_data_uri(uri)),
+            Some(uri) => try!(read_file(&format!("{}/{}", dir, uri))),
+            None => match bin.take() {
+                Some(d) => d,
+                None => return Err(missing("buffer", "uri")),
+            },
+        };
+        let length = get_index(buf, "byteLength").unwrap_or(0);
+        if data.len() < length {
+            return Err(Error::Format(format!("buffer is {} bytes, expected {}",
+                data.len(), length)))
+        }
+        buffers.push(data);
+    }
+    Ok(buffers)
+}
+
+/// Contents of an image: either an external file, or the encoded
+/// bytes from a data URI or a buffer view.
+enum Image {
+    File(String),
+    Memory(Vec<u8>),
+}
+
+fn read_image(root: &Json, buffers: &[Vec<u8>], dir: &str, index: usize)
+              -> Result<Image, Error> {
+    let image = try!(get_item(root, "images", index));
+    match image.find("uri").and_then(|j| j.as_string()) {
+        Some(uri) if uri.starts_with("data:") =>
+            return decode_data_uri(uri).map(|d| Image::Memory(d)),
+        Some(uri) => return Ok(Image::File(format!("{}/{}", dir, uri))),
+        None => (),
+    }
+    let view = match get_index(image, "bufferView") {
+        Some(v) => try!(get_item(root, "bufferViews", v)),
+        None => return Err(missing("image", "uri")),
+    };
+    let buffer = match get_index(view, "buffer").and_then(|b| buffers.get(b)) {
+        Some(b) => b,
+        None => return Err(missing("buffer view", "buffer")),
+    };
+    let start = get_index(view, "byteOffset").unwrap_or(0);
+    let end = start + get_index(view, "byteLength").unwrap_or(0);
+    if end > buffer.len() {
+        return Err(Error::Format(format!("image {} exceeds its buffer", index)))
+    }
+    Ok(Image::Memory(buffer[start.. end].to_vec()))
+}
+
+/// Tightly packed contents of an accessor.
+struct Accessor {
+    count: u32,
+    elem_count: u8,
+    type_: char,
+    normalized: bool,
+    data: Vec<u8>,
+}
+
+fn read_accessor(root: &Json, buffers: &[Vec<u8>], index: usize)
+                 -> Result<Accessor, Error> {
+    let acc = try!(get_item(root, "accessors", index));
+    if acc.find("sparse").is_some() {
+        return Err(Error::Unsupported(format!("sparse accessor {}", index)))
+    }
+    let count = match get_index(acc, "count") {
+        Some(c) => c,
+        None => return Err(missing("accessor", "count")),
+    };
+    let (type_, size) = match get_index(acc, "componentType") {
+        Some(5120) => ('b', 1),
+        Some(5121) => ('B', 1),
+        Some(5122) => ('h', 2),
+        Some(5123) => ('H', 2),
+        Some(5125) => ('L', 4),
+        Some(5126) => ('f', 4),
+        Some(other) => return Err(Error::Format(format!("component type {}", other))),
+        None => return Err(missing("accessor", "componentType")),
+    };
+    let elem_count = match acc.find("type").and_then(|j| j.as_string()) {
+        Some("SCALAR") => 1,
+        Some("VEC2") => 2,
+        Some("VEC3") => 3,
+        Some("VEC4") => 4,
+        Some(other) => return Err(Error::Unsupported(format!("accessor type {}", other))),
+        None => return Err(missing("accessor", "type")),
+    };
+    let elem_size = elem_count * size;
+    let normalized = match acc.find("normalized") {
+        Some(&Json::Boolean(b)) => b,
+        _ => false,
+    };
+    let mut data = Vec::new();
+    match get_index(acc, "bufferView") {
+        Some(view_id) => {
+            let view = try!(get_item(root, "bufferViews", view_id));
+            let buffer = match get_index(view, "buffer").and_then(|b| buffers.get(b)) {
+                Some(b) => b,
+                None => return Err(missing("buffer view", "buffer")),
+            };
+            let start = get_index(view, "byteOffset").unwrap_or(0) +
+                        get_index(acc, "byteOffset").unwrap_or(0);
+            let stride = get_index(view, "byteStride").unwrap_or(elem_size);
+            let view_end = get_index(view, "byteOffset").unwrap_or(0) +
+                           get_index(view, "byteLength").unwrap_or(0);
+            if count > 0 && (start + (count - 1) * stride + elem_size > view_end ||
+                             view_end > buffer.len()) {
+                return Err(Error::Format(format!("accessor {} exceeds its view", index)))
+            }
+            for i in 0.. count {
+                let offset = start + i * stride;
+                data.extend(buffer[offset.. offset + elem_size].iter().cloned());
+            }
+        },
+        None => data.extend((0.. count * elem_size).map(|_| 0u8)),
+    }
+    Ok(Accessor {
+        count: count as u32,
+        elem_count: elem_count as u8,
+        type_: type_,
+        normalized: normalized,
+        data: data,
+    })
+}
+
+fn get_attribute_name(semantic: &str) -> Option<String> {
+    Some(match semantic {
+        "POSITION" => "Position".to_string(),
+        "NORMAL" => "Normal".to_string(),
+        "TANGENT" => "Tangent".to_string(),
+        "JOINTS_0" => "BoneIndex".to_string(),
+        "WEIGHTS_0" => "BoneWeight".to_string(),
+        _ if semantic.starts_with("TEXCOORD_") => format!("Tex{}", &semantic[9..]),
+        _ if semantic.starts_with("COLOR_") => format!("Color{}", &semantic[6..]),
+        _ => return None,
+    })
+}
+
+/// Convert a mesh primitive into the k3mesh representation.
+fn read_primitive(root: &Json, buffers: &[Vec<u8>], name: &str, prim: &Json)
+                  -> Result<mesh::MeshData, Error> {
+    use gfx::PrimitiveType;
+    let topology = match get_index(prim, "mode").unwrap_or(4) {
+        0 => PrimitiveType::Point,
+        1 => PrimitiveType::Line,
+        3 => PrimitiveType::LineStrip,
+        4 => PrimitiveType::TriangleList,
+        5 => PrimitiveType::TriangleStrip,
+        6 => PrimitiveType::TriangleFan,
+        other => return Err(Error::Unsupported(format!("primitive mode {}", other))),
+    };
+    let attributes = match prim.find("attributes").and_then(|j| j.as_object()) {
+        Some(a) => a,
+        None => return Err(missing("primitive", "attributes")),
+    };
+    let mut data = mesh::MeshData {
+        name: name.to_string(),
+        num_vertices: 0,
+        topology: topology,
+        buffers: Vec::new(),
+        instances: Vec::new(),
+        indices: None,
+    };
+    for (semantic, value) in attributes.iter() {
+        let at_name = match get_attribute_name(semantic) {
+            Some(n) => n,
+            None => {
+                debug!("Skipping attribute {} of {}", semantic, name);
+                continue
+            },
+        };
+        let acc_id = match value.as_u64() {
+            Some(i) => i as usize,
+            None => return Err(Error::Format(format!("attribute {}", semantic))),
+        };
+        let acc = try!(read_accessor(root, buffers, acc_id));
+        if data.buffers.is_empty() {
+            data.num_vertices = acc.count;
+        } else if data.num_vertices != acc.count {
+            return Err(Error::Format(format!("attribute {} has {} elements, expected {}",
+                semantic, acc.count, data.num_vertices)))
+        }
+        data.buffers.push(mesh::Buffer {
+            stride: (acc.data.len() / ::std::cmp::max(acc.count as usize, 1)) as u8,
+            attributes: vec![mesh::Attribute {
+                name: at_name,
+                count: acc.elem_count,
+                type_: acc.type_,
+                flags: if acc.normalized {mesh::FLAG_NORMALIZED} else {0},
+            }],
+            data: acc.data,
+        });
+    }
+    if let Some(acc_id) = get_index(prim, "indices") {
+        let acc = try!(read_accessor(root, buffers, acc_id));
+        data.indices = Some(match acc.type_ {
+            'B' => mesh::Indices::U8(acc.data),
+            'H' => mesh::Indices::U16(acc.data.chunks(2).map(|c|
+                (c[0] as u16) | ((c[1] as u16) << 8)
+            ).collect()),
+            'L' => mesh::Indices::U32(acc.data.chunks(4).map(decode_u32).collect()),
+            other => return Err(Error::Mesh(acc_id, mesh::Error::IndexType(other))),
+        });
+        try!(data.check_indices().map_err(|e| Error::Mesh(acc_id, e)));
+    }
+    Ok(data)
+}
+
+/// Decompose a column-major matrix, assuming a uniform scale.
+fn decompose(m: &[f32]) -> cs::Transform<Scalar> {
+    let col = |i: usize| cgmath::Vector3::new(m[i*4], m[i*4+1], m[i*4+2]);
+    let scale = {
+        use cgmath::EuclideanVector;
+        col(0).length()
+    };
+    let s = if scale > 0.0 {1.0 / scale} else {0.0};
+    // rotation matrix elements, row then column
+    let r = |row: usize, c: usize| m[c*4 + row] * s;
+    let trace = r(0,0) + r(1,1) + r(2,2);
+    let (w, x, y, z) = if trace > 0.0 {
+        let k = 0.5 / (trace + 1.0).sqrt();
+        (0.25 / k, (r(2,1) - r(1,2)) * k, (r(0,2) - r(2,0)) * k, (r(1,0) - r(0,1)) * k)
+    } else if r(0,0) > r(1,1) && r(0,0) > r(2,2) {
+        let k = 2.0 * (1.0 + r(0,0) - r(1,1) - r(2,2)).sqrt();
+        ((r(2,1) - r(1,2)) / k, 0.25 * k, (r(0,1) + r(1,0)) / k, (r(0,2) + r(2,0)) / k)
+    } else if r(1,1) > r(2,2) {
+        let k = 2.0 * (1.0 + r(1,1) - r(0,0) - r(2,2)).sqrt();
+        ((r(0,2) - r(2,0)) / k, (r(0,1) + r(1,0)) / k, 0.25 * k, (r(1,2) + r(2,1)) / k)
+    } else {
+        let k = 2.0 * (1.0 + r(2,2) - r(0,0) - r(1,1)).sqrt();
+        ((r(1,0) - r(0,1)) / k, (r(0,2) + r(2,0)) / k, (r(1,2) + r(2,1)) / k, 0.25 * k)
+    };
+    cgmath::Decomposed {
+        scale: scale,
+        rot: cgmath::Quaternion::new(w, x, y, z).normalize(),
+        disp: cgmath::Vector3::new(m[12], m[13], m[14]),
+    }
+}
+
+fn read_transform(node: &Json) -> cs::Transform<Scalar> {
+    match get_f32s(node, "matrix", 16) {
+        Some(ref m) => decompose(m),
+        None => {
+            let t = get_f32s(node, "translation", 3).unwrap_or(vec![0.0; 3]);
+            let r = get_f32s(node, "rotation", 4).unwrap_or(vec![0.0, 0.0, 0.0, 1.0]);
+            let s = get_f32s(node, "scale", 3).unwrap_or(vec![1.0; 3]);
+            if s[0] != s[1] || s[0] != s[2] {
+                warn!("Non-uniform scale {:?} is approximated", s);
+            }
+            cgmath::Decomposed {
+                scale: (s[0] + s[1] + s[2]) / 3.0,
+                rot: cgmath::Quaternion::new(r[3], r[0], r[1], r[2]).normalize(),
+                disp: cgmath::Vector3::new(t[0], t[1], t[2]),
+            }
+        },
+    }
+}
+
+fn add_nodes(root: &Json, world: &mut cs::World<Scalar>, ids: &mut Vec<Option<cs::NodeId<Scalar>>>,
+             list: &[Json], parent: cs::Parent<Scalar>) -> Result<(), Error> {
+    for item in list.iter() {
+        let index = match item.as_u64() {
+            Some(i) => i as usize,
+            None => return Err(Error::Format("node index".to_string())),
+        };
+        let node = try!(get_item(root, "nodes", index));
+        if ids[index].is_some() {
+            return Err(Error::Format(format!("node {} has several parents", index)))
+        }
+        let name = match node.find("name").and_then(|j| j.as_string()) {
+            Some(n) => n.to_string(),
+            None => format!("node{}", index),
+        };
+        let nid = world.add_node(name, parent, read_transform(node));
+        ids[index] = Some(nid);
+        try!(add_nodes(root, world, ids, get_array(node, "children"),
+                       cs::space::Parent::Domestic(nid)));
+    }
+    Ok(())
+}
+
+fn load_material<'a, R: 'a + gfx::Resources, F: 'a + gfx::Factory<R>>(
+                 root: &Json, buffers: &[Vec<u8>], index: usize, path: &str,
+                 context: &mut super::Context<'a, R, F>)
+                 -> Result<cs::Material<R>, Error> {
+    let mat = try!(get_item(root, "materials", index));
+    let mut out = cs::Material {
+        color: [1.0, 1.0, 1.0, 1.0],
+        texture: None,
+        transparency: match mat.find("alphaMode").and_then(|j| j.as_string()) {
+            Some("BLEND") => cs::Transparency::Blend(gfx::BlendPreset::Alpha),
+            Some("MASK") => {
+                let cutoff = get_f32(mat, "alphaCutoff").unwrap_or(0.5);
+                cs::Transparency::Cutout((cutoff * 255.0) as u8)
+            },
+            _ => cs::Transparency::Opaque,
+        },
+    };
+    let pbr = match mat.find("pbrMetallicRoughness") {
+        Some(p) => p,
+        None => return Ok(out),
+    };
+    if let Some(c) = get_f32s(pbr, "baseColorFactor", 4) {
+        out.color = [c[0], c[1], c[2], c[3]];
+    }
+    let tex_id = match pbr.find("baseColorTexture").and_then(|t| get_index(t, "index")) {
+        Some(t) => t,
+        None => return Ok(out),
+    };
+    let texture = try!(get_item(root, "textures", tex_id));
+    let image_id = match get_index(texture, "source") {
+        Some(i) => i,
+        None => return Err(missing("texture", "source")),
+    };
+    // glTF images start at the top left corner, no flipping is needed
+    let (name, result) = match try!(read_image(root, buffers, get_dir(path), image_id)) {
+        Image::File(file) => {
+            let result = context.request_texture(&file, true, false);
+            (file, result)
+        },
+        Image::Memory(data) => {
+            let key = format!("{}#images[{}]", path, image_id);
+            let result = context.request_texture_memory(&key, &data, true, false);
+            (key, result)
+        },
+    };
+    let handle = match result {
+        Ok(t) => t,
+        Err(_) if context.forgive => return Ok(out),
+        Err(e) => return Err(Error::Texture(name, e)),
+    };
+    let (filter, wrap_s, wrap_t) = {
+        let sampler = match get_index(texture, "sampler") {
+            Some(s) => Some(try!(get_item(root, "samplers", s))),
+            None => None,
+        };
+        let get = |key: &str| sampler.and_then(|s| get_index(s, key));
+        fn unwrap(mode: Option<usize>) -> gfx::tex::WrapMode {
+            match mode {
+                Some(33071) => gfx::tex::WrapMode::Clamp,
+                Some(33648) => gfx::tex::WrapMode::Mirror,
+                _ => gfx::tex::WrapMode::Tile,
+            }
+        }
+        let filter = match get("minFilter") {
+            Some(9728) => gfx::tex::FilterMethod::Scale,
+            Some(9729) => gfx::tex::FilterMethod::Bilinear,
+            _ => gfx::tex::FilterMethod::Trilinear,
+        };
+        (filter, unwrap(get("wrapS")), unwrap(get("wrapT")))
+    };
+    let mut sinfo = gfx::tex::SamplerInfo::new(filter, wrap_s);
+    sinfo.wrap_mode.1 = wrap_t;
+    let sampler = context.factory.create_sampler(sinfo);
+    out.texture = Some((handle, Some(sampler)));
+    Ok(out)
+}
+
+struct Primitive<R: gfx::Resources> {
+    mesh: gfx::Mesh<R>,
+    slice: gfx::Slice<R>,
+    bound: cgmath::Aabb3<Scalar>,
+    material: Option<usize>,
+}
+
+fn load_mesh<'a, R: 'a + gfx::Resources, F: 'a + gfx::Factory<R>>(
+             root: &Json, buffers: &[Vec<u8>], index: usize,
+             context: &mut super::Context<'a, R, F>)
+             -> Result<Vec<Primitive<R>>, Error> {
+    let raw = try!(get_item(root, "meshes", index));
+    let name = match raw.find("name").and_then(|j| j.as_string()) {
+        Some(n) => n.to_string(),
+        None => format!("mesh{}", index),
+    };
+    let mut primitives = Vec::new();
+    for prim in get_array(raw, "primitives").iter() {
+        let mut data = try!(read_primitive(root, buffers, &name, prim));
+        if let Some(mode) = context.complete_meshes {
+            super::process::complete(&mut data, mode);
+        }
+        let num = match data.indices {
+            Some(_) => data.get_index_list().len() as u32,
+            None => data.num_vertices,
+        };
+        let bound = match data.compute_bound(0, num) {
+            Some(b) => b,
+            None => return Err(Error::Format(format!("mesh {} has no positions", name))),
+        };
+        let (mesh, slice) = match mesh::upload(&data, context.factory) {
+            Ok(success) => success,
+            Err(e) => return Err(Error::Mesh(index, e)),
+        };
+        primitives.push(Primitive {
+            mesh: mesh,
+            slice: slice,
+            bound: bound,
+            material: get_index(prim, "material"),
+        });
+    }
+    Ok(primitives)
+}
+
+/// Directory of a document, where the relative paths start.
+fn get_dir(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(p) => &path[..p],
+        None => ".",
+    }
+}
+
+/// Populate the scene with the contents of a parsed glTF document,
+/// read from the given path. Relative paths of the external resources
+/// start at the directory of the document.
+pub fn load_into<'a, R: 'a + gfx::Resources, F: 'a + gfx::Factory<R>>(
+                 this: &mut cs::Scene<R, Scalar>, global_parent: cs::Parent<Scalar>,
+                 root: &Json, bin: Option<Vec<u8>>, path: &str,
+                 context: &mut super::Context<'a, R, F>)
+                 -> Result<(), Error>
+{
+    match root.find("asset").and_then(|a| a.find("version")).and_then(|v| v.as_string()) {
+        Some(v) if v.starts_with("2.") => (),
+        Some(v) => return Err(Error::Unsupported(format!("version {}", v))),
+        None => return Err(missing("asset", "version")),
+    }
+    let buffers = try!(load_buffers(root, get_dir(path), bin));
+    // create world
+    let num_nodes = get_array(root, "nodes").len();
+    let mut node_ids = vec![None; num_nodes];
+    let scene_id = get_index(root, "scene").unwrap_or(0);
+    let roots = match get_array(root, "scenes").get(scene_id) {
+        Some(s) => get_array(s, "nodes").to_vec(),
+        // no scenes, instantiate all the nodes without parents
+        None => {
+            let mut is_child = vec![false; num_nodes];
+            for node in get_array(root, "nodes").iter() {
+                for c in get_array(node, "children").iter() {
+                    match c.as_u64() {
+                        Some(i) if (i as usize) < num_nodes => is_child[i as usize] = true,
+                        _ => (),
+                    }
+                }
+            }
+            (0.. num_nodes).filter(|&i| !is_child[i])
+                           .map(|i| Json::U64(i as u64))
+                           .collect()
+        },
+    };
+    // glTF is Y-up, rotate it around X to match our Z-up convention
+    let half = (0.5 as Scalar).sqrt();
+    let up_node = this.world.add_node("glTF".to_string(), global_parent, cgmath::Decomposed {
+        scale: 1.0,
+        rot: cgmath::Quaternion::new(half, half, 0.0, 0.0),
+        disp: cgmath::zero(),
+    });
+    try!(add_nodes(root, &mut this.world, &mut node_ids, &roots,
+                   cs::space::Parent::Domestic(up_node)));
+    // read cameras, entities and materials
+    let mut meshes: Vec<Option<Vec<Primitive<R>>>> =
+        (0.. get_array(root, "meshes").len()).map(|_| None).collect();
+    let mut materials: Vec<Option<cs::Material<R>>> =
+        (0.. get_array(root, "materials").len()).map(|_| None).collect();
+    let default_material = cs::Material {
+        color: [1.0, 1.0, 1.0, 1.0],
+        texture: None,
+        transparency: cs::Transparency::Opaque,
+    };
+    for (index, raw_node) in get_array(root, "nodes").iter().enumerate() {
+        let node = match node_ids[index] {
+            Some(n) => n,
+            None => continue, // not in the scene
+        };
+        let name = this.world.get_node(node).name.clone();
+        if let Some(cam_id) = get_index(raw_node, "camera") {
+            let cam = try!(get_item(root, "cameras", cam_id));
+            let projection = if let Some(p) = cam.find("perspective") {
+                let near = get_f32(p, "znear").unwrap_or(0.1);
+                Some(cs::Projection::Perspective(cgmath::PerspectiveFov {
+                    fovy: cgmath::rad(get_f32(p, "yfov").unwrap_or(1.0)),
+                    aspect: get_f32(p, "aspectRatio").unwrap_or(1.0),
+                    near: near,
+                    // infinite projections are not supported
+                    far: get_f32(p, "zfar").unwrap_or(near * 10000.0),
+                }))
+            } else if let Some(o) = cam.find("orthographic") {
+                let (xmag, ymag) = (get_f32(o, "xmag").unwrap_or(1.0),
+                                    get_f32(o, "ymag").unwrap_or(1.0));
+                Some(cs::Projection::Ortho(cgmath::Ortho {
+                    left: -xmag, right: xmag,
+                    bottom: -ymag, top: ymag,
+                    near: get_f32(o, "znear").unwrap_or(0.0),
+                    far: get_f32(o, "zfar").unwrap_or(1.0),
+                }))
+            } else {
+                None
+            };
+            match projection {
+                Some(p) => this.cameras.push(cs::Camera {
+                    name: name.clone(),
+                    node: node,
+                    projection: p,
+                }),
+                None => warn!("Camera {} has unsupported projection", cam_id),
+            }
+        }
+        let mesh_id = match get_index(raw_node, "mesh") {
+            Some(m) if m < meshes.len() => m,
+            Some(m) => return Err(Error::Format(format!("meshes[{}] is out of range", m))),
+            None => continue,
+        };
+        if meshes[mesh_id].is_none() {
+            meshes[mesh_id] = Some(try!(load_mesh(root, &buffers, mesh_id, context)));
+        }
+        for prim in meshes[mesh_id].as_ref().unwrap().iter() {
+            let material = match prim.material {
+                Some(m) if m < materials.len() => {
+                    if materials[m].is_none() {
+                        materials[m] = Some(try!(load_material(root, &buffers, m, path, context)));
+                    }
+                    materials[m].as_ref().unwrap().clone()
+                },
+                Some(m) => return Err(Error::Format(format!("materials[{}] is out of range", m))),
+                None => default_material.clone(),
+            };
+            let mut entity = cs::Entity::new(prim.mesh.clone(), node, prim.bound.clone());
+            entity.name = name.clone();
+            entity.add_fragment(material, prim.slice.clone());
+            this.entities.push(entity);
+        }
+    }
+    Ok(())
+}
+
+/// Read a .gltf or .glb file, returning the document and the binary chunk.
+pub fn read(path: &str) -> Result<(Json, Option<Vec<u8>>), Error> {
+    let data = try!(read_file(path));
+    let (text, bin) = if path.ends_with(".glb") {
+        try!(parse_glb(&data))
+    } else {
+        (data, None)
+    };
+    let s = match String::from_utf8(text) {
+        Ok(s) => s,
+        Err(_) => return Err(Error::Json("invalid UTF-8".to_string())),
+    };
+    match Json::from_str(&s) {
+        Ok(j) => Ok((j, bin)),
+        Err(e) => Err(Error::Json(format!("{:?}", e))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath::Quaternion;
+    use rustc_serialize::json::Json;
+    use mesh;
+    use super::*;
+    use super::{decompose, get_dir, load_buffers, parse_glb, read_accessor,
+                read_file, read_image, read_primitive, read_transform, Image};
+
+    fn sample(name: &str) -> String {
+        format!("{}/../../etc/samples/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn load(name: &str) -> (Json, Vec<Vec<u8>>) {
+        let path = sample(name);
+        let (root, bin) = read(&path).unwrap();
+        let buffers = load_buffers(&root, get_dir(&path), bin).unwrap();
+        (root, buffers)
+    }
+
+    fn assert_rotation(q: &Quaternion<f32>, expected: [f32; 4]) {
+        let v = [q.s, q.v.x, q.v.y, q.v.z];
+        // both signs describe the same rotation
+        let same = (0.. 4).all(|i| (v[i] - expected[i]).abs() < 1e-5);
+        let opposite = (0.. 4).all(|i| (v[i] + expected[i]).abs() < 1e-5);
+        assert!(same || opposite, "{:?} != {:?}", v, expected);
+    }
+
+    #[test]
+    fn glb_container() {
+        let data = read_file(&sample("box.glb")).unwrap();
+        let (text, bin) = parse_glb(&data).unwrap();
+        assert!(Json::from_str(&String::from_utf8(text).unwrap()).is_ok());
+        assert_eq!(bin.map(|b| b.len()), Some(916));
+    }
+
+    #[test]
+    fn glb_errors() {
+        let data = read_file(&sample("box.glb")).unwrap();
+        let mut bad = data.clone();
+        bad[0] = b'g';
+        match parse_glb(&bad) {
+            Err(Error::Container(_)) => (),
+            other => panic!("Unexpected {:?}", other.map(|_| ())),
+        }
+        let mut bad = data.clone();
+        bad[4] = 1;
+        match parse_glb(&bad) {
+            Err(Error::Unsupported(_)) => (),
+            other => panic!("Unexpected {:?}", other.map(|_| ())),
+        }
+        // the declared length is past the end
+        match parse_glb(&data[.. data.len() - 4]) {
+            Err(Error::Container(_)) => (),
+            other => panic!("Unexpected {:?}", other.map(|_| ())),
+        }
+        // the binary chunk overruns the declared length
+        let mut bad = data.clone();
+        let length = data.len() as u32 - 4;
+        bad[8] = length as u8;
+        bad[9] = (length >> 8) as u8;
+        match parse_glb(&bad) {
+            Err(Error::Container(_)) => (),
+            other => panic!("Unexpected {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn containers_match() {
+        let (root_text, buffers_text) = load("box.gltf");
+        let (root_bin, buffers_bin) = load("box.glb");
+        assert_eq!(root_text.find("meshes"), root_bin.find("meshes"));
+        assert_eq!(buffers_text.len(), 1);
+        assert_eq!(buffers_text, buffers_bin);
+    }
+
+    #[test]
+    fn accessors() {
+        let (root, buffers) = load("box.glb");
+        let acc = read_accessor(&root, &buffers, 1).unwrap();
+        assert_eq!((acc.count, acc.elem_count, acc.type_, acc.normalized), (24, 3, 'f', false));
+        assert_eq!(acc.data.len(), 24 * 12);
+        let ind = read_accessor(&root, &buffers, 0).unwrap();
+        assert_eq!((ind.count, ind.elem_count, ind.type_), (36, 1, 'H'));
+        assert_eq!(ind.data.len(), 36 * 2);
+        assert!(read_accessor(&root, &buffers, 4).is_err());
+    }
+
+    #[test]
+    fn primitive() {
+        let (root, buffers) = load("box.glb");
+        let prim = &root.find("meshes").unwrap()[0].find("primitives").unwrap()[0];
+        let data = read_primitive(&root, &buffers, "Box", prim).unwrap();
+        assert_eq!(data.num_vertices, 24);
+        for name in ["Position", "Normal", "Tex0"].iter() {
+            assert!(data.find_attribute(name).is_some(), "{} is missing", name);
+        }
+        match data.indices {
+            Some(mesh::Indices::U16(ref ind)) => {
+                assert_eq!(ind.len(), 36);
+                assert_eq!(ind[..6].to_vec(), vec![0, 1, 2, 0, 2, 3]);
+            },
+            ref other => panic!("Unexpected indices {:?}", other),
+        }
+        let bound = data.compute_bound(0, 36).unwrap();
+        assert_eq!((bound.min.x, bound.min.y, bound.min.z), (-0.5, -0.5, -0.5));
+        assert_eq!((bound.max.x, bound.max.y, bound.max.z), (0.5, 0.5, 0.5));
+        // positions and normals of the first face
+        let normals = data.read_attribute("Normal").unwrap();
+        assert_eq!(normals[0], [1.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn embedded_image() {
+        let (root, buffers) = load("box.glb");
+        match read_image(&root, &buffers, ".", 0).unwrap() {
+            Image::Memory(data) => assert_eq!(&data[..4], b"\x89PNG"),
+            Image::File(path) => panic!("Unexpected file {}", path),
+        }
+        assert!(read_image(&root, &buffers, ".", 1).is_err());
+    }
+
+    #[test]
+    fn decompose_rotation() {
+        let (root, _) = load("box.gltf");
+        let node = &root.find("nodes").unwrap()[0];
+        let t = read_transform(node);
+        assert!((t.scale - 1.0).abs() < 1e-5);
+        assert_eq!((t.disp.x, t.disp.y, t.disp.z), (0.0, 0.0, 0.0));
+        // -90 degrees around X
+        let h = (0.5f32).sqrt();
+        assert_rotation(&t.rot, [h, -h, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn decompose_scale_translation() {
+        // 180 degrees around Z, scaled by 2, moved to (1, 2, 3)
+        let m = [-2.0, 0.0, 0.0, 0.0,
+                 0.0, -2.0, 0.0, 0.0,
+                 0.0, 0.0, 2.0, 0.0,
+                 1.0, 2.0, 3.0, 1.0];
+        let t = decompose(&m);
+        assert!((t.scale - 2.0).abs() < 1e-5);
+        assert_eq!((t.disp.x, t.disp.y, t.disp.z), (1.0, 2.0, 3.0));
+        assert_rotation(&t.rot, [0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn node_trs() {
+        let node = Json::from_str(r#"{
+            "translation": [1, 2, 3],
+            "rotation": [0, 0, 0.70710677, 0.70710677],
+            "scale": [2, 2, 2]
+        }"#).unwrap();
+        let t = read_transform(&node);
+        assert_eq!(t.scale, 2.0);
+        assert_eq!((t.disp.x, t.disp.y, t.disp.z), (1.0, 2.0, 3.0));
+        let h = (0.5f32).sqrt();
+        assert_rotation(&t.rot, [h, 0.0, 0.0, h]);
+    }
+}
@@ -4,11 +4,16 @@ extern crate rustc_serialize;
 extern crate cgmath;
 extern crate gfx;
 extern crate gfx_texture;
+extern crate image;
 extern crate claymore_scene;
 
+pub mod action;
 mod aux;
 pub mod chunk;
-mod mesh;
+pub mod gltf;
+pub mod mesh;
+pub mod obj;
+pub mod process;
 mod mat;
 mod program;
 mod reflect;
@@ -29,16 +34,40 @@ pub static PREFIX_TEXTURE: &'static str = "t_";
 
 pub type TextureError = String;
 
+/// Mesh offsets within a collection file, indexed by mesh name.
+pub type MeshIndex = HashMap<String, u32>;
+
+/// How the entity bounds of a scene description are treated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BoundMode {
+    /// Use the exported bounds, computing only the missing or broken ones.
+    Trust,
+    /// Always compute the bounds from the mesh positions.
+    Compute,
+    /// Use the exported bounds, warning when they disagree with the computed.
+    Check,
+}
+
 pub struct Cache<R: gfx::Resources> {
-    meshes: HashMap<String, mesh::Success<R>>,
-    textures: HashMap<String, Result<gfx::handle::Texture<R>, TextureError>>,
+    collections: HashMap<String, MeshIndex>,
+    /// Uploaded meshes with the bounds of their fragments,
+    /// by the full path and the fragment ranges.
+    meshes: HashMap<(String, Vec<(u32, u32)>),
+                    (mesh::Success<R>, Vec<Option<cgmath::Aabb3<Scalar>>>)>,
+    actions: HashMap<String, Vec<action::Clip>>,
+    /// Descriptions of the loaded materials, by assets directory and name.
+    materials: HashMap<(String, String), reflect::Material>,
+    textures: HashMap<(String, bool), Result<gfx::handle::Texture<R>, TextureError>>,
     programs: HashMap<String, Result<gfx::handle::Program<R>, program::Error>>,
 }
 
 impl<R: gfx::Resources> Cache<R> {
     pub fn new() -> Cache<R> {
         Cache {
+            collections: HashMap::new(),
             meshes: HashMap::new(),
+            actions: HashMap::new(),
+            materials: HashMap::new(),
             textures: HashMap::new(),
             programs: HashMap::new(),
         }
@@ -53,6 +82,11 @@ pub struct Context<'a, R: 'a + gfx::Resources, F: 'a + gfx::Factory<R>> {
     pub alpha_test: Option<u8>,
     pub flip_textures: bool,
     pub forgive: bool,
+    pub bound_mode: BoundMode,
+    /// Generate missing normals and tangents of the loaded meshes.
+    pub complete_meshes: Option<process::Normals>,
+    /// Merge duplicate vertices and reorder the triangles of the loaded meshes.
+    pub optimize_meshes: bool,
 }
 
 impl<'a, R: gfx::Resources, F: gfx::Factory<R>> Context<'a, R, F> {
@@ -65,60 +99,108 @@ impl<'a, R: gfx::Resources, F: gfx::Factory<R>> Context<'a, R, F> {
             alpha_test: None,
             flip_textures: true,    // following Blender
             forgive: false,         // panic out
+            bound_mode: BoundMode::Trust,
+            complete_meshes: None,
+            optimize_meshes: false,
         }
     }
 
-    fn read_mesh_collection(&mut self, path_str: &str) -> Result<(), mesh::Error> {
-        info!("Loading mesh collection from {}", path_str);
-        let path = format!("{}/{}.k3mesh", self.prefix, path_str);
-        match File::open(path) {
-            Ok(file) => {
-                let size = file.metadata().unwrap().len() as u32;
-                let mut reader = chunk::Root::new(path_str.to_string(), file);
-                while reader.get_pos() < size {
-                    debug_assert_eq!(reader.tell(), reader.get_pos());
-                    debug!("Current position {}/{}", reader.get_pos(), size);
-                    let (name, success) = try!(mesh::load(&mut reader, self.factory));
-                    let full_name = format!("{}@{}", name, path_str);
-                    self.cache.meshes.insert(full_name, success);
-                }
-                Ok(())
-            },
-            Err(e) => Err(mesh::Error::Path(e)),
-        }
+    /// Find the offset of a mesh given as "name@container",
+    /// returning it together with the file path.
+    fn locate_mesh(&mut self, path_str: &str) -> Result<(String, u32), mesh::Error> {
+        let mut split = path_str.split('@');
+        let name = split.next().unwrap();
+        let container = match split.next() {
+            Some(c) => c,
+            None => return Err(mesh::Error::Other),
+        };
+        let path = format!("{}/{}.k3mesh", self.prefix, container);
+        let offset = {
+            let index = match self.cache.collections.entry(path.clone()) {
+                Entry::Occupied(v) => v.into_mut(),
+                Entry::Vacant(v) => v.insert(try!(index_mesh_collection(&path))),
+            };
+            match index.get(name) {
+                Some(&offset) => offset,
+                None => return Err(mesh::Error::NameNotInCollection),
+            }
+        };
+        Ok((path, offset))
     }
 
-    pub fn request_mesh(&mut self, path: &str)
+    pub fn request_mesh(&mut self, path_str: &str)
                         -> Result<mesh::Success<R>, mesh::Error> {
-        match self.cache.meshes.get(path) {
+        self.request_mesh_sliced(path_str, &[]).map(|(success, _)| success)
+    }
+
+    /// Request a mesh, given the index ranges of its fragments,
+    /// which the optimization keeps intact. Returns the bound of each range,
+    /// or of the whole mesh if there are none, with `None` for the bounds
+    /// that can not be computed. Only the first request of each mesh
+    /// with the same ranges is processed, the rest are served from the cache.
+    pub fn request_mesh_sliced(&mut self, path_str: &str, ranges: &[(u32, u32)])
+                               -> Result<(mesh::Success<R>, Vec<Option<cgmath::Aabb3<Scalar>>>),
+                                         mesh::Error> {
+        let key = (format!("{}/{}", self.prefix, path_str), ranges.to_vec());
+        match self.cache.meshes.get(&key) {
             Some(m) => return Ok(m.clone()),
             None => (),
         }
-        let mut split = path.split('@');
-        let _name = split.next().unwrap();
-        match split.next() {
-            Some(container) => {
-                try!(self.read_mesh_collection(container));
-                match self.cache.meshes.get(path) {
-                    Some(m) => Ok(m.clone()),
-                    None => Err(mesh::Error::NameNotInCollection),
-                }
-            },
-            None => Err(mesh::Error::Other),
+        let (path, offset) = try!(self.locate_mesh(path_str));
+        info!("Loading mesh {} from {}", path_str, path);
+        let mut data = try!(parse_mesh_at(&path, offset));
+        if let Some(mode) = self.complete_meshes {
+            process::complete(&mut data, mode);
+        }
+        if self.optimize_meshes {
+            let report = try!(process::optimize(&mut data, ranges));
+            info!("Optimized mesh {}: {} -> {} vertices, ACMR {:.3} -> {:.3}",
+                path_str, report.vertices_before, report.vertices_after,
+                report.acmr_before, report.acmr_after);
         }
+        let success = try!(mesh::upload(&data, self.factory));
+        let bounds = if ranges.is_empty() {
+            vec![data.compute_bound(success.1.start as u32, success.1.end as u32)]
+        } else {
+            ranges.iter().map(|&(start, end)| data.compute_bound(start, end)).collect()
+        };
+        let result = (success, bounds);
+        self.cache.meshes.insert(key, result.clone());
+        Ok(result)
     }
 
-    pub fn request_texture(&mut self, path_str: &str, srgb: bool)
+    /// Get an action given as "name@container", reading
+    /// the whole container on the first request.
+    pub fn request_action(&mut self, path_str: &str)
+                          -> Result<action::Clip, action::Error> {
+        let mut split = path_str.split('@');
+        let name = split.next().unwrap();
+        let container = match split.next() {
+            Some(c) => c,
+            None => return Err(action::Error::NameNotInCollection),
+        };
+        let path = format!("{}/{}", self.prefix, container);
+        let clips = match self.cache.actions.entry(path.clone()) {
+            Entry::Occupied(v) => v.into_mut(),
+            Entry::Vacant(v) => v.insert(try!(load_action_collection(&path))),
+        };
+        match clips.iter().find(|c| c.name == name) {
+            Some(c) => Ok(c.clone()),
+            None => Err(action::Error::NameNotInCollection),
+        }
+    }
+
+    /// Load a texture, flipping it vertically if `flip` is set.
+    /// Formats following Blender pass `flip_textures` here, while glTF
+    /// images already have their origin at the top left.
+    pub fn request_texture(&mut self, path_str: &str, srgb: bool, flip: bool)
                            -> Result<gfx::handle::Texture<R>, TextureError> {
-        match self.cache.textures.entry(path_str.to_string()) {
+        match self.cache.textures.entry((path_str.to_string(), flip)) {
             Entry::Occupied(v) => v.get().clone(),
             Entry::Vacant(v) => {
                 info!("Loading texture from {}", path_str);
                 let path = format!("{}{}", self.prefix, path_str);
-                let mut settings = gfx_texture::Settings::new();
-                settings.flip_vertical = true;
-                settings.convert_gamma = srgb;
-                settings.generate_mipmap = true;
+                let settings = make_texture_settings(srgb, flip);
                 let tex_result = gfx_texture::Texture::from_path(
                     self.factory, path, &settings);
                 let tex = match tex_result {
@@ -135,6 +217,34 @@ impl<'a, R: gfx::Resources, F: gfx::Factory<R>> Context<'a, R, F> {
         }
     }
 
+    /// Create a texture from the encoded contents of an image file,
+    /// like the images embedded into glTF buffers. The key names
+    /// the image in the cache and in the error messages.
+    pub fn request_texture_memory(&mut self, key: &str, data: &[u8], srgb: bool, flip: bool)
+                                  -> Result<gfx::handle::Texture<R>, TextureError> {
+        match self.cache.textures.entry((key.to_string(), flip)) {
+            Entry::Occupied(v) => v.get().clone(),
+            Entry::Vacant(v) => {
+                info!("Decoding texture {}", key);
+                let tex = match image::load_from_memory(data) {
+                    Ok(img) => {
+                        let settings = make_texture_settings(srgb, flip);
+                        let t = gfx_texture::Texture::from_image(
+                            self.factory, &img.to_rgba(), &settings);
+                        Ok(t.handle())
+                    },
+                    Err(e) => {
+                        if self.forgive {
+                            error!("Texture {} failed to decode: {:?}", key, e);
+                        }
+                        Err(format!("{:?}", e))
+                    },
+                };
+                v.insert(tex).clone()
+            },
+        }
+    }
+
     pub fn request_program(&mut self, name: &str)
                            -> Result<gfx::handle::Program<R>, program::Error> {
         match self.cache.programs.entry(name.to_string()) {
@@ -146,6 +256,23 @@ impl<'a, R: gfx::Resources, F: gfx::Factory<R>> Context<'a, R, F> {
             },
         }
     }
+
+    /// Request the program of a material and bind the material parameters to it,
+    /// the same way the scene does for every fragment it draws with the program.
+    pub fn bind_material(&mut self, params: &cs::param::Parameters<R>)
+                         -> Result<(gfx::handle::Program<R>, gfx::shade::ParamDictionary<R>),
+                                   BindError> {
+        let program = try!(self.request_program(&params.shader)
+                               .map_err(|e| BindError::Program(e)));
+        let dict = try!(params.bind(&program).map_err(|e| BindError::Param(e)));
+        Ok((program, dict))
+    }
+}
+
+#[derive(Debug)]
+pub enum BindError {
+    Program(program::Error),
+    Param(cs::param::Error),
 }
 
 #[derive(Debug)]
@@ -154,6 +281,9 @@ pub enum SceneError {
     Read(io::Error),
     Decode(json::DecoderError),
     Parse(scene::Error),
+    Create(io::Error),
+    Write(io::Error),
+    Encode(json::EncoderError),
 }
 
 impl<'a, R: gfx::Resources, F: gfx::Factory<R>> Context<'a, R, F> {
@@ -170,7 +300,7 @@ impl<'a, R: gfx::Resources, F: gfx::Factory<R>> Context<'a, R, F> {
                 let mut s = String::new();
                 match file.read_to_string(&mut s) {
                     Ok(_) => match json::decode(&s) {
-                        Ok(raw) => match scene::load_into(scene, global_parent, raw, self) {
+                        Ok(raw) => match scene::load_into(scene, global_parent, raw, path_str, self) {
                             Ok(s) => Ok(s),
                             Err(e) => Err(SceneError::Parse(e)),
                         },
@@ -183,16 +313,84 @@ impl<'a, R: gfx::Resources, F: gfx::Factory<R>> Context<'a, R, F> {
         }
     }
 
+    /// Load a scene, adding a default camera if it has none.
     pub fn load_scene(&mut self, path_str: &str)
                       -> Result<cs::Scene<R, Scalar>, SceneError>
     {
         let mut scene = cs::Scene::new();
         match self.load_scene_into(&mut scene, cs::space::Parent::None, path_str) {
-            Ok(()) => Ok(scene),
+            Ok(()) => {
+                if scene.cameras.is_empty() {
+                    info!("Scene {} has no cameras, adding the default", path_str);
+                    scene.add_default_camera();
+                }
+                Ok(scene)
+            },
             Err(e) => Err(e),
         }
     }
 
+    /// Save the scene, so that `load_scene` can read it back.
+    /// Entities refer to the assets they were loaded from.
+    pub fn save_scene(&self, scene: &cs::Scene<R, Scalar>, path_str: &str)
+                      -> Result<(), SceneError>
+    {
+        use std::io::Write;
+        info!("Saving scene to {}", path_str);
+        let raw = try!(scene::save(scene, &self.cache).map_err(|e| SceneError::Parse(e)));
+        let text = try!(json::encode(&raw).map_err(|e| SceneError::Encode(e)));
+        let path = format!("{}/{}.json", self.base_path, path_str);
+        match File::create(&path) {
+            Ok(mut file) => file.write_all(text.as_bytes())
+                                .map_err(|e| SceneError::Write(e)),
+            Err(e) => Err(SceneError::Create(e)),
+        }
+    }
+
+    /// Load a glTF 2.0 document (.gltf or .glb) into the scene.
+    /// The path is relative to `base_path` and includes the extension.
+    pub fn load_gltf_into(&mut self, scene: &mut cs::Scene<R, Scalar>,
+                          global_parent: cs::Parent<Scalar>,
+                          path_str: &str) -> Result<(), gltf::Error>
+    {
+        info!("Loading glTF from {}", path_str);
+        let path = format!("{}/{}", self.base_path, path_str);
+        let (root, bin) = try!(gltf::read(&path));
+        // resources are referenced by full paths
+        let prefix = ::std::mem::replace(&mut self.prefix, String::new());
+        let result = gltf::load_into(scene, global_parent, &root, bin, &path, self);
+        self.prefix = prefix;
+        result
+    }
+
+    pub fn load_gltf(&mut self, path_str: &str)
+                     -> Result<cs::Scene<R, Scalar>, gltf::Error>
+    {
+        let mut scene = cs::Scene::new();
+        try!(self.load_gltf_into(&mut scene, cs::space::Parent::None, path_str));
+        Ok(scene)
+    }
+
+    /// Load a Wavefront OBJ file with its materials as an entity
+    /// under a new node. The path is relative to `base_path`.
+    pub fn load_obj_into(&mut self, scene: &mut cs::Scene<R, Scalar>,
+                         global_parent: cs::Parent<Scalar>, path_str: &str)
+                         -> Result<cs::NodeId<Scalar>, obj::Error>
+    {
+        info!("Loading OBJ from {}", path_str);
+        let path = format!("{}/{}", self.base_path, path_str);
+        let nid = scene.world.add_node(
+            path_str.to_string(),
+            global_parent,
+            cgmath::Transform::identity()
+        );
+        // resources are referenced by full paths
+        let prefix = ::std::mem::replace(&mut self.prefix, String::new());
+        let result = obj::load_into(scene, nid, &path, self);
+        self.prefix = prefix;
+        result.map(|_| nid)
+    }
+
     pub fn extend_scene(&mut self, scene: &mut cs::Scene<R, Scalar>, path_str: &str)
                         -> Result<cs::NodeId<Scalar>, SceneError>
     {
@@ -206,15 +404,115 @@ impl<'a, R: gfx::Resources, F: gfx::Factory<R>> Context<'a, R, F> {
     }
 }
 
+fn make_texture_settings(srgb: bool, flip: bool) -> gfx_texture::Settings {
+    let mut settings = gfx_texture::Settings::new();
+    settings.flip_vertical = flip;
+    settings.convert_gamma = srgb;
+    settings.generate_mipmap = true;
+    settings
+}
+
+/// Scan a mesh collection, recording where each mesh starts.
+pub fn index_mesh_collection(path: &str) -> Result<MeshIndex, mesh::Error> {
+    info!("Indexing mesh collection {}", path);
+    match File::open(path) {
+        Ok(file) => {
+            let size = match file.metadata() {
+                Ok(m) => m.len() as u32,
+                Err(e) => return Err(mesh::Error::Path(e)),
+            };
+            let mut reader = chunk::Root::new(path.to_string(), io::BufReader::new(file));
+            let mut index = HashMap::new();
+            while reader.get_pos() < size {
+                let offset = reader.get_pos();
+                let name = try!(mesh::skip(&mut reader));
+                debug!("\tfound {} at {}", name, offset);
+                index.insert(name, offset);
+            }
+            Ok(index)
+        },
+        Err(e) => Err(mesh::Error::Path(e)),
+    }
+}
+
+/// Parse a single mesh of a collection, starting at the given offset.
+fn parse_mesh_at(path: &str, offset: u32) -> Result<mesh::MeshData, mesh::Error> {
+    match File::open(path) {
+        Ok(file) => {
+            let mut reader = chunk::Root::new(path.to_string(), io::BufReader::new(file));
+            try!(reader.seek(offset));
+            mesh::parse(&mut reader)
+        },
+        Err(e) => Err(mesh::Error::Path(e)),
+    }
+}
+
 pub fn load_mesh<'a, R: gfx::Resources, F: gfx::Factory<R>>(path_str: &str, factory: &mut F)
                  -> Result<(String, mesh::Success<R>), mesh::Error> {
     info!("Loading mesh from {}", path_str);
     let path = format!("{}.k3mesh", path_str);
     match File::open(&path) {
         Ok(file) => {
-            let mut reader = chunk::Root::new(path, file);
+            let mut reader = chunk::Root::new(path, io::BufReader::new(file));
             mesh::load(&mut reader, factory)
         },
         Err(e) => Err(mesh::Error::Path(e)),
     }
 }
+
+/// Parse all the meshes of a collection, without uploading them to GPU.
+pub fn load_mesh_collection_data(path_str: &str)
+                                 -> Result<Vec<mesh::MeshData>, mesh::Error> {
+    info!("Loading mesh data from {}", path_str);
+    let path = format!("{}.k3mesh", path_str);
+    match File::open(&path) {
+        Ok(file) => {
+            let size = match file.metadata() {
+                Ok(m) => m.len() as u32,
+                Err(e) => return Err(mesh::Error::Path(e)),
+            };
+            let mut reader = chunk::Root::new(path, io::BufReader::new(file));
+            let mut meshes = Vec::new();
+            while reader.get_pos() < size {
+                meshes.push(try!(mesh::parse(&mut reader)));
+            }
+            Ok(meshes)
+        },
+        Err(e) => Err(mesh::Error::Path(e)),
+    }
+}
+
+/// Parse all the actions of a collection.
+pub fn load_action_collection(path_str: &str)
+                              -> Result<Vec<action::Clip>, action::Error> {
+    info!("Loading actions from {}", path_str);
+    let path = format!("{}.k3act", path_str);
+    match File::open(&path) {
+        Ok(file) => {
+            let size = match file.metadata() {
+                Ok(m) => m.len() as u32,
+                Err(e) => return Err(action::Error::Path(e)),
+            };
+            let mut reader = chunk::Root::new(path, io::BufReader::new(file));
+            action::parse_collection(&mut reader, size)
+        },
+        Err(e) => Err(action::Error::Path(e)),
+    }
+}
+
+pub fn save_mesh_collection(path_str: &str, meshes: &[mesh::MeshData])
+                            -> Result<(), mesh::Error> {
+    use std::io::Write;
+    info!("Saving mesh collection to {}", path_str);
+    let path = format!("{}.k3mesh", path_str);
+    match File::create(&path) {
+        Ok(file) => {
+            let mut writer = chunk::Writer::new(io::BufWriter::new(file));
+            for m in meshes.iter() {
+                try!(mesh::save(&mut writer, m));
+            }
+            writer.into_inner().flush().map_err(|e| mesh::Error::Write(e))
+        },
+        Err(e) => Err(mesh::Error::Path(e)),
+    }
+}
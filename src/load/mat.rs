@@ -1,5 +1,7 @@
 use gfx;
+use gfx::shade::UniformValue;
 use claymore_scene::{Material, Transparency};
+use claymore_scene::param::{Parameters, TextureParam};
 use super::reflect;
 
 #[derive(Debug)]
@@ -11,8 +13,98 @@ pub enum Error {
     SamplerWrap(i8),
 }
 
+/// Load the image of a texture slot and create its sampler.
+/// Returns `None` if the image failed to load and the context forgives.
+fn load_texture<R: gfx::Resources, F: gfx::Factory<R>>(rt: &reflect::Texture,
+                context: &mut super::Context<R, F>)
+                -> Result<Option<TextureParam<R>>, Error> {
+    let space = match rt.image.space.as_ref() {
+        "Linear" => false,
+        "sRGB" => true,
+        other => {
+            warn!("Unknown color space: {}", other);
+            false
+        }
+    };
+    if rt.image.mapping != "UV" {
+        warn!("Texture {} has unsupported mapping {}, using UV",
+            rt.name, rt.image.mapping);
+    }
+    let flip = context.flip_textures;
+    match context.request_texture(&rt.image.path, space, flip) {
+        Ok(t) => {
+            fn unwrap(mode: i8) -> Result<gfx::tex::WrapMode, Error> {
+                match mode {
+                    -1 => Ok(gfx::tex::WrapMode::Mirror),
+                    0 => Ok(gfx::tex::WrapMode::Clamp),
+                    1 => Ok(gfx::tex::WrapMode::Tile),
+                    _ => Err(Error::SamplerWrap(mode)),
+                }
+            }
+            let (wx, wy, wz) = (
+                try!(unwrap(rt.wrap.0)),
+                try!(unwrap(rt.wrap.1)),
+                try!(unwrap(rt.wrap.2)),
+            );
+            let filter = match rt.filter {
+                1 => gfx::tex::FilterMethod::Scale,
+                2 => gfx::tex::FilterMethod::Bilinear,
+                3 => gfx::tex::FilterMethod::Trilinear,
+                other => return Err(Error::SamplerFilter(rt.name.clone(), other)),
+            };
+            let mut sinfo = gfx::tex::SamplerInfo::new(filter, wx);
+            sinfo.wrap_mode.1 = wy;
+            sinfo.wrap_mode.2 = wz;
+            let sampler = context.factory.create_sampler(sinfo);
+            Ok(Some((t, Some(sampler))))
+        },
+        Err(_) if context.forgive => Ok(None), //already errored in request_texture()
+        Err(e) => Err(Error::Texture(rt.image.path.clone(), e)),
+    }
+}
+
+/// Name of the shader parameter of a texture slot.
+fn texture_name(rt: &reflect::Texture) -> String {
+    format!("{}{}", super::PREFIX_TEXTURE, rt.name)
+}
+
+/// Uniforms of the UV transform of a texture slot.
+fn texture_uniforms(rt: &reflect::Texture) -> Vec<(String, UniformValue)> {
+    let (ox, oy, oz) = rt.offset;
+    let (sx, sy, sz) = rt.scale;
+    vec![
+        (format!("{}{}Offset", super::PREFIX_UNIFORM, rt.name),
+            UniformValue::F32Vector3([ox, oy, oz])),
+        (format!("{}{}Scale", super::PREFIX_UNIFORM, rt.name),
+            UniformValue::F32Vector3([sx, sy, sz])),
+    ]
+}
+
+/// Uniforms of the material data entries.
+fn data_uniforms(mat: &reflect::Material) -> Vec<(String, UniformValue)> {
+    mat.data.iter().filter_map(|(name, &(_, ref vec))| {
+        let value = match vec.len() {
+            1 => UniformValue::F32(vec[0]),
+            2 => UniformValue::F32Vector2([vec[0], vec[1]]),
+            3 => UniformValue::F32Vector3([vec[0], vec[1], vec[2]]),
+            4 => UniformValue::F32Vector4([vec[0], vec[1], vec[2], vec[3]]),
+            n => {
+                warn!("Skipping material data {} with {} values", name, n);
+                return None
+            },
+        };
+        Some((format!("{}{}", super::PREFIX_UNIFORM, name), value))
+    }).collect()
+}
+
+/// Load a material, returning the fixed pipeline `Material` together
+/// with the generic parameters. The first texture slot is also used
+/// as the diffuse texture of the `Material`, and the "DiffuseColor"
+/// data entry as its color. The parameters get the program
+/// of the shader if it loads, so that the scene draws with it.
 pub fn load<R: gfx::Resources, F: gfx::Factory<R>>(mat: &reflect::Material,
-            context: &mut super::Context<R, F>) -> Result<Material<R>, Error> {
+            context: &mut super::Context<R, F>)
+            -> Result<(Material<R>, Parameters<R>), Error> {
     let mut out = Material {
         color: [1.0, 1.0, 1.0, 1.0],
         texture: None,
@@ -22,48 +114,108 @@ pub fn load<R: gfx::Resources, F: gfx::Factory<R>>(mat: &reflect::Material,
             (false, _)      => Transparency::Opaque,
         },
     };
-    if let Some(ref rt) = mat.textures.first() {
-        let space = match rt.image.space.as_ref() {
-            "Linear" => false,
-            "sRGB" => true,
-            other => {
-                warn!("Unknown color space: {}", other);
-                false
-            }
+    let mut params = Parameters::new(mat.shader.clone());
+    params.program = match context.request_program(&mat.shader) {
+        Ok(p) => Some(p),
+        Err(e) => {
+            info!("Material {} is left to the pipeline, no program: {:?}", mat.name, e);
+            None
+        },
+    };
+    for (i, rt) in mat.textures.iter().enumerate() {
+        let tex = match try!(load_texture(rt, context)) {
+            Some(t) => t,
+            None => continue,
         };
-        match context.request_texture(&rt.image.path, space) {
-            Ok(t) => {
-                fn unwrap(mode: i8) -> Result<gfx::tex::WrapMode, Error> {
-                    match mode {
-                        -1 => Ok(gfx::tex::WrapMode::Mirror),
-                        0 => Ok(gfx::tex::WrapMode::Clamp),
-                        1 => Ok(gfx::tex::WrapMode::Tile),
-                        _ => Err(Error::SamplerWrap(mode)),
-                    }
-                }
-                let (wx, wy, wz) = (
-                    try!(unwrap(rt.wrap.0)),
-                    try!(unwrap(rt.wrap.1)),
-                    try!(unwrap(rt.wrap.2)),
-                );
-                let filter = match rt.filter {
-                    1 => gfx::tex::FilterMethod::Scale,
-                    2 => gfx::tex::FilterMethod::Bilinear,
-                    3 => gfx::tex::FilterMethod::Trilinear,
-                    other => return Err(Error::SamplerFilter(rt.name.clone(), other)),
-                };
-                let mut sinfo = gfx::tex::SamplerInfo::new(filter, wx);
-                sinfo.wrap_mode.1 = wy;
-                sinfo.wrap_mode.2 = wz;
-                let sampler = context.factory.create_sampler(sinfo);
-                out.texture = Some((t, Some(sampler)));
-            },
-            Err(_) if context.forgive => (), //already errored in request_texture()
-            Err(e) => return Err(Error::Texture(rt.image.path.clone(), e)),
+        if i == 0 {
+            out.texture = Some(tex.clone());
         }
-    };
-    if let Some(&(_, ref vec)) = mat.data.get("DiffuseColor") {
-        out.color = [vec[0], vec[1], vec[2], 1.0];
+        params.textures.insert(texture_name(rt), tex);
+        params.uniforms.extend(texture_uniforms(rt).into_iter());
+    }
+    params.uniforms.extend(data_uniforms(mat).into_iter());
+    match mat.data.get("DiffuseColor") {
+        Some(&(_, ref vec)) if vec.len() >= 3 =>
+            out.color = [vec[0], vec[1], vec[2], 1.0],
+        _ => (),
+    }
+    Ok((out, params))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use gfx;
+    use gfx::device::dummy::DummyResources;
+    use gfx::shade::{BaseType, ContainerType, IsArray, IsMultiSample, IsRect,
+                     IsShadow, ProgramInfo, SamplerType, SamplerVar, UniformVar};
+    use claymore_scene::param::Parameters;
+    use super::{data_uniforms, texture_name, texture_uniforms};
+    use super::super::reflect;
+
+    fn uniform(name: &str, location: usize, container: ContainerType) -> UniformVar {
+        UniformVar {
+            name: name.to_string(),
+            location: location as gfx::shade::Location,
+            count: 1,
+            base_type: BaseType::F32,
+            container: container,
+        }
+    }
+
+    #[test]
+    fn names() {
+        let mut data = HashMap::new();
+        data.insert("DiffuseColor".to_string(), ("color".to_string(), vec![1.0, 0.5, 0.0]));
+        data.insert("Shininess".to_string(), ("scalar".to_string(), vec![20.0]));
+        let mat = reflect::Material {
+            name: "Stone".to_string(),
+            shader: "stone".to_string(),
+            transparent: false,
+            data: data,
+            textures: vec![reflect::Texture {
+                name: "Diffuse".to_string(),
+                image: reflect::Image {
+                    path: "stone.png".to_string(),
+                    space: "sRGB".to_string(),
+                    mapping: "UV".to_string(),
+                },
+                filter: 2,
+                wrap: (1, 1, 1),
+                offset: (0.0, 0.0, 0.0),
+                scale: (2.0, 2.0, 1.0),
+            }],
+        };
+        let info = ProgramInfo {
+            attributes: Vec::new(),
+            uniforms: vec![
+                uniform("u_DiffuseColor", 0, ContainerType::Vector(3)),
+                uniform("u_Shininess", 1, ContainerType::Single),
+                uniform("u_DiffuseOffset", 2, ContainerType::Vector(3)),
+                uniform("u_DiffuseScale", 3, ContainerType::Vector(3)),
+                uniform("u_Transform", 4, ContainerType::Matrix(
+                    gfx::shade::MatrixFormat::ColumnMajor, 4, 4)),
+            ],
+            blocks: Vec::new(),
+            textures: vec![SamplerVar {
+                name: "t_Diffuse".to_string(),
+                location: 0,
+                base_type: BaseType::F32,
+                sampler_type: SamplerType::Sampler2D(IsArray::NoArray,
+                    IsShadow::NoShadow, IsMultiSample::NoMultiSample, IsRect::NoRect),
+            }],
+        };
+        let mut params: Parameters<DummyResources> = Parameters::new(mat.shader.clone());
+        params.uniforms.extend(texture_uniforms(&mat.textures[0]).into_iter());
+        params.uniforms.extend(data_uniforms(&mat).into_iter());
+        // every uniform of the material is declared by the program
+        for name in params.uniforms.keys() {
+            assert!(info.uniforms.iter().any(|var| &var.name == name), "{}", name);
+        }
+        assert_eq!(texture_name(&mat.textures[0]), info.textures[0].name);
+        // and binds to it, leaving the transform to the scene
+        let dict = params.bind_info(&info).unwrap();
+        let bound: Vec<_> = dict.uniforms.iter().map(|c| &c.name[..]).collect();
+        assert_eq!(bound, ["u_DiffuseColor", "u_Shininess", "u_DiffuseOffset", "u_DiffuseScale"]);
     }
-    Ok(out)
 }
@@ -1,14 +1,270 @@
 use std::io;
+use cgmath;
 use gfx;
-use ::aux::ReadExt;
+use ::aux::decode_u32;
+use ::chunk;
 use ::chunk::Root;
 
 pub type Success<R> = (gfx::Mesh<R>, gfx::Slice<R>);
 
+/// Vertex attribute, as described in a `buffer` chunk.
+#[derive(Clone, Debug)]
+pub struct Attribute {
+    /// Name without the `PREFIX_ATTRIB`.
+    pub name: String,
+    pub count: u8,
+    /// Type character in Python packing notation.
+    pub type_: char,
+    pub flags: u8,
+}
+
+/// Attribute flag: integer values are normalized.
+pub static FLAG_NORMALIZED: u8 = 1;
+/// Attribute flag: values are interpolated across the primitive.
+pub static FLAG_INTERPOLATE: u8 = 2;
+/// Attribute flag: a single 'l' or 'L' element holds
+/// four 10:10:10:2 packed components.
+pub static FLAG_PACKED: u8 = 4;
+/// Attribute flag: values advance per instance instead of per vertex.
+/// Only allowed in `instance` chunks, which carry their own element count.
+pub static FLAG_INSTANCED: u8 = 8;
+
+/// Interleaved vertex buffer.
+#[derive(Clone, Debug)]
+pub struct Buffer {
+    pub stride: u8,
+    pub attributes: Vec<Attribute>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Indices {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+/// CPU-side contents of a single `mesh` chunk.
+#[derive(Clone, Debug)]
+pub struct MeshData {
+    pub name: String,
+    pub num_vertices: u32,
+    pub topology: gfx::PrimitiveType,
+    pub buffers: Vec<Buffer>,
+    /// Buffers of per-instance attributes, not touched by the processing passes.
+    pub instances: Vec<Buffer>,
+    pub indices: Option<Indices>,
+}
+
+/// Convert IEEE 754 half-precision bits into a float.
+fn decode_half(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 {-1.0} else {1.0};
+    let exponent = ((bits >> 10) & 0x1F) as i32;
+    let mantissa = (bits & 0x3FF) as f32;
+    sign * match exponent {
+        0 => mantissa * (2.0f32).powi(-24),
+        0x1F if mantissa == 0.0 => ::std::f32::INFINITY,
+        0x1F => ::std::f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * (2.0f32).powi(exponent - 15),
+    }
+}
+
+/// Split 10:10:10:2 packed components, normalizing them.
+fn unpack_10_10_10_2(value: u32, signed: bool) -> [f32; 4] {
+    let mut out = [0.0; 4];
+    for i in 0.. 4 {
+        let bits = if i < 3 {10} else {2};
+        let raw = (value >> (i * 10)) & ((1 << bits) - 1);
+        out[i] = if signed {
+            // sign-extend and map into [-1, 1]
+            let shift = 32 - bits;
+            let v = ((raw << shift) as i32 >> shift) as f32;
+            (v / ((1 << (bits - 1)) - 1) as f32).max(-1.0)
+        } else {
+            raw as f32 / ((1 << bits) - 1) as f32
+        };
+    }
+    out
+}
+
+/// Decode a single element of the given type into a float,
+/// mapping normalized integers into [0, 1] or [-1, 1] ranges.
+fn decode_element(type_: char, normalized: bool, b: &[u8]) -> Option<f32> {
+    use std::mem::transmute;
+    let u16_ = || (b[0] as u16) | ((b[1] as u16) << 8);
+    Some(match (type_, normalized) {
+        ('b', false) => b[0] as i8 as f32,
+        ('b', true) => (b[0] as i8 as f32 / 127.0).max(-1.0),
+        ('B', false) => b[0] as f32,
+        ('B', true) => b[0] as f32 / 255.0,
+        ('h', false) => u16_() as i16 as f32,
+        ('h', true) => (u16_() as i16 as f32 / 32767.0).max(-1.0),
+        ('H', false) => u16_() as f32,
+        ('H', true) => u16_() as f32 / 65535.0,
+        ('l', false) => decode_u32(b) as i32 as f32,
+        ('l', true) => (decode_u32(b) as i32 as f64 / 2147483647.0).max(-1.0) as f32,
+        ('L', false) => decode_u32(b) as f32,
+        ('L', true) => (decode_u32(b) as f64 / 4294967295.0) as f32,
+        ('e', false) => decode_half(u16_()),
+        ('f', false) => unsafe { transmute::<u32, f32>(decode_u32(b)) },
+        ('d', false) => {
+            let low = decode_u32(&b[..4]) as u64;
+            let high = decode_u32(&b[4..]) as u64;
+            unsafe { transmute::<u64, f64>(low | (high << 32)) as f32 }
+        },
+        _ => return None,
+    })
+}
+
+/// Re-encode packed 10:10:10:2 attributes of a buffer
+/// as four normalized bytes, which is what the GPU can consume.
+fn unpack_buffer(buf: &Buffer) -> Vec<u8> {
+    let mut data = buf.data.clone();
+    let stride = buf.stride as usize;
+    let mut offset = 0;
+    for at in buf.attributes.iter() {
+        let size = match get_element_type(at) {
+            Ok((count, el_type)) => count as usize * el_type.get_size() as usize,
+            Err(_) => break,
+        };
+        if at.flags & FLAG_PACKED != 0 {
+            let signed = at.type_ == 'l';
+            for vertex in data.chunks_mut(stride) {
+                let value = decode_u32(&vertex[offset.. offset + 4]);
+                let unpacked = unpack_10_10_10_2(value, signed);
+                for (b, v) in vertex[offset.. offset + 4].iter_mut().zip(unpacked.iter()) {
+                    *b = if signed {
+                        (v * 127.0).round() as i8 as u8
+                    } else {
+                        (v * 255.0).round() as u8
+                    };
+                }
+            }
+        }
+        offset += size;
+    }
+    data
+}
+
+impl Buffer {
+    /// Check if the attributes advance per instance. Buffers never mix
+    /// the per-vertex and per-instance attributes.
+    pub fn is_instanced(&self) -> bool {
+        self.attributes.iter().any(|at| at.flags & FLAG_INSTANCED != 0)
+    }
+}
+
+impl MeshData {
+    /// Find an attribute by name, returning the buffer,
+    /// the attribute, and its byte offset within a vertex.
+    pub fn find_attribute(&self, name: &str) -> Option<(&Buffer, &Attribute, usize)> {
+        for buf in self.buffers.iter() {
+            let mut offset = 0;
+            for at in buf.attributes.iter() {
+                if at.name == name {
+                    return Some((buf, at, offset))
+                }
+                offset += match get_element_type(at) {
+                    Ok((count, el_type)) => count as usize * el_type.get_size() as usize,
+                    Err(_) => break,
+                };
+            }
+        }
+        None
+    }
+
+    /// Decode the named attribute of every vertex into floats,
+    /// filling the missing components with zeros.
+    pub fn read_attribute(&self, name: &str) -> Option<Vec<[f32; 4]>> {
+        let (buf, at, offset) = match self.find_attribute(name) {
+            Some(found) => found,
+            None => return None,
+        };
+        let (count, size) = match get_element_type(at) {
+            Ok((count, el_type)) => (count as usize, el_type.get_size() as usize),
+            Err(_) => return None,
+        };
+        let normalized = at.flags & FLAG_NORMALIZED != 0;
+        let packed = at.flags & FLAG_PACKED != 0;
+        let count = ::std::cmp::min(count, 4);
+        let stride = buf.stride as usize;
+        if offset + count * size > stride ||
+                buf.data.len() != stride * self.num_vertices as usize {
+            return None
+        }
+        if packed {
+            let signed = at.type_ == 'l';
+            return Some(buf.data.chunks(stride).map(|vertex|
+                unpack_10_10_10_2(decode_u32(&vertex[offset..]), signed)
+            ).collect())
+        }
+        let mut result = Vec::with_capacity(self.num_vertices as usize);
+        for vertex in buf.data.chunks(stride) {
+            let mut value = [0.0; 4];
+            for i in 0.. count {
+                let start = offset + i * size;
+                value[i] = match decode_element(at.type_, normalized,
+                                                &vertex[start.. start + size]) {
+                    Some(v) => v,
+                    None => return None,
+                };
+            }
+            result.push(value);
+        }
+        Some(result)
+    }
+
+    /// Get the list of vertex indices, generating a trivial one
+    /// for non-indexed meshes.
+    pub fn get_index_list(&self) -> Vec<u32> {
+        match self.indices {
+            Some(Indices::U8(ref ind)) => ind.iter().map(|&i| i as u32).collect(),
+            Some(Indices::U16(ref ind)) => ind.iter().map(|&i| i as u32).collect(),
+            Some(Indices::U32(ref ind)) => ind.clone(),
+            None => (0.. self.num_vertices).collect(),
+        }
+    }
+
+    /// Make sure all the indices refer to existing vertices,
+    /// so that the processing passes can index the buffers safely.
+    pub fn check_indices(&self) -> Result<(), Error> {
+        match self.get_index_list().into_iter().find(|&i| i >= self.num_vertices) {
+            Some(i) => Err(Error::IndexRange(i, self.num_vertices)),
+            None => Ok(()),
+        }
+    }
+
+    /// Compute the bounding box of the positions referenced by a range
+    /// of the index list, or of the vertices for a mesh without indices.
+    pub fn compute_bound(&self, start: u32, end: u32) -> Option<cgmath::Aabb3<f32>> {
+        use cgmath::{Aabb, Point3};
+        let positions = match self.read_attribute("Position") {
+            Some(p) => p,
+            None => return None,
+        };
+        let indices = self.get_index_list();
+        if start >= end || end as usize > indices.len() {
+            return None
+        }
+        let mut bound: Option<cgmath::Aabb3<f32>> = None;
+        for &i in indices[start as usize .. end as usize].iter() {
+            let p = match positions.get(i as usize) {
+                Some(v) => Point3::new(v[0], v[1], v[2]),
+                None => return None,
+            };
+            bound = Some(match bound {
+                Some(b) => b.grow(&p),
+                None => cgmath::Aabb3::new(p, p),
+            });
+        }
+        bound
+    }
+}
+
 /// Parse type character to gfx attribute type
 /// using Python packing notation:
 /// https://docs.python.org/2/library/struct.html#format-characters
-fn parse_type(type_: char, normalized: u8) -> Result<gfx::attrib::Type, ()> {
+pub fn parse_type(type_: char, normalized: u8) -> Result<gfx::attrib::Type, ()> {
     use gfx::attrib::Type::*;
     use gfx::attrib::IntSubType::*;
     use gfx::attrib::IntSize::*;
@@ -28,15 +284,64 @@ fn parse_type(type_: char, normalized: u8) -> Result<gfx::attrib::Type, ()> {
         ('L', 0) => Int(Raw, U32, Unsigned),
         ('l', 1) => Int(Normalized, U32, Signed),
         ('L', 1) => Int(Normalized, U32, Unsigned),
+        ('e', 0) => Float(Default, F16),
         ('f', 0) => Float(Default, F32),
         ('d', 0) => Float(Precision, F64),
         _ => return Err(()),
     })
 }
 
+/// Get the element count and the GPU-side type of an attribute.
+/// Packed attributes are exposed as four normalized bytes.
+pub fn get_element_type(at: &Attribute) -> Result<(u8, gfx::attrib::Type), Error> {
+    use gfx::attrib::Type::Int;
+    use gfx::attrib::IntSubType::Normalized;
+    use gfx::attrib::IntSize::U8;
+    use gfx::attrib::SignFlag::{Signed, Unsigned};
+    if at.flags & FLAG_PACKED != 0 {
+        match (at.type_, at.count) {
+            ('l', 1) => Ok((4, Int(Normalized, U8, Signed))),
+            ('L', 1) => Ok((4, Int(Normalized, U8, Unsigned))),
+            _ => Err(Error::AttribType(at.type_, at.flags)),
+        }
+    } else {
+        match parse_type(at.type_, at.flags & FLAG_NORMALIZED) {
+            Ok(t) => Ok((at.count, t)),
+            Err(_) => Err(Error::AttribType(at.type_, at.flags)),
+        }
+    }
+}
+
+pub fn parse_topology(code: &str) -> Option<gfx::PrimitiveType> {
+    use gfx::PrimitiveType;
+    Some(match code {
+        "1" => PrimitiveType::Point,
+        "2" => PrimitiveType::Line,
+        "2s"=> PrimitiveType::LineStrip,
+        "3" => PrimitiveType::TriangleList,
+        "3s"=> PrimitiveType::TriangleStrip,
+        "3f"=> PrimitiveType::TriangleFan,
+        _ => return None,
+    })
+}
+
+fn get_topology_code(prim: gfx::PrimitiveType) -> &'static str {
+    use gfx::PrimitiveType;
+    match prim {
+        PrimitiveType::Point => "1",
+        PrimitiveType::Line => "2",
+        PrimitiveType::LineStrip => "2s",
+        PrimitiveType::TriangleList => "3",
+        PrimitiveType::TriangleStrip => "3s",
+        PrimitiveType::TriangleFan => "3f",
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
     Path(io::Error),
+    Read(chunk::Error),
+    Write(io::Error),
     NameNotInCollection,
     Chunk(String),
     Signature(String),
@@ -44,103 +349,521 @@ pub enum Error {
     DoubleIndex,
     AttribType(char, u8),
     IndexType(char),
-    Stride(u8),
+    /// Attribute with a given name is marked as instanced in a vertex
+    /// buffer, or is not marked in an instance buffer.
+    Instanced(String),
+    /// Index value is not below the vertex count.
+    IndexRange(u32, u32),
+    /// Declared stride doesn't match the total size of attributes.
+    Stride(u8, u32),
+    Format(String),
+    Size(u32, u32),
     Other,
 }
 
-pub fn load<I: io::Read, R: gfx::Resources, F: gfx::Factory<R>>(
-            reader: &mut Root<I>, factory: &mut F)
-            -> Result<(String, Success<R>), Error> {
-    use gfx::PrimitiveType;
-    let mut cmesh = reader.enter();
+impl From<chunk::Error> for Error {
+    fn from(e: chunk::Error) -> Error {
+        Error::Read(e)
+    }
+}
+
+/// Compute the byte size of `count` elements of `size` bytes each,
+/// guarding against overflow on corrupted input.
+fn get_data_size(count: u32, size: u32) -> Result<u32, Error> {
+    count.checked_mul(size).ok_or(Error::Size(count, size))
+}
+
+/// Read the name of the next mesh, skipping over its contents.
+pub fn skip<I: io::Read>(reader: &mut Root<I>) -> Result<String, Error> {
+    let mut cmesh = try!(reader.enter());
     if cmesh.get_name() != "mesh"    {
         return Err(Error::Signature(cmesh.get_name().to_string()))
     }
-    let mesh_name = cmesh.read_str().to_string();
-    let n_vert = cmesh.read_u32();
-    info!("\tname: {}, vertices: {}", mesh_name, n_vert);
-    let mut slice = gfx::Slice {
-        start: 0,
-        end: n_vert,
-        prim_type: match cmesh.read_str() {
-            "1" => PrimitiveType::Point,
-            "2" => PrimitiveType::Line,
-            "2s"=> PrimitiveType::LineStrip,
-            "3" => PrimitiveType::TriangleList,
-            "3s"=> PrimitiveType::TriangleStrip,
-            "3f"=> PrimitiveType::TriangleFan,
-            top => return Err(Error::Topology(top.to_string())),
+    let mesh_name = try!(cmesh.read_str()).to_string();
+    try!(cmesh.ignore());
+    Ok(mesh_name)
+}
+
+/// Read the contents of a `buffer` chunk, holding `n_vert` vertices.
+pub fn read_buffer<I: io::Read>(cbuf: &mut Root<I>, n_vert: u32)
+                   -> Result<Buffer, Error> {
+    read_buffer_data(cbuf, n_vert, false)
+}
+
+/// Read the contents of an `instance` chunk, which is a buffer
+/// prefixed by the number of instances.
+pub fn read_instances<I: io::Read>(cbuf: &mut Root<I>) -> Result<Buffer, Error> {
+    let n_inst = try!(cbuf.read_u32());
+    debug!("	Instances: {}", n_inst);
+    read_buffer_data(cbuf, n_inst, true)
+}
+
+fn read_buffer_data<I: io::Read>(cbuf: &mut Root<I>, count: u32, instanced: bool)
+                    -> Result<Buffer, Error> {
+    let stride = try!(cbuf.read_u8());
+    let format_str = try!(cbuf.read_str()).to_string();
+    debug!("\tBuffer stride: {}, format: {}", stride, format_str);
+    if format_str.len() % 2 != 0 {
+        return Err(Error::Format(format_str))
+    }
+    let size = try!(get_data_size(count, stride as u32));
+    let data = try!(cbuf.read_vec(size));
+    let mut attributes = Vec::new();
+    for sub in format_str.as_bytes().chunks(2) {
+        let el_count = match (sub[0] as char).to_digit(10) {
+            Some(c) if c > 0 => c as u8,
+            _ => return Err(Error::Format(format_str.clone())),
+        };
+        let type_ = sub[1] as char;
+        let name = try!(cbuf.read_str()).to_string();
+        let flags = try!(cbuf.read_u8());
+        if (flags & FLAG_INSTANCED != 0) != instanced {
+            return Err(Error::Instanced(name))
+        }
+        debug!("\t\tname: {}, count: {}, type: {}, flags: {}",
+            name, el_count, type_, flags);
+        attributes.push(Attribute {
+            name: name,
+            count: el_count,
+            type_: type_,
+            flags: flags,
+        });
+    }
+    Ok(Buffer {
+        stride: stride,
+        attributes: attributes,
+        data: data,
+    })
+}
+
+/// Compute attribute formats of a buffer, checking the types and the stride.
+pub fn get_formats(buf: &Buffer) -> Result<Vec<gfx::attrib::Format>, Error> {
+    let mut offset = 0u32;
+    let mut formats = Vec::with_capacity(buf.attributes.len());
+    let instanced = buf.is_instanced();
+    for at in buf.attributes.iter() {
+        if (at.flags & FLAG_INSTANCED != 0) != instanced {
+            return Err(Error::Instanced(at.name.clone()))
+        }
+        let (el_count, el_type) = try!(get_element_type(at));
+        formats.push(gfx::attrib::Format {
+            elem_count: el_count,
+            elem_type: el_type,
+            offset: offset as gfx::attrib::Offset,
+            stride: buf.stride as gfx::attrib::Stride,
+            instance_rate: if instanced {1} else {0},
+        });
+        offset += el_count as u32 * el_type.get_size() as u32;
+    }
+    if offset != buf.stride as u32 {
+        return Err(Error::Stride(buf.stride, offset))
+    }
+    Ok(formats)
+}
+
+/// Read the contents of an `index` chunk.
+pub fn read_indices<I: io::Read>(cind: &mut Root<I>) -> Result<Indices, Error> {
+    let n_ind = try!(cind.read_u32());
+    let format = try!(cind.read_u8()) as char;
+    debug!("\tIndex format: {}, count: {}", format, n_ind);
+    Ok(match format {
+        'B' => {
+            let data = try!(cind.read_vec(n_ind));
+            Indices::U8(data)
         },
-        kind: gfx::SliceKind::Vertex,
+        'H' => {
+            let data = try!(cind.read_bytes(try!(get_data_size(n_ind, 2))));
+            Indices::U16(data.chunks(2).map(|c|
+                (c[0] as u16) | ((c[1] as u16) << 8)
+            ).collect())
+        },
+        // the exporter uses 'I' for 32-bit indices
+        'L' | 'I' => {
+            let data = try!(cind.read_bytes(try!(get_data_size(n_ind, 4))));
+            Indices::U32(data.chunks(4).map(decode_u32).collect())
+        },
+        _ => return Err(Error::IndexType(format)),
+    })
+}
+
+/// Parse the next `mesh` chunk into CPU-side data.
+pub fn parse<I: io::Read>(reader: &mut Root<I>) -> Result<MeshData, Error> {
+    let mut cmesh = try!(reader.enter());
+    if cmesh.get_name() != "mesh"    {
+        return Err(Error::Signature(cmesh.get_name().to_string()))
+    }
+    let mesh_name = try!(cmesh.read_str()).to_string();
+    let n_vert = try!(cmesh.read_u32());
+    info!("\tname: {}, vertices: {}", mesh_name, n_vert);
+    let topology = {
+        let top = try!(cmesh.read_str());
+        match parse_topology(top) {
+            Some(p) => p,
+            None => return Err(Error::Topology(top.to_string())),
+        }
+    };
+    let mut data = MeshData {
+        name: mesh_name,
+        num_vertices: n_vert,
+        topology: topology,
+        buffers: Vec::new(),
+        instances: Vec::new(),
+        indices: None,
     };
-    let mut mesh = gfx::Mesh::new(n_vert);
     while cmesh.has_more() {
-        let mut cbuf = cmesh.enter();
-        match &slice.kind {
-            _ if cbuf.get_name() == "buffer" => {
-                let stride = cbuf.read_u8();
-                let format_str = cbuf.read_str().to_string();
-                debug!("\tBuffer stride: {}, format: {}", stride, format_str);
-                let buffer = {
-                    let data = cbuf.read_bytes(n_vert * (stride as u32));
-                    factory.create_buffer_static_raw(data, gfx::BufferRole::Vertex)
-                };
-                let mut offset = 0;
-                for sub in format_str.as_bytes().chunks(2) {
-                    let el_count = sub[0] - ('0' as u8);
-                    let type_ = sub[1] as char;
-                    let name = cbuf.read_str().to_string();
-                    let flags = cbuf.read_u8();
-                    debug!("\t\tname: {}, count: {}, type: {}, flags: {}",
-                        name, el_count, type_, flags);
-                    let normalized = flags & 1;
-                    let el_type = match parse_type(type_, normalized) {
-                        Ok(t) => t,
-                        Err(_) => return Err(Error::AttribType(type_, flags)),
-                    };
-                    mesh.attributes.push(gfx::Attribute {
-                        name: format!("{}{}", super::PREFIX_ATTRIB, name),
-                        buffer: buffer.clone(),
-                        format: gfx::attrib::Format {
-                            elem_count: el_count,
-                            elem_type: el_type,
-                            offset: offset as gfx::attrib::Offset,
-                            stride: stride as gfx::attrib::Stride,
-                            instance_rate: 0,
-                        },
-                    });
-                    offset += el_count * el_type.get_size();
-                }
-                if offset != stride {
-                    return Err(Error::Stride(offset));
-                }
+        let mut cbuf = try!(cmesh.enter());
+        let chunk_name = cbuf.get_name().to_string();
+        match &chunk_name[..] {
+            "buffer" => {
+                let buf = try!(read_buffer(&mut *cbuf, n_vert));
+                data.buffers.push(buf);
             },
-            &gfx::SliceKind::Vertex if cbuf.get_name() == "index" => {
-                let n_ind = cbuf.read_u32();
-                let format = cbuf.read_u8() as char;
-                debug!("\tIndex format: {}, count: {}", format, n_ind);
-                slice.kind = match format {
-                    'B' => {
-                        let data = cbuf.read_bytes(n_ind * 1);
-                        let buf = factory.create_buffer_static(data, gfx::BufferRole::Index);
-                        gfx::SliceKind::Index8(buf, 0)
-                    },
-                    'H' => {
-                        let data = cbuf.read_bytes(n_ind * 2);
-                        let buf = factory.create_buffer_static(data, gfx::BufferRole::Index);
-                        gfx::SliceKind::Index16(buf.cast(), 0)
+            "instance" => {
+                let buf = try!(read_instances(&mut *cbuf));
+                data.instances.push(buf);
+            },
+            "index" if data.indices.is_none() => {
+                let indices = try!(read_indices(&mut *cbuf));
+                data.indices = Some(indices);
+            },
+            "index" => return Err(Error::DoubleIndex),
+            other => return Err(Error::Chunk(other.to_string())),
+        }
+        try!(cbuf.leave());
+    }
+    try!(data.check_indices());
+    Ok(data)
+}
+
+/// Upload the mesh data into GPU buffers.
+pub fn upload<R: gfx::Resources, F: gfx::Factory<R>>(data: &MeshData, factory: &mut F)
+              -> Result<Success<R>, Error> {
+    let mut mesh = gfx::Mesh::new(data.num_vertices);
+    for buf in data.buffers.iter().chain(data.instances.iter()) {
+        let formats = try!(get_formats(buf));
+        let buffer = if buf.attributes.iter().any(|at| at.flags & FLAG_PACKED != 0) {
+            factory.create_buffer_static_raw(&unpack_buffer(buf), gfx::BufferRole::Vertex)
+        } else {
+            factory.create_buffer_static_raw(&buf.data, gfx::BufferRole::Vertex)
+        };
+        for (at, fm) in buf.attributes.iter().zip(formats.into_iter()) {
+            mesh.attributes.push(gfx::Attribute {
+                name: format!("{}{}", super::PREFIX_ATTRIB, at.name),
+                buffer: buffer.clone(),
+                format: fm,
+            });
+        }
+    }
+    let (end, kind) = match data.indices {
+        Some(Indices::U8(ref ind)) => (ind.len(), gfx::SliceKind::Index8(
+            factory.create_buffer_static(&ind[..], gfx::BufferRole::Index), 0)),
+        Some(Indices::U16(ref ind)) => (ind.len(), gfx::SliceKind::Index16(
+            factory.create_buffer_static(&ind[..], gfx::BufferRole::Index), 0)),
+        Some(Indices::U32(ref ind)) => (ind.len(), gfx::SliceKind::Index32(
+            factory.create_buffer_static(&ind[..], gfx::BufferRole::Index), 0)),
+        None => (data.num_vertices as usize, gfx::SliceKind::Vertex),
+    };
+    let slice = gfx::Slice {
+        start: 0,
+        end: end as gfx::VertexCount,
+        prim_type: data.topology,
+        kind: kind,
+    };
+    Ok((mesh, slice))
+}
+
+pub fn load<I: io::Read, R: gfx::Resources, F: gfx::Factory<R>>(
+            reader: &mut Root<I>, factory: &mut F)
+            -> Result<(String, Success<R>), Error> {
+    let data = try!(parse(reader));
+    let success = try!(upload(&data, factory));
+    Ok((data.name, success))
+}
+
+/// Encode the mesh into a `mesh` chunk, readable by `load`.
+pub fn save<W: io::Write + io::Seek>(writer: &mut chunk::Writer<W>, mesh: &MeshData)
+            -> Result<(), Error> {
+    // validate first, so that nothing is written for a bad mesh
+    let all = mesh.buffers.iter().map(|b| (b, false))
+                  .chain(mesh.instances.iter().map(|b| (b, true)));
+    for (buf, instanced) in all {
+        try!(get_formats(buf));
+        if buf.attributes.iter().any(|at| at.count == 0 || at.count > 9) {
+            let format = buf.attributes.iter()
+                            .map(|at| format!("{}{}", at.count, at.type_))
+                            .collect();
+            return Err(Error::Format(format))
+        }
+        if buf.is_instanced() != instanced {
+            let name = buf.attributes.first().map(|at| at.name.clone());
+            return Err(Error::Instanced(name.unwrap_or(String::new())))
+        }
+        let count = if instanced {
+            // instance buffers have attributes, so the stride is not zero
+            buf.data.len() as u32 / buf.stride as u32
+        } else {
+            mesh.num_vertices
+        };
+        let size = try!(get_data_size(count, buf.stride as u32));
+        if buf.data.len() != size as usize {
+            return Err(Error::Size(buf.data.len() as u32, size))
+        }
+    }
+    write_mesh(writer, mesh).map_err(|e| Error::Write(e))
+}
+
+fn write_buffer<W: io::Write + io::Seek>(out: &mut chunk::Writer<W>, buf: &Buffer)
+                -> Result<(), io::Error> {
+    let format: String = buf.attributes.iter()
+                            .map(|at| format!("{}{}", at.count, at.type_))
+                            .collect();
+    try!(out.write_u8(buf.stride));
+    try!(out.write_str(&format));
+    try!(out.write_bytes(&buf.data));
+    for at in buf.attributes.iter() {
+        try!(out.write_str(&at.name));
+        try!(out.write_u8(at.flags));
+    }
+    Ok(())
+}
+
+fn write_mesh<W: io::Write + io::Seek>(out: &mut chunk::Writer<W>, mesh: &MeshData)
+              -> Result<(), io::Error> {
+    try!(out.begin("mesh"));
+    try!(out.write_str(&mesh.name));
+    try!(out.write_u32(mesh.num_vertices));
+    try!(out.write_str(get_topology_code(mesh.topology)));
+    for buf in mesh.buffers.iter() {
+        try!(out.begin("buffer"));
+        try!(write_buffer(out, buf));
+        try!(out.end());
+    }
+    for buf in mesh.instances.iter() {
+        try!(out.begin("instance"));
+        try!(out.write_u32(buf.data.len() as u32 / buf.stride as u32));
+        try!(write_buffer(out, buf));
+        try!(out.end());
+    }
+    match mesh.indices {
+        Some(ref indices) => {
+            try!(out.begin("index"));
+            match *indices {
+                Indices::U8(ref data) => {
+                    try!(out.write_u32(data.len() as u32));
+                    try!(out.write_u8('B' as u8));
+                    try!(out.write_bytes(data));
+                },
+                Indices::U16(ref data) => {
+                    try!(out.write_u32(data.len() as u32));
+                    try!(out.write_u8('H' as u8));
+                    for &i in data.iter() {
+                        try!(out.write_bytes(&[i as u8, (i >> 8) as u8]));
+                    }
+                },
+                Indices::U32(ref data) => {
+                    try!(out.write_u32(data.len() as u32));
+                    try!(out.write_u8('L' as u8));
+                    for &i in data.iter() {
+                        try!(out.write_u32(i));
+                    }
+                },
+            }
+            try!(out.end());
+        },
+        None => (),
+    }
+    out.end()
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use gfx;
+    use chunk;
+    use super::*;
+
+    fn make_mesh() -> MeshData {
+        MeshData {
+            name: "quad".to_string(),
+            num_vertices: 4,
+            topology: gfx::PrimitiveType::TriangleList,
+            buffers: vec![Buffer {
+                stride: 4,
+                attributes: vec![
+                    Attribute {
+                        name: "Position".to_string(),
+                        count: 2,
+                        type_: 'b',
+                        flags: 0,
                     },
-                    'L' => {
-                        let data = cbuf.read_bytes(n_ind * 4);
-                        let buf = factory.create_buffer_static(data, gfx::BufferRole::Index);
-                        gfx::SliceKind::Index32(buf.cast(), 0)
+                    Attribute {
+                        name: "Tex0".to_string(),
+                        count: 2,
+                        type_: 'B',
+                        flags: FLAG_NORMALIZED,
                     },
-                    _ => return Err(Error::IndexType(format)),
-                };
-            },
-            _ if cbuf.get_name() == "index" => return Err(Error::DoubleIndex),
-            _ => return Err(Error::Chunk(cbuf.get_name().to_string())),
+                ],
+                data: vec![0, 0, 0, 0, 1, 0, 255, 0, 0, 1, 0, 255, 1, 1, 255, 255],
+            }],
+            instances: Vec::new(),
+            indices: Some(Indices::U16(vec![0, 1, 2, 2, 1, 3])),
+        }
+    }
+
+    fn write(mesh: &MeshData) -> Vec<u8> {
+        let mut writer = chunk::Writer::new(Cursor::new(Vec::new()));
+        save(&mut writer, mesh).unwrap();
+        writer.into_inner().into_inner()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mesh = make_mesh();
+        let data = write(&mesh);
+        let mut root = chunk::Root::new("test".to_string(), Cursor::new(data));
+        let other = parse(&mut root).unwrap();
+        assert_eq!(other.name, mesh.name);
+        assert_eq!(other.num_vertices, mesh.num_vertices);
+        assert_eq!(other.topology, mesh.topology);
+        assert_eq!(other.buffers.len(), mesh.buffers.len());
+        for (a, b) in other.buffers.iter().zip(mesh.buffers.iter()) {
+            assert_eq!(a.stride, b.stride);
+            assert_eq!(a.data, b.data);
+            assert_eq!(a.attributes.len(), b.attributes.len());
+            for (x, y) in a.attributes.iter().zip(b.attributes.iter()) {
+                assert_eq!((&x.name, x.count, x.type_, x.flags),
+                           (&y.name, y.count, y.type_, y.flags));
+            }
+        }
+        match other.indices {
+            Some(Indices::U16(ref ind)) => assert_eq!(ind, &vec![0, 1, 2, 2, 1, 3]),
+            ref ind => panic!("Unexpected indices {:?}", ind),
+        }
+    }
+
+    #[test]
+    fn index_range() {
+        let mut mesh = make_mesh();
+        mesh.indices = Some(Indices::U16(vec![0, 1, 5]));
+        let data = write(&mesh);
+        let mut root = chunk::Root::new("test".to_string(), Cursor::new(data));
+        match parse(&mut root) {
+            Err(Error::IndexRange(5, 4)) => (),
+            other => panic!("Unexpected {:?}", other.map(|m| m.name)),
+        }
+    }
+
+    #[test]
+    fn nested_sizes() {
+        let mesh = make_mesh();
+        let data = write(&mesh);
+        let total = data.len() as u32;
+        let mut root = chunk::Root::new("test".to_string(), Cursor::new(data));
+        let mut cmesh = root.enter().unwrap();
+        assert_eq!(cmesh.get_name(), "mesh");
+        assert_eq!(cmesh.get_size(), total - 12);
+        cmesh.read_str().unwrap();
+        cmesh.read_u32().unwrap();
+        cmesh.read_str().unwrap();
+        {
+            let cbuf = cmesh.enter().unwrap();
+            assert_eq!(cbuf.get_name(), "buffer");
+            // stride, format, data, then the name and flags of each attribute
+            assert_eq!(cbuf.get_size(), 1 + 5 + 16 + (9 + 1) + (5 + 1));
+            cbuf.ignore().unwrap();
+        }
+        {
+            let cind = cmesh.enter().unwrap();
+            assert_eq!(cind.get_name(), "index");
+            assert_eq!(cind.get_size(), 4 + 1 + 6 * 2);
+            cind.ignore().unwrap();
+        }
+        assert!(!cmesh.has_more());
+    }
+
+    #[test]
+    fn half_floats() {
+        assert_eq!(decode_half(0x0000), 0.0);
+        assert_eq!(decode_half(0x3C00), 1.0);
+        assert_eq!(decode_half(0xC000), -2.0);
+        assert_eq!(decode_half(0x7BFF), 65504.0);
+        // smallest subnormal
+        assert_eq!(decode_half(0x0001), (2.0f32).powi(-24));
+        assert_eq!(decode_half(0x7C00), ::std::f32::INFINITY);
+        assert_eq!(decode_half(0xFC00), ::std::f32::NEG_INFINITY);
+        assert!(decode_half(0x7E00).is_nan());
+    }
+
+    #[test]
+    fn packed_unsigned() {
+        assert_eq!(unpack_10_10_10_2(0, false), [0.0; 4]);
+        assert_eq!(unpack_10_10_10_2(0xFFFFFFFF, false), [1.0; 4]);
+        let v = unpack_10_10_10_2(0x3FF | (1 << 30), false);
+        assert_eq!(v, [1.0, 0.0, 0.0, 1.0 / 3.0]);
+    }
+
+    #[test]
+    fn packed_signed() {
+        // x = 511, y = -512, z = 0, w = 1
+        let value = 0x1FF | (0x200 << 10) | (1 << 30);
+        assert_eq!(unpack_10_10_10_2(value, true), [1.0, -1.0, 0.0, 1.0]);
+        // w = -2 is clamped as well
+        assert_eq!(unpack_10_10_10_2(2 << 30, true), [0.0, 0.0, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn packed_buffer() {
+        let make = |type_, data| Buffer {
+            stride: 4,
+            attributes: vec![Attribute {
+                name: "Normal".to_string(),
+                count: 1,
+                type_: type_,
+                flags: FLAG_PACKED,
+            }],
+            data: data,
+        };
+        let unsigned = make('L', vec![0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x03, 0, 0]);
+        assert_eq!(unpack_buffer(&unsigned), vec![255, 255, 255, 255, 255, 0, 0, 0]);
+        // x = 511, y = -512, z = 0, w = 1
+        let signed = make('l', vec![0xFF, 0x01, 0x08, 0x40]);
+        assert_eq!(unpack_buffer(&signed), vec![127, 129, 0, 127]);
+    }
+
+    #[test]
+    fn instanced_round_trip() {
+        let mut mesh = make_mesh();
+        mesh.instances.push(Buffer {
+            stride: 2,
+            attributes: vec![Attribute {
+                name: "Offset".to_string(),
+                count: 2,
+                type_: 'b',
+                flags: FLAG_INSTANCED,
+            }],
+            data: vec![0, 0, 5, 0, 0, 5],
+        });
+        let data = write(&mesh);
+        let mut root = chunk::Root::new("test".to_string(), Cursor::new(data));
+        let other = parse(&mut root).unwrap();
+        assert_eq!(other.buffers.len(), 1);
+        assert_eq!(other.instances.len(), 1);
+        let buf = &other.instances[0];
+        assert_eq!(buf.data, vec![0, 0, 5, 0, 0, 5]);
+        assert_eq!(buf.attributes[0].flags, FLAG_INSTANCED);
+        let formats = get_formats(buf).unwrap();
+        assert_eq!(formats[0].instance_rate, 1);
+        assert_eq!(get_formats(&other.buffers[0]).unwrap()[0].instance_rate, 0);
+    }
+
+    #[test]
+    fn instanced_vertex_buffer() {
+        let mut mesh = make_mesh();
+        mesh.buffers[0].attributes[1].flags |= FLAG_INSTANCED;
+        let mut writer = chunk::Writer::new(Cursor::new(Vec::new()));
+        match save(&mut writer, &mesh) {
+            Err(Error::Instanced(ref name)) if name == "Tex0" => (),
+            other => panic!("Unexpected {:?}", other),
         }
     }
-    Ok((mesh_name, (mesh, slice)))
 }
@@ -0,0 +1,485 @@
+//! Wavefront OBJ/MTL importer, meant for quick prototyping.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::BufRead;
+use std::fs::File;
+use gfx;
+use claymore_scene as cs;
+use ::mesh;
+use ::process;
+use super::Scalar;
+
+#[derive(Debug)]
+pub enum Error {
+    Open(String, io::Error),
+    Read(io::Error),
+    /// Malformed statement at the given line of a file.
+    Syntax(String, usize),
+    /// Face refers to a missing element.
+    Index(String, usize),
+    NoGeometry,
+    Mesh(mesh::Error),
+    Texture(String, super::TextureError),
+}
+
+/// Material, as described in an MTL file.
+#[derive(Clone, Debug)]
+pub struct MaterialDesc {
+    pub color: [f32; 4],
+    pub texture: Option<String>,
+}
+
+fn open(path: &str) -> Result<io::BufReader<File>, Error> {
+    match File::open(path) {
+        Ok(file) => Ok(io::BufReader::new(file)),
+        Err(e) => Err(Error::Open(path.to_string(), e)),
+    }
+}
+
+fn parse_floats(words: &[&str], path: &str, line: usize) -> Result<[f32; 4], Error> {
+    let mut out = [0.0; 4];
+    if words.is_empty() || words.len() > 4 {
+        return Err(Error::Syntax(path.to_string(), line))
+    }
+    for (o, w) in out.iter_mut().zip(words.iter()) {
+        *o = match w.parse() {
+            Ok(v) => v,
+            Err(_) => return Err(Error::Syntax(path.to_string(), line)),
+        };
+    }
+    Ok(out)
+}
+
+/// Read the materials of an MTL file.
+pub fn read_materials(path: &str) -> Result<HashMap<String, MaterialDesc>, Error> {
+    parse_materials(try!(open(path)), path)
+}
+
+/// Parse the materials from MTL statements. The path is only used
+/// for the error messages.
+pub fn parse_materials<B: BufRead>(input: B, path: &str)
+                       -> Result<HashMap<String, MaterialDesc>, Error> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MaterialDesc)> = None;
+    for (number, line) in input.lines().enumerate() {
+        let line = try!(line.map_err(|e| Error::Read(e)));
+        let words: Vec<_> = line.split_whitespace().collect();
+        if words.is_empty() || words[0].starts_with('#') {
+            continue
+        }
+        if words[0] == "newmtl" {
+            if let Some((name, desc)) = current.take() {
+                materials.insert(name, desc);
+            }
+            current = Some((words[1..].join(" "), MaterialDesc {
+                color: [1.0, 1.0, 1.0, 1.0],
+                texture: None,
+            }));
+            continue
+        }
+        let desc = match current {
+            Some((_, ref mut d)) => d,
+            None => return Err(Error::Syntax(path.to_string(), number + 1)),
+        };
+        match words[0] {
+            "Kd" => {
+                let c = try!(parse_floats(&words[1..], path, number + 1));
+                desc.color = [c[0], c[1], c[2], desc.color[3]];
+            },
+            "d" => {
+                let d = try!(parse_floats(&words[1..], path, number + 1));
+                desc.color[3] = d[0];
+            },
+            "Tr" => {
+                let t = try!(parse_floats(&words[1..], path, number + 1));
+                desc.color[3] = 1.0 - t[0];
+            },
+            // options are not supported, the file name comes last
+            "map_Kd" if words.len() > 1 => {
+                desc.texture = Some(words[words.len() - 1].to_string());
+            },
+            other => debug!("Ignoring MTL statement {}", other),
+        }
+    }
+    if let Some((name, desc)) = current {
+        materials.insert(name, desc);
+    }
+    Ok(materials)
+}
+
+/// Mesh data of an OBJ file, with the triangles grouped by material.
+pub struct Model {
+    pub mesh: mesh::MeshData,
+    /// Material name with the range of indices, per fragment.
+    pub groups: Vec<(String, (u32, u32))>,
+    pub libraries: Vec<String>,
+}
+
+/// Resolve a 1-based or a negative relative index.
+fn resolve(word: &str, total: usize, path: &str, line: usize) -> Result<usize, Error> {
+    let id: i64 = match word.parse() {
+        Ok(v) => v,
+        Err(_) => return Err(Error::Syntax(path.to_string(), line)),
+    };
+    let index = if id < 0 {total as i64 + id} else {id - 1};
+    if index < 0 || index >= total as i64 {
+        Err(Error::Index(path.to_string(), line))
+    } else {
+        Ok(index as usize)
+    }
+}
+
+/// Read the geometry of an OBJ file, triangulating the polygons.
+pub fn read_model(path: &str) -> Result<Model, Error> {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    parse_model(try!(open(path)), path, name)
+}
+
+/// Parse the geometry from OBJ statements into a mesh with the given name.
+/// The path is only used for the error messages.
+pub fn parse_model<B: BufRead>(input: B, path: &str, name: &str)
+                   -> Result<Model, Error> {
+    let mut positions = Vec::new();
+    let mut tex_coords = Vec::new();
+    let mut normals = Vec::new();
+    let mut libraries = Vec::new();
+    // vertices are unique combinations of the position, texture and normal
+    let mut vertex_map: HashMap<(usize, Option<usize>, Option<usize>), u32> = HashMap::new();
+    let mut vertices = Vec::new();
+    // triangle lists of the materials, in the order of appearance
+    let mut groups: Vec<(String, Vec<u32>)> = Vec::new();
+    let mut group_id: Option<usize> = None;
+    for (number, line) in input.lines().enumerate() {
+        let line = try!(line.map_err(|e| Error::Read(e)));
+        let number = number + 1;
+        let words: Vec<_> = line.split_whitespace().collect();
+        if words.is_empty() || words[0].starts_with('#') {
+            continue
+        }
+        match words[0] {
+            "v" => {
+                // some exporters append the vertex color, which is ignored
+                let count = match words.len() - 1 {
+                    6 | 7 => words.len() - 4,
+                    n => n,
+                };
+                positions.push(try!(parse_floats(&words[1.. 1 + count], path, number)));
+            },
+            "vt" => tex_coords.push(try!(parse_floats(&words[1..], path, number))),
+            "vn" => normals.push(try!(parse_floats(&words[1..], path, number))),
+            "mtllib" => libraries.extend(words[1..].iter().map(|w| w.to_string())),
+            "usemtl" => {
+                let name = words[1..].join(" ");
+                group_id = Some(match groups.iter().position(|g| g.0 == name) {
+                    Some(id) => id,
+                    None => {
+                        groups.push((name, Vec::new()));
+                        groups.len() - 1
+                    },
+                });
+            },
+            "f" => {
+                if words.len() < 4 {
+                    return Err(Error::Syntax(path.to_string(), number))
+                }
+                let mut face = Vec::with_capacity(words.len() - 1);
+                for w in words[1..].iter() {
+                    let mut parts = w.split('/');
+                    let v = try!(resolve(parts.next().unwrap(), positions.len(), path, number));
+                    let vt = match parts.next() {
+                        Some(s) if !s.is_empty() =>
+                            Some(try!(resolve(s, tex_coords.len(), path, number))),
+                        _ => None,
+                    };
+                    let vn = match parts.next() {
+                        Some(s) if !s.is_empty() =>
+                            Some(try!(resolve(s, normals.len(), path, number))),
+                        _ => None,
+                    };
+                    let key = (v, vt, vn);
+                    let next = vertices.len() as u32;
+                    let id = *vertex_map.entry(key).or_insert(next);
+                    if id == next {
+                        vertices.push(key);
+                    }
+                    face.push(id);
+                }
+                let gid = match group_id {
+                    Some(id) => id,
+                    None => {
+                        groups.push((String::new(), Vec::new()));
+                        group_id = Some(groups.len() - 1);
+                        groups.len() - 1
+                    },
+                };
+                for i in 2.. face.len() {
+                    groups[gid].1.extend([face[0], face[i-1], face[i]].iter().cloned());
+                }
+            },
+            other => debug!("Ignoring OBJ statement {}", other),
+        }
+    }
+    if vertices.is_empty() {
+        return Err(Error::NoGeometry)
+    }
+    let mut data = mesh::MeshData {
+        name: name.to_string(),
+        num_vertices: vertices.len() as u32,
+        topology: gfx::PrimitiveType::TriangleList,
+        buffers: Vec::new(),
+        instances: Vec::new(),
+        indices: None,
+    };
+    let values: Vec<_> = vertices.iter().map(|&(v, _, _)| positions[v]).collect();
+    data.buffers.push(process::make_buffer("Position", &values, 3));
+    if vertices.iter().any(|&(_, vt, _)| vt.is_some()) {
+        let values: Vec<_> = vertices.iter().map(|&(_, vt, _)|
+            vt.map_or([0.0; 4], |i| tex_coords[i])
+        ).collect();
+        data.buffers.push(process::make_buffer("Tex0", &values, 2));
+    }
+    if vertices.iter().all(|&(_, _, vn)| vn.is_some()) {
+        let values: Vec<_> = vertices.iter().map(|&(_, _, vn)|
+            normals[vn.unwrap()]
+        ).collect();
+        data.buffers.push(process::make_buffer("Normal", &values, 3));
+    }
+    let mut indices = Vec::new();
+    let mut ranges = Vec::with_capacity(groups.len());
+    for (name, list) in groups.into_iter() {
+        let start = indices.len() as u32;
+        indices.extend(list.into_iter());
+        ranges.push((name, (start, indices.len() as u32)));
+    }
+    data.indices = Some(if data.num_vertices <= 0x10000 {
+        mesh::Indices::U16(indices.into_iter().map(|i| i as u16).collect())
+    } else {
+        mesh::Indices::U32(indices)
+    });
+    Ok(Model {
+        mesh: data,
+        groups: ranges,
+        libraries: libraries,
+    })
+}
+
+fn load_material<'a, R: 'a + gfx::Resources, F: 'a + gfx::Factory<R>>(
+                 desc: &MaterialDesc, dir: &str,
+                 sampler: &mut Option<gfx::handle::Sampler<R>>,
+                 context: &mut super::Context<'a, R, F>)
+                 -> Result<cs::Material<R>, Error> {
+    let mut out = cs::Material {
+        color: desc.color,
+        texture: None,
+        transparency: match (desc.color[3] < 1.0, context.alpha_test) {
+            (true, Some(v)) => cs::Transparency::Cutout(v),
+            (true, None)    => cs::Transparency::Blend(gfx::BlendPreset::Alpha),
+            (false, _)      => cs::Transparency::Opaque,
+        },
+    };
+    if let Some(ref name) = desc.texture {
+        let path = format!("{}/{}", dir, name);
+        let flip = context.flip_textures;
+        match context.request_texture(&path, true, flip) {
+            Ok(t) => {
+                if sampler.is_none() {
+                    let sinfo = gfx::tex::SamplerInfo::new(
+                        gfx::tex::FilterMethod::Trilinear,
+                        gfx::tex::WrapMode::Tile);
+                    *sampler = Some(context.factory.create_sampler(sinfo));
+                }
+                out.texture = Some((t, sampler.clone()));
+            },
+            Err(_) if context.forgive => (), //already errored in request_texture()
+            Err(e) => return Err(Error::Texture(path, e)),
+        }
+    }
+    Ok(out)
+}
+
+/// Load an OBJ file as a single entity attached to the given node,
+/// with a fragment per material.
+pub fn load_into<'a, R: 'a + gfx::Resources, F: 'a + gfx::Factory<R>>(
+                 this: &mut cs::Scene<R, Scalar>, node: cs::NodeId<Scalar>,
+                 path: &str, context: &mut super::Context<'a, R, F>)
+                 -> Result<(), Error> {
+    let dir = match path.rfind('/') {
+        Some(p) => &path[..p],
+        None => ".",
+    };
+    let mut model = try!(read_model(path));
+    let mut materials = HashMap::new();
+    for lib in model.libraries.iter() {
+        materials.extend(try!(read_materials(&format!("{}/{}", dir, lib))).into_iter());
+    }
+    if let Some(mode) = context.complete_meshes {
+        process::complete(&mut model.mesh, mode);
+    }
+    let total = model.mesh.get_index_list().len() as u32;
+    let bound = match model.mesh.compute_bound(0, total) {
+        Some(b) => b,
+        None => return Err(Error::NoGeometry),
+    };
+    let (mesh, slice) = try!(mesh::upload(&model.mesh, context.factory)
+                                 .map_err(|e| Error::Mesh(e)));
+    let mut entity = cs::Entity::new(mesh, node, bound);
+    entity.name = model.mesh.name.clone();
+    let default_desc = MaterialDesc {
+        color: [1.0, 1.0, 1.0, 1.0],
+        texture: None,
+    };
+    // all the textures share the sampler, and the missing
+    // materials share the default one
+    let mut sampler = None;
+    let mut loaded: HashMap<&str, cs::Material<R>> = HashMap::new();
+    for &(ref name, (start, end)) in model.groups.iter() {
+        if start == end {
+            continue
+        }
+        let (key, desc) = match materials.get(name) {
+            Some(d) => (&name[..], d),
+            None => {
+                if !name.is_empty() {
+                    warn!("Material {} is not found, using the default", name);
+                }
+                ("", &default_desc)
+            },
+        };
+        if !loaded.contains_key(key) {
+            let m = try!(load_material(desc, dir, &mut sampler, context));
+            loaded.insert(key, m);
+        }
+        let material = loaded[key].clone();
+        let mut frag_slice = slice.clone();
+        frag_slice.start = start as gfx::VertexCount;
+        frag_slice.end = end as gfx::VertexCount;
+        entity.add_fragment(material, frag_slice);
+    }
+    this.entities.push(entity);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use super::*;
+
+    fn model(text: &str) -> Result<Model, Error> {
+        parse_model(Cursor::new(text.as_bytes()), "test.obj", "test")
+    }
+
+    const QUAD: &'static str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0";
+
+    #[test]
+    fn triangulate() {
+        let m = model(&format!("{}\n f 1 2 3 4", QUAD)).unwrap();
+        assert_eq!(m.mesh.num_vertices, 4);
+        assert_eq!(m.mesh.get_index_list(), vec![0, 1, 2, 0, 2, 3]);
+        let m = model("v 0 0 0\n v 1 0 0\n v 2 1 0\n v 1 2 0\n v 0 1 0\n f 1 2 3 4 5").unwrap();
+        assert_eq!(m.mesh.get_index_list(), vec![0, 1, 2, 0, 2, 3, 0, 3, 4]);
+        match model(&format!("{}\n f 1 2", QUAD)) {
+            Err(Error::Syntax(_, 5)) => (),
+            other => panic!("unexpected {:?}", other.map(|m| m.groups)),
+        }
+    }
+
+    #[test]
+    fn relative_indices() {
+        let m = model(&format!("{}\n f -4 -3 -2\n f 1 -2 -1", QUAD)).unwrap();
+        assert_eq!(m.mesh.get_index_list(), vec![0, 1, 2, 0, 2, 3]);
+        // relative to the vertices declared so far
+        let m = model("v 0 0 0\n v 1 0 0\n v 1 1 0\n f -3 -2 -1\n v 0 1 0\n f -4 -2 -1").unwrap();
+        assert_eq!(m.mesh.get_index_list(), vec![0, 1, 2, 0, 2, 3]);
+        for face in ["f 1 2 5", "f 0 1 2", "f -5 1 2"].iter() {
+            match model(&format!("{}\n {}", QUAD, face)) {
+                Err(Error::Index(_, 5)) => (),
+                other => panic!("unexpected {:?}", other.map(|m| m.groups)),
+            }
+        }
+    }
+
+    #[test]
+    fn attributes() {
+        let text = format!("{}\n vt 0 0\n vt 1 1\n vn 0 0 1
+            f 1/1/1 2/2/1 3/1/1\n f 1/1/1 3/1/1 4/2/1", QUAD);
+        let m = model(&text).unwrap();
+        // the shared combinations are welded
+        assert_eq!(m.mesh.num_vertices, 4);
+        let tex = m.mesh.read_attribute("Tex0").unwrap();
+        assert_eq!(&tex[..2], &[[0.0, 0.0, 0.0, 0.0], [1.0, 1.0, 0.0, 0.0]]);
+        let normals = m.mesh.read_attribute("Normal").unwrap();
+        assert!(normals.iter().all(|n| n[..3] == [0.0, 0.0, 1.0]));
+        // a different texture coordinate makes a new vertex
+        let m = model(&format!("{}\n vt 0 0\n vt 1 1\n f 1/1 2/1 3/1\n f 1/2 3/1 4/1", QUAD)).unwrap();
+        assert_eq!(m.mesh.num_vertices, 5);
+        assert!(m.mesh.find_attribute("Normal").is_none());
+    }
+
+    #[test]
+    fn vertex_colors() {
+        let m = model("v 0 0 0 1 0 0\n v 1 0 0 0 1 0\n v 1 1 0 1 1 0 0.5\n f 1 2 3").unwrap();
+        let positions = m.mesh.read_attribute("Position").unwrap();
+        assert_eq!(&positions[..], &[[0.0, 0.0, 0.0, 0.0], [1.0, 0.0, 0.0, 0.0],
+                                     [1.0, 1.0, 0.0, 0.0]]);
+        match model("v 0 0 0 1 0\n v 1 0 0\n v 1 1 0\n f 1 2 3") {
+            Err(Error::Syntax(_, 1)) => (),
+            other => panic!("unexpected {:?}", other.map(|m| m.groups)),
+        }
+    }
+
+    #[test]
+    fn material_groups() {
+        let text = format!("{}
+            mtllib a.mtl b.mtl
+            f 1 2 3
+            usemtl red
+            f 1 2 3
+            usemtl blue
+            f 1 3 4
+            usemtl red
+            f 2 3 4", QUAD);
+        let m = model(&text).unwrap();
+        assert_eq!(m.libraries, vec!["a.mtl".to_string(), "b.mtl".to_string()]);
+        let groups: Vec<_> = m.groups.iter().map(|&(ref n, r)| (&n[..], r)).collect();
+        assert_eq!(groups, vec![("", (0, 3)), ("red", (3, 9)), ("blue", (9, 12))]);
+        assert_eq!(m.mesh.get_index_list(), vec![0, 1, 2, 0, 1, 2, 1, 2, 3, 0, 2, 3]);
+    }
+
+    #[test]
+    fn no_geometry() {
+        match model(QUAD) {
+            Err(Error::NoGeometry) => (),
+            other => panic!("unexpected {:?}", other.map(|m| m.groups)),
+        }
+    }
+
+    #[test]
+    fn materials() {
+        let text = "
+            # exported
+            newmtl red
+            Kd 1 0 0
+            d 0.5
+            newmtl textured stone
+            Ka 0.1 0.1 0.1
+            Tr 0.25
+            map_Kd -s 2 2 1 stone.png
+        ";
+        let mats = parse_materials(Cursor::new(text.as_bytes()), "test.mtl").unwrap();
+        assert_eq!(mats.len(), 2);
+        assert_eq!(mats["red"].color, [1.0, 0.0, 0.0, 0.5]);
+        assert_eq!(mats["red"].texture, None);
+        let stone = &mats["textured stone"];
+        assert_eq!(stone.color, [1.0, 1.0, 1.0, 0.75]);
+        assert_eq!(stone.texture, Some("stone.png".to_string()));
+        match parse_materials(Cursor::new(&b"Kd 1 1 1\nnewmtl x"[..]), "test.mtl") {
+            Err(Error::Syntax(_, 1)) => (),
+            other => panic!("unexpected {:?}", other.map(|m| m.len())),
+        }
+        match parse_materials(Cursor::new(&b"newmtl x\nKd 1 one 1"[..]), "test.mtl") {
+            Err(Error::Syntax(_, 2)) => (),
+            other => panic!("unexpected {:?}", other.map(|m| m.len())),
+        }
+    }
+}
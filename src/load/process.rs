@@ -0,0 +1,801 @@
+use cgmath;
+use cgmath::{EuclideanVector, Matrix, Matrix4, Vector, Vector3, Vector4};
+use gfx;
+use ::mesh;
+use ::mesh::{Attribute, Buffer, Indices, MeshData};
+
+/// How to generate missing normals.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Normals {
+    /// Average the face normals around each vertex, weighted by area.
+    Smooth,
+    /// Use the face normal on each corner, splitting the vertices.
+    /// Only available for triangle lists, falling back to `Smooth`.
+    Flat,
+}
+
+fn encode_f32(value: f32, output: &mut Vec<u8>) {
+    let bits: u32 = unsafe { ::std::mem::transmute(value) };
+    output.extend([
+        (bits >> 0) as u8, (bits >> 8) as u8,
+        (bits >> 16) as u8, (bits >> 24) as u8,
+    ].iter().cloned());
+}
+
+/// Create a float buffer with a single attribute, taking
+/// the first `count` components of each value.
+pub fn make_buffer(name: &str, values: &[[f32; 4]], count: u8) -> Buffer {
+    let mut data = Vec::with_capacity(values.len() * count as usize * 4);
+    for v in values.iter() {
+        for &x in v[.. count as usize].iter() {
+            encode_f32(x, &mut data);
+        }
+    }
+    Buffer {
+        stride: count * 4,
+        attributes: vec![Attribute {
+            name: name.to_string(),
+            count: count,
+            type_: 'f',
+            flags: 0,
+        }],
+        data: data,
+    }
+}
+
+fn to_vector(v: &[f32; 4]) -> Vector3<f32> {
+    Vector3::new(v[0], v[1], v[2])
+}
+
+/// Collect the vertex indices of all triangles, respecting the strip winding.
+pub fn get_triangles(mesh: &MeshData) -> Vec<[u32; 3]> {
+    let ind = mesh.get_index_list();
+    match mesh.topology {
+        gfx::PrimitiveType::TriangleList => ind.chunks(3)
+            .filter(|t| t.len() == 3)
+            .map(|t| [t[0], t[1], t[2]])
+            .collect(),
+        gfx::PrimitiveType::TriangleStrip => (2.. ind.len()).map(|i|
+            if i & 1 == 0 {
+                [ind[i-2], ind[i-1], ind[i]]
+            } else {
+                [ind[i-1], ind[i-2], ind[i]]
+            }).collect(),
+        gfx::PrimitiveType::TriangleFan => (2.. ind.len()).map(|i|
+            [ind[0], ind[i-1], ind[i]]
+            ).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn compute_smooth_normals(positions: &[[f32; 4]], triangles: &[[u32; 3]])
+                          -> Vec<[f32; 4]> {
+    let mut normals = vec![Vector3::new(0.0f32, 0.0, 0.0); positions.len()];
+    for t in triangles.iter() {
+        let p0 = to_vector(&positions[t[0] as usize]);
+        let e1 = to_vector(&positions[t[1] as usize]).sub_v(&p0);
+        let e2 = to_vector(&positions[t[2] as usize]).sub_v(&p0);
+        // the length of the cross product is twice the area
+        let n = e1.cross(&e2);
+        for &i in t.iter() {
+            normals[i as usize].add_self_v(&n);
+        }
+    }
+    normals.iter().map(|n| {
+        let n = if n.length() > 0.0 {n.normalize()} else {Vector3::unit_z()};
+        [n.x, n.y, n.z, 0.0]
+    }).collect()
+}
+
+/// Split the vertices of an indexed triangle list, so that each
+/// triangle corner gets its own vertex.
+fn unweld(mesh: &MeshData) -> MeshData {
+    let ind = mesh.get_index_list();
+    let buffers = mesh.buffers.iter().map(|buf| {
+        let stride = buf.stride as usize;
+        let mut data = Vec::with_capacity(ind.len() * stride);
+        for &i in ind.iter() {
+            let start = i as usize * stride;
+            data.extend(buf.data[start.. start + stride].iter().cloned());
+        }
+        Buffer {
+            stride: buf.stride,
+            attributes: buf.attributes.clone(),
+            data: data,
+        }
+    }).collect();
+    MeshData {
+        name: mesh.name.clone(),
+        num_vertices: ind.len() as u32,
+        topology: mesh.topology,
+        buffers: buffers,
+        instances: mesh.instances.clone(),
+        indices: None,
+    }
+}
+
+/// Append a `Normal` attribute. Returns false if the mesh has no triangles
+/// or positions to compute it from.
+pub fn generate_normals(mesh: &mut MeshData, mode: Normals) -> bool {
+    let triangles = get_triangles(mesh);
+    if triangles.is_empty() {
+        return false
+    }
+    if mode == Normals::Flat {
+        if mesh.topology == gfx::PrimitiveType::TriangleList {
+            if mesh.indices.is_some() {
+                *mesh = unweld(mesh);
+            }
+            // with the vertices split, each vertex belongs to one triangle
+            return generate_normals(mesh, Normals::Smooth)
+        }
+        warn!("Flat normals are not supported for {:?} of mesh {}, using smooth",
+            mesh.topology, mesh.name);
+    }
+    let positions = match mesh.read_attribute("Position") {
+        Some(p) => p,
+        None => return false,
+    };
+    let normals = compute_smooth_normals(&positions, &triangles);
+    mesh.buffers.push(make_buffer("Normal", &normals, 3));
+    true
+}
+
+/// Append a `Tangent` attribute, with the bitangent sign in W,
+/// following the MikkTSpace conventions. Requires positions, normals
+/// and the texture coordinates of the given layer.
+pub fn generate_tangents(mesh: &mut MeshData, tex_name: &str) -> bool {
+    let triangles = get_triangles(mesh);
+    let (positions, normals, tex_coords) = match (
+            mesh.read_attribute("Position"),
+            mesh.read_attribute("Normal"),
+            mesh.read_attribute(tex_name)) {
+        (Some(p), Some(n), Some(t)) => (p, n, t),
+        _ => return false,
+    };
+    if triangles.is_empty() {
+        return false
+    }
+    let zero = Vector3::new(0.0f32, 0.0, 0.0);
+    let mut tangents = vec![zero; positions.len()];
+    let mut bitangents = vec![zero; positions.len()];
+    for t in triangles.iter() {
+        let (i0, i1, i2) = (t[0] as usize, t[1] as usize, t[2] as usize);
+        let p0 = to_vector(&positions[i0]);
+        let e1 = to_vector(&positions[i1]).sub_v(&p0);
+        let e2 = to_vector(&positions[i2]).sub_v(&p0);
+        let (s1, t1) = (tex_coords[i1][0] - tex_coords[i0][0],
+                        tex_coords[i1][1] - tex_coords[i0][1]);
+        let (s2, t2) = (tex_coords[i2][0] - tex_coords[i0][0],
+                        tex_coords[i2][1] - tex_coords[i0][1]);
+        let det = s1 * t2 - s2 * t1;
+        if det == 0.0 {
+            continue
+        }
+        // keep only the sign of the determinant, weighting the
+        // contribution by the size of the triangle
+        let sign = if det > 0.0 {1.0} else {-1.0};
+        let tan = e1.mul_s(t2).sub_v(&e2.mul_s(t1)).mul_s(sign);
+        let bit = e2.mul_s(s1).sub_v(&e1.mul_s(s2)).mul_s(sign);
+        for &i in [i0, i1, i2].iter() {
+            tangents[i].add_self_v(&tan);
+            bitangents[i].add_self_v(&bit);
+        }
+    }
+    let result: Vec<_> = normals.iter().enumerate().map(|(i, n)| {
+        let n = to_vector(n);
+        // Gram-Schmidt orthogonalization against the normal
+        let t = tangents[i].sub_v(&n.mul_s(n.dot(&tangents[i])));
+        let t = if t.length() > 0.0 {
+            t.normalize()
+        } else {
+            // any direction orthogonal to the normal
+            let axis = if n.x.abs() < 0.9 {Vector3::unit_x()} else {Vector3::unit_y()};
+            n.cross(&axis).normalize()
+        };
+        let hand = if n.cross(&t).dot(&bitangents[i]) < 0.0 {-1.0} else {1.0};
+        [t.x, t.y, t.z, hand]
+    }).collect();
+    mesh.buffers.push(make_buffer("Tangent", &result, 4));
+    true
+}
+
+/// Generate the normals and tangents the mesh lacks.
+/// Tangents are based on the first texture coordinate layer.
+pub fn complete(mesh: &mut MeshData, mode: Normals) {
+    if mesh.find_attribute("Normal").is_none() {
+        info!("Generating normals for mesh {}", mesh.name);
+        if !generate_normals(mesh, mode) {
+            warn!("Unable to generate normals for mesh {}", mesh.name);
+            return
+        }
+    }
+    if mesh.find_attribute("Tangent").is_none() &&
+            mesh.find_attribute("Quaternion").is_none() &&
+            mesh.find_attribute("Tex0").is_some() {
+        info!("Generating tangents for mesh {}", mesh.name);
+        if !generate_tangents(mesh, "Tex0") {
+            warn!("Unable to generate tangents for mesh {}", mesh.name);
+        }
+    }
+}
+
+/// Size of the simulated post-transform cache, used for optimization.
+static CACHE_SIZE: usize = 32;
+
+/// Average cache miss ratio: the number of vertex transformations
+/// per triangle, given a FIFO cache of the given size.
+pub fn compute_acmr(indices: &[u32], cache_size: usize) -> f32 {
+    use std::collections::VecDeque;
+    let num_triangles = indices.len() / 3;
+    if num_triangles == 0 {
+        return 0.0
+    }
+    let mut cache = VecDeque::with_capacity(cache_size);
+    let mut misses = 0;
+    for &i in indices[.. num_triangles * 3].iter() {
+        if !cache.contains(&i) {
+            misses += 1;
+            if cache.len() == cache_size {
+                cache.pop_front();
+            }
+            cache.push_back(i);
+        }
+    }
+    misses as f32 / num_triangles as f32
+}
+
+fn get_vertex_score(cache_pos: Option<usize>, valence: u32) -> f32 {
+    if valence == 0 {
+        return -1.0
+    }
+    let cache_score = match cache_pos {
+        None => 0.0,
+        // the last triangle is going to be reused anyway
+        Some(p) if p < 3 => 0.75,
+        Some(p) => {
+            let scale = 1.0 / (CACHE_SIZE - 3) as f32;
+            (1.0 - (p - 3) as f32 * scale).powf(1.5)
+        },
+    };
+    // prefer finishing the vertices with few triangles left
+    cache_score + 2.0 * (valence as f32).powf(-0.5)
+}
+
+/// Reorder triangles of a list for the post-transform cache,
+/// following the "Linear-Speed Vertex Cache Optimisation" by Tom Forsyth.
+/// All the indices have to be below `num_vertices`.
+pub fn optimize_triangles(indices: &[u32], num_vertices: u32) -> Vec<u32> {
+    let num_triangles = indices.len() / 3;
+    let num_vertices = num_vertices as usize;
+    let mut vert_triangles: Vec<Vec<usize>> = vec![Vec::new(); num_vertices];
+    for (t, tri) in indices.chunks(3).take(num_triangles).enumerate() {
+        for &i in tri.iter() {
+            vert_triangles[i as usize].push(t);
+        }
+    }
+    let mut cache_pos: Vec<Option<usize>> = vec![None; num_vertices];
+    let mut vert_scores: Vec<f32> = vert_triangles.iter().map(|tris|
+        get_vertex_score(None, tris.len() as u32)
+    ).collect();
+    let mut tri_scores: Vec<f32> = indices.chunks(3).take(num_triangles).map(|tri|
+        tri.iter().map(|&i| vert_scores[i as usize]).fold(0.0, |a, b| a + b)
+    ).collect();
+    let mut added = vec![false; num_triangles];
+    let mut cache: Vec<u32> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut output = Vec::with_capacity(num_triangles * 3);
+    let mut best: Option<usize> = None;
+    let mut cursor = 0;
+    for _ in 0.. num_triangles {
+        let t = match best {
+            Some(t) => t,
+            None => {
+                // nothing is adjacent to the cache, continue with the first
+                // remaining triangle, which keeps the whole pass linear
+                while added[cursor] {
+                    cursor += 1;
+                }
+                cursor
+            },
+        };
+        added[t] = true;
+        let tri = &indices[t*3 .. t*3 + 3];
+        output.extend(tri.iter().cloned());
+        // update the adjacency and the cache
+        for &i in tri.iter() {
+            vert_triangles[i as usize].retain(|&x| x != t);
+            cache.retain(|&x| x != i);
+        }
+        let mut new_cache = tri.to_vec();
+        new_cache.extend(cache.iter().cloned());
+        for &i in new_cache[::std::cmp::min(new_cache.len(), CACHE_SIZE)..].iter() {
+            cache_pos[i as usize] = None;
+            vert_scores[i as usize] = get_vertex_score(None,
+                vert_triangles[i as usize].len() as u32);
+        }
+        new_cache.truncate(CACHE_SIZE);
+        for (p, &i) in new_cache.iter().enumerate() {
+            cache_pos[i as usize] = Some(p);
+            vert_scores[i as usize] = get_vertex_score(Some(p),
+                vert_triangles[i as usize].len() as u32);
+        }
+        // evicted vertices are not adjacent to any candidates
+        // worth considering, so only the cache is rescored
+        best = None;
+        for &i in new_cache.iter() {
+            for &ot in vert_triangles[i as usize].iter() {
+                let score = indices[ot*3 .. ot*3 + 3].iter()
+                    .map(|&j| vert_scores[j as usize])
+                    .fold(0.0, |a, b| a + b);
+                tri_scores[ot] = score;
+                match best {
+                    Some(b) if tri_scores[b] >= score => (),
+                    _ => best = Some(ot),
+                }
+            }
+        }
+        cache = new_cache;
+    }
+    output
+}
+
+fn make_indices(list: Vec<u32>, num_vertices: u32) -> Indices {
+    if num_vertices <= 0x100 {
+        Indices::U8(list.into_iter().map(|i| i as u8).collect())
+    } else if num_vertices <= 0x10000 {
+        Indices::U16(list.into_iter().map(|i| i as u16).collect())
+    } else {
+        Indices::U32(list)
+    }
+}
+
+/// Merge the vertices that are identical in all the buffers.
+/// Returns the new index list, together with the old index of each
+/// unique vertex.
+fn deduplicate(mesh: &MeshData, indices: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    use std::collections::HashMap;
+    let mut map: HashMap<Vec<u8>, u32> = HashMap::new();
+    let mut remap = Vec::with_capacity(mesh.num_vertices as usize);
+    let mut unique = Vec::new();
+    for v in 0.. mesh.num_vertices as usize {
+        let mut key = Vec::new();
+        for buf in mesh.buffers.iter() {
+            let stride = buf.stride as usize;
+            key.extend(buf.data[v * stride .. (v+1) * stride].iter().cloned());
+        }
+        let next = unique.len() as u32;
+        let id = *map.entry(key).or_insert(next);
+        if id == next {
+            unique.push(v as u32);
+        }
+        remap.push(id);
+    }
+    let list = indices.iter().map(|&i| remap[i as usize]).collect();
+    (list, unique)
+}
+
+/// Outcome of the `optimize` pass.
+#[derive(Clone, Debug)]
+pub struct Report {
+    pub vertices_before: u32,
+    pub vertices_after: u32,
+    pub acmr_before: f32,
+    pub acmr_after: f32,
+}
+
+/// Merge the identical vertices, reorder the triangles of each index
+/// range for the post-transform cache, and store the vertices in the
+/// order of their first use, dropping the unused ones. Triangles never
+/// move between the ranges, so the fragment slices stay valid.
+/// An empty list of ranges covers the whole mesh.
+pub fn optimize(mesh: &mut MeshData, ranges: &[(u32, u32)]) -> Result<Report, mesh::Error> {
+    try!(mesh.check_indices());
+    for buf in mesh.buffers.iter() {
+        let size = mesh.num_vertices as usize * buf.stride as usize;
+        if buf.data.len() != size {
+            return Err(mesh::Error::Size(buf.data.len() as u32, size as u32))
+        }
+    }
+    let indices = mesh.get_index_list();
+    let acmr_before = compute_acmr(&indices, CACHE_SIZE);
+    let vertices_before = mesh.num_vertices;
+    let (mut list, unique) = deduplicate(mesh, &indices);
+    // reorder the triangles
+    if mesh.topology == gfx::PrimitiveType::TriangleList {
+        let full = [(0, list.len() as u32)];
+        let ranges = if ranges.is_empty() {&full[..]} else {ranges};
+        for &(start, end) in ranges.iter() {
+            let (start, end) = (start as usize, end as usize);
+            if start > end || end > list.len() || (end - start) % 3 != 0 {
+                warn!("Skipping the invalid range {:?} of mesh {}",
+                    (start, end), mesh.name);
+                continue
+            }
+            let optimized = optimize_triangles(&list[start..end], unique.len() as u32);
+            for (dst, src) in list[start..end].iter_mut().zip(optimized.into_iter()) {
+                *dst = src;
+            }
+        }
+    }
+    // compact the vertices in the order of use
+    let mut order = Vec::with_capacity(unique.len());
+    let mut new_ids: Vec<Option<u32>> = vec![None; unique.len()];
+    for i in list.iter_mut() {
+        let id = match new_ids[*i as usize] {
+            Some(id) => id,
+            None => {
+                let id = order.len() as u32;
+                new_ids[*i as usize] = Some(id);
+                order.push(unique[*i as usize]);
+                id
+            },
+        };
+        *i = id;
+    }
+    for buf in mesh.buffers.iter_mut() {
+        let stride = buf.stride as usize;
+        let mut data = Vec::with_capacity(order.len() * stride);
+        for &v in order.iter() {
+            let start = v as usize * stride;
+            data.extend(buf.data[start.. start + stride].iter().cloned());
+        }
+        buf.data = data;
+    }
+    mesh.num_vertices = order.len() as u32;
+    let report = Report {
+        vertices_before: vertices_before,
+        vertices_after: mesh.num_vertices,
+        acmr_before: acmr_before,
+        acmr_after: compute_acmr(&list, CACHE_SIZE),
+    };
+    mesh.indices = Some(make_indices(list, mesh.num_vertices));
+    Ok(report)
+}
+
+/// Skin the mesh on CPU: blend the palette matrices by the bone weights
+/// of each vertex and transform the positions, normals and tangents.
+/// Bone indices refer to the palette, as produced by
+/// `World::get_skinning_palette`. Other attributes are copied as floats,
+/// while the bone attributes are dropped. Returns `None` if the mesh
+/// lacks positions or bone attributes.
+pub fn skin(mesh: &MeshData, palette: &[Matrix4<f32>]) -> Option<MeshData> {
+    let (bone_ids, weights) = match (
+            mesh.read_attribute("BoneIndex"),
+            mesh.read_attribute("BoneWeight")) {
+        (Some(i), Some(w)) => (i, w),
+        _ => return None,
+    };
+    if mesh.find_attribute("Position").is_none() {
+        return None
+    }
+    let zero: Matrix4<f32> = cgmath::zero();
+    let transforms: Vec<Matrix4<f32>> = bone_ids.iter().zip(weights.iter()).map(|(ids, ws)| {
+        let mut mx = zero;
+        for (&id, &w) in ids.iter().zip(ws.iter()) {
+            if w == 0.0 {
+                continue
+            }
+            match palette.get(id as usize) {
+                Some(bone) => mx = mx.add_m(&bone.mul_s(w)),
+                None => warn!("Bone index {} is out of the palette of size {}",
+                    id, palette.len()),
+            }
+        }
+        mx
+    }).collect();
+    let mut out = MeshData {
+        name: mesh.name.clone(),
+        num_vertices: mesh.num_vertices,
+        topology: mesh.topology,
+        buffers: Vec::new(),
+        instances: mesh.instances.clone(),
+        indices: mesh.indices.clone(),
+    };
+    for at in mesh.buffers.iter().flat_map(|b| b.attributes.iter()) {
+        let mut values = match &at.name[..] {
+            "BoneIndex" | "BoneWeight" => continue,
+            name => match mesh.read_attribute(name) {
+                Some(v) => v,
+                None => return None,
+            },
+        };
+        let is_point = match &at.name[..] {
+            "Position" => true,
+            "Normal" | "Tangent" => false,
+            _ => {
+                out.buffers.push(make_buffer(&at.name, &values, ::std::cmp::min(at.count, 4)));
+                continue
+            },
+        };
+        for (v, mx) in values.iter_mut().zip(transforms.iter()) {
+            let w = if is_point {1.0} else {0.0};
+            let r = mx.mul_v(&Vector4::new(v[0], v[1], v[2], w));
+            let r = if is_point {
+                Vector3::new(r.x, r.y, r.z)
+            } else {
+                Vector3::new(r.x, r.y, r.z).normalize()
+            };
+            // the tangent handedness in W is preserved
+            *v = [r.x, r.y, r.z, v[3]];
+        }
+        out.buffers.push(make_buffer(&at.name, &values, if at.name == "Tangent" {4} else {3}));
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath::Matrix4;
+    use gfx;
+    use mesh::{Indices, MeshData};
+    use super::*;
+
+    fn translation(x: f32, y: f32, z: f32) -> Matrix4<f32> {
+        Matrix4::new(1.0, 0.0, 0.0, 0.0,
+                     0.0, 1.0, 0.0, 0.0,
+                     0.0, 0.0, 1.0, 0.0,
+                     x, y, z, 1.0)
+    }
+
+    /// Two vertices: the first fully bound to bone 1,
+    /// the second shared evenly between bones 1 and 2.
+    fn make_mesh() -> MeshData {
+        MeshData {
+            name: "skinned".to_string(),
+            num_vertices: 2,
+            topology: gfx::PrimitiveType::Point,
+            buffers: vec![
+                make_buffer("Position", &[[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0]], 3),
+                make_buffer("Normal", &[[0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 1.0, 0.0]], 3),
+                make_buffer("BoneIndex", &[[1.0, 0.0, 0.0, 0.0], [1.0, 2.0, 0.0, 0.0]], 2),
+                make_buffer("BoneWeight", &[[1.0, 0.0, 0.0, 0.0], [0.5, 0.5, 0.0, 0.0]], 2),
+            ],
+            instances: Vec::new(),
+            indices: Some(Indices::U8(vec![0, 1])),
+        }
+    }
+
+    fn assert_near(a: &[f32; 4], b: [f32; 3]) {
+        for i in 0.. 3 {
+            assert!((a[i] - b[i]).abs() < 1e-5, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn skin_weights() {
+        let palette = [
+            translation(0.0, 0.0, 0.0),
+            translation(1.0, 0.0, 0.0),
+            translation(0.0, 0.0, 2.0),
+        ];
+        let out = skin(&make_mesh(), &palette).unwrap();
+        assert!(out.find_attribute("BoneIndex").is_none());
+        assert!(out.find_attribute("BoneWeight").is_none());
+        let pos = out.read_attribute("Position").unwrap();
+        assert_near(&pos[0], [2.0, 0.0, 0.0]);
+        assert_near(&pos[1], [0.5, 1.0, 1.0]);
+        // normals are not affected by translation
+        let normals = out.read_attribute("Normal").unwrap();
+        assert_near(&normals[0], [0.0, 0.0, 1.0]);
+        assert_near(&normals[1], [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn skin_palette_base() {
+        // index 0 refers to the skeleton node, not to the first bone
+        let mut mesh = make_mesh();
+        mesh.buffers[2] = make_buffer("BoneIndex", &[[0.0; 4], [0.0; 4]], 2);
+        let palette = [translation(0.0, 3.0, 0.0), translation(1.0, 0.0, 0.0)];
+        let out = skin(&mesh, &palette).unwrap();
+        let pos = out.read_attribute("Position").unwrap();
+        assert_near(&pos[0], [1.0, 3.0, 0.0]);
+        assert_near(&pos[1], [0.0, 4.0, 0.0]);
+    }
+
+    #[test]
+    fn skin_without_bones() {
+        let mut mesh = make_mesh();
+        mesh.buffers.truncate(2);
+        assert!(skin(&mesh, &[translation(0.0, 0.0, 0.0)]).is_none());
+    }
+
+    /// Indexed triangle list with the given positions.
+    fn make_geometry(positions: &[[f32; 4]], indices: Vec<u8>) -> MeshData {
+        MeshData {
+            name: "geometry".to_string(),
+            num_vertices: positions.len() as u32,
+            topology: gfx::PrimitiveType::TriangleList,
+            buffers: vec![make_buffer("Position", positions, 3)],
+            instances: Vec::new(),
+            indices: Some(Indices::U8(indices)),
+        }
+    }
+
+    /// Unit quad in the XY plane, facing +Z.
+    fn make_quad() -> MeshData {
+        make_geometry(&[
+            [0.0, 0.0, 0.0, 0.0], [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0], [1.0, 1.0, 0.0, 0.0],
+        ], vec![0, 1, 2, 2, 1, 3])
+    }
+
+    /// Two triangles sharing the edge along X, folded by 90 degrees.
+    fn make_roof() -> MeshData {
+        make_geometry(&[
+            [0.0, 0.0, 0.0, 0.0], [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 1.0, 0.0], [0.0, -1.0, 1.0, 0.0],
+        ], vec![0, 1, 2, 1, 0, 3])
+    }
+
+    #[test]
+    fn normals_quad() {
+        let mut mesh = make_quad();
+        assert!(generate_normals(&mut mesh, Normals::Smooth));
+        let normals = mesh.read_attribute("Normal").unwrap();
+        assert_eq!(normals.len(), 4);
+        for n in normals.iter() {
+            assert_near(n, [0.0, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn normals_smooth() {
+        let mut mesh = make_roof();
+        assert!(generate_normals(&mut mesh, Normals::Smooth));
+        assert_eq!(mesh.num_vertices, 4);
+        let normals = mesh.read_attribute("Normal").unwrap();
+        let h = (0.5f32).sqrt();
+        // the shared edge gets the average of both faces
+        assert_near(&normals[0], [0.0, 0.0, 1.0]);
+        assert_near(&normals[1], [0.0, 0.0, 1.0]);
+        assert_near(&normals[2], [0.0, -h, h]);
+        assert_near(&normals[3], [0.0, h, h]);
+    }
+
+    #[test]
+    fn normals_flat() {
+        let mut mesh = make_roof();
+        assert!(generate_normals(&mut mesh, Normals::Flat));
+        // each corner gets its own vertex
+        assert_eq!(mesh.num_vertices, 6);
+        assert!(mesh.indices.is_none());
+        let positions = mesh.read_attribute("Position").unwrap();
+        assert_near(&positions[3], [1.0, 0.0, 0.0]);
+        assert_near(&positions[5], [0.0, -1.0, 1.0]);
+        let normals = mesh.read_attribute("Normal").unwrap();
+        let h = (0.5f32).sqrt();
+        for n in normals[..3].iter() {
+            assert_near(n, [0.0, -h, h]);
+        }
+        for n in normals[3..].iter() {
+            assert_near(n, [0.0, h, h]);
+        }
+    }
+
+    #[test]
+    fn normals_flat_strip() {
+        // strips can't be split, so the normals are smooth
+        let mut mesh = make_geometry(&[
+            [0.0, 0.0, 0.0, 0.0], [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0], [1.0, 1.0, 0.0, 0.0],
+        ], vec![0, 1, 2, 3]);
+        mesh.topology = gfx::PrimitiveType::TriangleStrip;
+        assert!(generate_normals(&mut mesh, Normals::Flat));
+        assert_eq!(mesh.num_vertices, 4);
+        for n in mesh.read_attribute("Normal").unwrap().iter() {
+            assert_near(n, [0.0, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn normals_without_triangles() {
+        let mut mesh = make_quad();
+        mesh.topology = gfx::PrimitiveType::Line;
+        assert!(!generate_normals(&mut mesh, Normals::Smooth));
+        assert!(mesh.find_attribute("Normal").is_none());
+    }
+
+    #[test]
+    fn tangents_quad() {
+        let mut mesh = make_quad();
+        let uv = mesh.read_attribute("Position").unwrap();
+        mesh.buffers.push(make_buffer("Tex0", &uv, 2));
+        complete(&mut mesh, Normals::Smooth);
+        let tangents = mesh.read_attribute("Tangent").unwrap();
+        for t in tangents.iter() {
+            assert_near(t, [1.0, 0.0, 0.0]);
+            assert_eq!(t[3], 1.0);
+        }
+    }
+
+    #[test]
+    fn tangents_mirrored() {
+        let mut mesh = make_quad();
+        let uv: Vec<_> = mesh.read_attribute("Position").unwrap().iter()
+                             .map(|p| [1.0 - p[0], p[1], 0.0, 0.0]).collect();
+        mesh.buffers.push(make_buffer("Tex0", &uv, 2));
+        complete(&mut mesh, Normals::Smooth);
+        // U goes along -X, so the bitangent is flipped relative to N x T
+        for t in mesh.read_attribute("Tangent").unwrap().iter() {
+            assert_near(t, [-1.0, 0.0, 0.0]);
+            assert_eq!(t[3], -1.0);
+        }
+    }
+
+    #[test]
+    fn tangents_orthogonal() {
+        // skewed texture mapping, the tangent still has to be orthogonal
+        let mut mesh = make_quad();
+        mesh.buffers.push(make_buffer("Normal", &[[0.0, 0.0, 1.0, 0.0]; 4], 3));
+        let uv: Vec<_> = mesh.read_attribute("Position").unwrap().iter()
+                             .map(|p| [p[0] + 0.5 * p[1], p[1], 0.0, 0.0]).collect();
+        mesh.buffers.push(make_buffer("Tex0", &uv, 2));
+        assert!(generate_tangents(&mut mesh, "Tex0"));
+        for t in mesh.read_attribute("Tangent").unwrap().iter() {
+            assert!(t[2].abs() < 1e-5);
+            assert!((t[0] * t[0] + t[1] * t[1] - 1.0).abs() < 1e-5);
+        }
+    }
+
+    /// Triangle list of a grid with `n` by `n` quads, row by row.
+    fn make_grid(n: u32) -> Vec<u32> {
+        let mut indices = Vec::new();
+        for y in 0.. n {
+            for x in 0.. n {
+                let i = y * (n + 1) + x;
+                indices.extend([i, i + 1, i + n + 1, i + n + 1, i + 1, i + n + 2].iter().cloned());
+            }
+        }
+        indices
+    }
+
+    /// Sorted triangles, each rotated to start with the smallest index,
+    /// so that the winding is preserved by the comparison.
+    fn sort_triangles(indices: &[u32]) -> Vec<[u32; 3]> {
+        let mut list: Vec<[u32; 3]> = indices.chunks(3).map(|t| {
+            let k = (0.. 3).fold(0, |b, k| if t[k] < t[b] {k} else {b});
+            [t[k], t[(k + 1) % 3], t[(k + 2) % 3]]
+        }).collect();
+        list.sort();
+        list
+    }
+
+    #[test]
+    fn optimize_permutation() {
+        let indices = make_grid(20);
+        let optimized = optimize_triangles(&indices, 21 * 21);
+        assert_eq!(optimized.len(), indices.len());
+        assert_eq!(sort_triangles(&optimized), sort_triangles(&indices));
+    }
+
+    #[test]
+    fn optimize_acmr_grid() {
+        let indices = make_grid(40);
+        let before = compute_acmr(&indices, CACHE_SIZE);
+        let after = compute_acmr(&optimize_triangles(&indices, 41 * 41), CACHE_SIZE);
+        assert!(after <= before, "ACMR went from {} to {}", before, after);
+    }
+
+    #[test]
+    fn optimize_acmr_shuffled() {
+        let grid = make_grid(40);
+        let mut triangles: Vec<&[u32]> = grid.chunks(3).collect();
+        // deterministic shuffle
+        let mut seed = 12345u32;
+        for i in (1.. triangles.len()).rev() {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            triangles.swap(i, (seed >> 16) as usize % (i + 1));
+        }
+        let indices: Vec<u32> = triangles.iter().flat_map(|t| t.iter().cloned()).collect();
+        let before = compute_acmr(&indices, CACHE_SIZE);
+        let optimized = optimize_triangles(&indices, 41 * 41);
+        let after = compute_acmr(&optimized, CACHE_SIZE);
+        assert_eq!(sort_triangles(&optimized), sort_triangles(&indices));
+        assert!(after < before, "ACMR went from {} to {}", before, after);
+    }
+
+    #[test]
+    fn optimize_empty() {
+        assert!(optimize_triangles(&[], 0).is_empty());
+    }
+}
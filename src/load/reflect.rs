@@ -3,7 +3,7 @@ use std::collections::HashMap;
 pub type Scalar = f32;
 pub type Vector3 = (Scalar, Scalar, Scalar);
 
-#[derive(RustcDecodable)]
+#[derive(Clone, RustcDecodable, RustcEncodable)]
 pub struct Scene {
     pub global: Global,
     pub nodes: Vec<Node>,
@@ -11,14 +11,18 @@ pub struct Scene {
     pub entities: Vec<Entity>,
     pub cameras: Vec<Camera>,
     pub lights: Vec<Light>,
+    pub armatures: Option<Vec<Armature>>,
+    /// Directory of the meshes and textures, relative to the base path.
+    /// Defaults to the scene path without the extension.
+    pub assets: Option<String>,
 }
 
-#[derive(RustcDecodable)]
+#[derive(Clone, RustcDecodable, RustcEncodable)]
 pub struct Global {
     pub gravity: (Scalar, Scalar, Scalar),
 }
 
-#[derive(RustcDecodable)]
+#[derive(Clone, RustcDecodable, RustcEncodable)]
 pub struct Node {
     pub name: String,
     pub space: Space<Scalar>,
@@ -26,30 +30,58 @@ pub struct Node {
     pub actions: Vec<Action>,
 }
 
-#[derive(RustcDecodable)]
+#[derive(Clone, RustcDecodable, RustcEncodable)]
 pub struct Space<S> {
     pub pos: (S, S, S),
     pub rot: (S, S, S, S),
     pub scale: S,
 }
 
-#[derive(RustcDecodable)]
+#[derive(Clone, RustcDecodable, RustcEncodable)]
 pub struct Entity {
     pub node: String,
     pub mesh: String,
     pub armature: String,
-    pub bounds: (Vector3, Vector3),
+    pub bounds: Option<(Vector3, Vector3)>,
     pub fragments: Vec<Fragment>,
+    pub lods: Option<Vec<Lod>>,
     pub actions: Vec<Action>,
 }
 
-#[derive(RustcDecodable)]
+/// Coarser level of detail. Uses the entity mesh if no other is given,
+/// and exactly one of the thresholds.
+#[derive(Clone, RustcDecodable, RustcEncodable)]
+pub struct Lod {
+    pub mesh: Option<String>,
+    pub distance: Option<Scalar>,
+    pub screen_size: Option<Scalar>,
+    pub fragments: Vec<Fragment>,
+}
+
+#[derive(Clone, RustcDecodable, RustcEncodable)]
 pub struct Fragment {
     pub material: String,
     pub slice: (u32, u32),
 }
 
-#[derive(RustcDecodable)]
+#[derive(Clone, RustcDecodable, RustcEncodable)]
+pub struct Armature {
+    pub name: String,
+    pub node: String,
+    pub dual_quat: bool,
+    pub bones: Vec<Bone>,
+    pub actions: Vec<Action>,
+}
+
+/// Bone in the rest position, relative to the parent bone.
+#[derive(Clone, RustcDecodable, RustcEncodable)]
+pub struct Bone {
+    pub name: String,
+    pub space: Space<Scalar>,
+    pub children: Vec<Bone>,
+}
+
+#[derive(Clone, RustcDecodable, RustcEncodable)]
 pub struct Light {
     pub name: String,
     pub node: String,
@@ -63,16 +95,22 @@ pub struct Light {
     pub actions: Vec<Action>,
 }
 
-#[derive(RustcDecodable)]
+#[derive(Clone, RustcDecodable, RustcEncodable)]
 pub struct Camera {
     pub name: String,
     pub node: String,
+    /// Blender camera type: "PERSP" or "ORTHO".
+    pub kind: Option<String>,
     pub angle: (Scalar, Scalar),
     pub range: (Scalar, Scalar),
+    /// Larger dimension of the orthographic view.
+    pub ortho_scale: Option<Scalar>,
+    /// Lens shift, relative to the larger dimension of the view.
+    pub shift: Option<(Scalar, Scalar)>,
     pub actions: Vec<Action>,
 }
 
-#[derive(RustcDecodable)]
+#[derive(Clone, RustcDecodable, RustcEncodable)]
 pub struct Material {
     pub name: String,
     pub shader: String,
@@ -83,7 +121,7 @@ pub struct Material {
 
 pub type Data = (String, Vec<f32>);
 
-#[derive(RustcDecodable)]
+#[derive(Clone, RustcDecodable, RustcEncodable)]
 pub struct Texture {
     pub name: String,
     pub image: Image,
@@ -93,11 +131,12 @@ pub struct Texture {
     pub scale: Vector3,
 }
 
-#[derive(RustcDecodable)]
+#[derive(Clone, RustcDecodable, RustcEncodable)]
 pub struct Image {
     pub path: String,
     pub space: String,
     pub mapping: String,
 }
 
-pub type Action = ();   //TODO
+/// Action reference in the "name@container" form.
+pub type Action = String;
@@ -5,73 +5,296 @@ use super::reflect as json;
 
 pub type Scalar = f32;
 
+/// Allowed mismatch between the exported and computed bounds,
+/// relative to the size of the computed box.
+static BOUND_TOLERANCE: Scalar = 0.01;
+
 #[derive(Debug)]
 pub enum Error {
-    NoCamera,
     MissingNode(String),
+    MissingArmature(String),
     Mesh(String, super::mesh::Error),
     Material(String, super::mat::Error),
+    Action(String, super::action::Error),
+    /// Level of detail of the entity on a given node
+    /// has no threshold, or more than one.
+    LodThreshold(String),
+    /// Light has an unknown kind, or lacks its parameters.
+    LightKind(String),
+    /// Bound of the entity on a given node is neither exported,
+    /// nor can be computed.
+    NoBound(String),
+    /// Saved entities come from different asset directories,
+    /// while the description refers to a single one.
+    MixedAssets(String, String),
+}
+
+fn read_bound(raw: &Option<(json::Vector3, json::Vector3)>)
+              -> Option<cgmath::Aabb3<Scalar>> {
+    match *raw {
+        Some(((x0, y0, z0), (x1, y1, z1))) => {
+            let all = [x0, y0, z0, x1, y1, z1];
+            if all.iter().any(|v| !v.is_finite()) || x0 > x1 || y0 > y1 || z0 > z1 {
+                None
+            } else {
+                Some(cgmath::Aabb3::new(
+                    cgmath::Point3::new(x0, y0, z0),
+                    cgmath::Point3::new(x1, y1, z1),
+                ))
+            }
+        },
+        None => None,
+    }
+}
+
+fn bounds_agree(exported: &cgmath::Aabb3<Scalar>, computed: &cgmath::Aabb3<Scalar>) -> bool {
+    use cgmath::{EuclideanVector, Point};
+    let size = computed.max.sub_p(&computed.min).length();
+    let tolerance = BOUND_TOLERANCE * size;
+    exported.min.sub_p(&computed.min).length() <= tolerance &&
+    exported.max.sub_p(&computed.max).length() <= tolerance
+}
+
+fn read_projection(cam: &json::Camera) -> cs::Projection<Scalar> {
+    let (fovx, fovy) = cam.angle;
+    let (near, far) = cam.range;
+    let aspect = fovx.tan() / fovy.tan();
+    let (shift_x, shift_y) = cam.shift.unwrap_or((0.0, 0.0));
+    match (cam.kind.as_ref().map(|k| &k[..]), cam.ortho_scale) {
+        (Some("ORTHO"), Some(scale)) => {
+            let (right, top) = if aspect >= 1.0 {
+                (scale * 0.5, scale * 0.5 / aspect)
+            } else {
+                (scale * 0.5 * aspect, scale * 0.5)
+            };
+            let (dx, dy) = (shift_x * scale, shift_y * scale);
+            return cs::Projection::Ortho(cgmath::Ortho {
+                left: dx - right, right: dx + right,
+                bottom: dy - top, top: dy + top,
+                near: near, far: far,
+            })
+        },
+        (None, _) | (Some("PERSP"), _) => (),
+        (Some("ORTHO"), None) =>
+            warn!("Camera {} lacks the orthographic scale, using a perspective", cam.name),
+        (Some(other), _) =>
+            warn!("Camera {} has unknown kind {}, using a perspective", cam.name, other),
+    }
+    if shift_x == 0.0 && shift_y == 0.0 {
+        cs::Projection::Perspective(cgmath::PerspectiveFov {
+            fovy: cgmath::rad(fovy),
+            aspect: aspect,
+            near: near,
+            far: far,
+        })
+    } else {
+        // the shift is relative to the larger dimension of the view
+        let top = near * (fovy * 0.5).tan();
+        let right = top * aspect;
+        let size = 2.0 * top.max(right);
+        let (dx, dy) = (shift_x * size, shift_y * size);
+        cs::Projection::Frustum(cgmath::Perspective {
+            left: dx - right, right: dx + right,
+            bottom: dy - top, top: dy + top,
+            near: near, far: far,
+        })
+    }
+}
+
+fn read_space<S: cgmath::BaseFloat>(space: &json::Space<S>)
+              -> cs::Transform<S> {
+    cgmath::Decomposed {
+        scale: space.scale,
+        rot: {
+            let (x, y, z, w) = space.rot;
+            cgmath::Quaternion::new(w, x, y, z).normalize()
+        },
+        disp: {
+            let (x, y, z) = space.pos;
+            cgmath::Vector3::new(x, y, z)
+        },
+    }
+}
+
+fn populate_world(world: &mut cs::World<Scalar>,
+                  raw_nodes: &[json::Node],
+                  parent: cs::space::Parent<cs::Transform<Scalar>>) {
+    for n in raw_nodes.iter() {
+        let space = read_space(&n.space);
+        let nid = world.add_node(n.name.clone(), parent, space);
+        populate_world(world, &n.children, cs::space::Parent::Domestic(nid));
+    }
+}
+
+fn populate_skeleton(world: &mut cs::World<Scalar>,
+                     skeleton: cs::SkeletonId<Scalar>,
+                     raw_bones: &[json::Bone],
+                     parent: Option<cs::BoneId<Scalar>>) {
+    for b in raw_bones.iter() {
+        let space = read_space(&b.space);
+        let bid = world.add_bone(skeleton, b.name.clone(), parent, space);
+        populate_skeleton(world, skeleton, &b.children, Some(bid));
+    }
+}
+
+fn read_light_kind(light: &json::Light) -> Result<cs::LightKind<Scalar>, Error> {
+    let par = &light.parameters;
+    match (&light.kind[..], par.len()) {
+        ("POINT", _) => Ok(cs::LightKind::Point),
+        ("SUN", _) => Ok(cs::LightKind::Directional),
+        ("HEMI", _) => Ok(cs::LightKind::Hemisphere),
+        ("SPOT", n) if n >= 2 => Ok(cs::LightKind::Spot(cgmath::rad(par[0]), par[1])),
+        ("AREA", n) if n >= 2 => Ok(cs::LightKind::Area(par[0], par[1])),
+        _ => Err(Error::LightKind(light.kind.clone())),
+    }
 }
 
 pub fn load_into<'a, R: 'a + gfx::Resources, F: 'a + gfx::Factory<R>>(
                  this: &mut cs::Scene<R, Scalar>, global_parent: cs::Parent<Scalar>,
-                 raw: json::Scene, context: &mut super::Context<'a, R, F>)
+                 raw: json::Scene, path_str: &str,
+                 context: &mut super::Context<'a, R, F>)
                  -> Result<(), Error>
 {
     use std::collections::hash_map::{HashMap, Entry};
 
-    fn read_space<S: cgmath::BaseFloat>(space: &json::Space<S>)
-                  -> cs::Transform<S> {
-        cgmath::Decomposed {
-            scale: space.scale,
-            rot: {
-                let (x, y, z, w) = space.rot;
-                cgmath::Quaternion::new(w, x, y, z).normalize()
-            },
-            disp: {
-                let (x, y, z) = space.pos;
-                cgmath::Vector3::new(x, y, z)
-            },
+    fn load_actions<'a, R: 'a + gfx::Resources, F: 'a + gfx::Factory<R>>(
+                    this: &mut cs::Scene<R, Scalar>, names: &[json::Action],
+                    node: cs::NodeId<Scalar>, skeleton: Option<cs::SkeletonId<Scalar>>,
+                    context: &mut super::Context<'a, R, F>)
+                    -> Result<(), Error> {
+        for name in names.iter() {
+            match context.request_action(name) {
+                Ok(clip) => this.animations.push(cs::anim::Animation {
+                    clip: clip,
+                    node: node,
+                    skeleton: skeleton,
+                    source: Some(name.clone()),
+                }),
+                // the actions are listed even if they were not exported
+                Err(super::action::Error::Path(e)) =>
+                    warn!("Skipping action {}: {:?}", name, e),
+                Err(e) => if context.forgive {
+                    error!("Unable to load action {}: {:?}", name, e);
+                } else {
+                    return Err(Error::Action(name.clone(), e))
+                },
+            }
         }
+        Ok(())
     }
 
-    fn populate_world(world: &mut cs::World<Scalar>,
-                      raw_nodes: &[json::Node],
-                      parent: cs::space::Parent<cs::Transform<Scalar>>) {
+    fn load_node_actions<'a, R: 'a + gfx::Resources, F: 'a + gfx::Factory<R>>(
+                         this: &mut cs::Scene<R, Scalar>, raw_nodes: &[json::Node],
+                         context: &mut super::Context<'a, R, F>)
+                         -> Result<(), Error> {
         for n in raw_nodes.iter() {
-            let space = read_space(&n.space);
-            let nid = world.add_node(n.name.clone(), parent, space);
-            populate_world(world, &n.children, cs::space::Parent::Domestic(nid));
+            if !n.actions.is_empty() {
+                let node = this.world.find_node(&n.name).unwrap();
+                try!(load_actions(this, &n.actions, node, None, context));
+            }
+            try!(load_node_actions(this, &n.children, context));
         }
+        Ok(())
     }
 
+    fn get_material<'a, R: 'a + gfx::Resources, F: 'a + gfx::Factory<R>>(
+                    name: &str, map: &mut HashMap<String, cs::Material<R>>,
+                    params: &mut HashMap<(String, String), cs::param::Parameters<R>>,
+                    raw_materials: &[json::Material], assets: &str,
+                    context: &mut super::Context<'a, R, F>)
+                    -> Result<cs::Material<R>, Error> {
+        match map.entry(name.to_string()) {
+            Entry::Occupied(m) => Ok(m.get().clone()),
+            Entry::Vacant(v) => match raw_materials.iter().find(|r| r.name == name) {
+                Some(raw_mat) => match super::mat::load(&raw_mat, context) {
+                    Ok((m, p)) => {
+                        // remember the description for saving
+                        context.cache.materials.insert(
+                            (assets.to_string(), name.to_string()), raw_mat.clone());
+                        params.insert((assets.to_string(), name.to_string()), p);
+                        Ok(v.insert(m).clone())
+                    },
+                    Err(e) => Err(Error::Material(name.to_string(), e)),
+                },
+                None => Err(Error::Material(
+                    name.to_string(), super::mat::Error::NotFound)),
+            },
+        }
+    }
+
+    // the assets are found next to the scene, unless specified
+    let assets = match raw.assets {
+        Some(ref dir) => {
+            context.prefix = format!("{}/{}", context.base_path, dir);
+            dir.clone()
+        },
+        None => path_str.to_string(),
+    };
+    let make_source = |mesh: &str, fragments: &[json::Fragment]| cs::Source {
+        assets: assets.clone(),
+        mesh: mesh.to_string(),
+        materials: fragments.iter().map(|f| f.material.clone()).collect(),
+    };
+
+    let (gx, gy, gz) = raw.global.gravity;
+    this.gravity = Some(cgmath::Vector3::new(gx, gy, gz));
     // create world
     populate_world(&mut this.world, &raw.nodes, global_parent);
-    // read camera
-    let camera = {
-        let cam = match raw.cameras.first() {
-            Some(c) => c,
-            None => return Err(Error::NoCamera),
+    // create skeletons
+    for arm in raw.armatures.as_ref().map_or(&[][..], |a| &a[..]).iter() {
+        let node = match this.world.find_node(&arm.node) {
+            Some(n) => n,
+            None => return Err(Error::MissingNode(arm.node.clone())),
         };
+        let sid = this.world.add_skeleton(arm.name.clone(), node);
+        populate_skeleton(&mut this.world, sid, &arm.bones, None);
+        try!(load_actions(this, &arm.actions, node, Some(sid), context));
+    }
+    try!(load_node_actions(this, &raw.nodes, context));
+    // read cameras
+    for cam in raw.cameras.iter() {
         let node = match this.world.find_node(&cam.node) {
             Some(n) => n,
             None => return Err(Error::MissingNode(cam.node.to_string())),
         };
-        let (fovx, fovy) = cam.angle;
-        let (near, far) = cam.range;
-        let proj = cgmath::PerspectiveFov {
-            fovy: cgmath::rad(fovy),
-            aspect: fovx.tan() / fovy.tan(),
-            near: near,
-            far: far,
-        };
-        cs::Camera {
+        let proj = read_projection(cam);
+        this.cameras.push(cs::Camera {
             name: cam.name.clone(),
             node: node,
             projection: proj,
+        });
+    }
+    // read lights
+    for light in raw.lights.iter() {
+        let node = match this.world.find_node(&light.node) {
+            Some(n) => n,
+            None => return Err(Error::MissingNode(light.node.clone())),
+        };
+        let kind = try!(read_light_kind(light));
+        let (r, g, b) = light.color;
+        this.lights.push(cs::Light {
+            name: light.name.clone(),
+            node: node,
+            kind: kind,
+            color: [r, g, b],
+            energy: light.energy,
+            distance: light.distance,
+            attenuation: light.attenuation,
+            spherical: light.spherical,
+        });
+    }
+    // node animations of cameras and lights
+    for (node_name, actions) in raw.cameras.iter().map(|c| (&c.node, &c.actions))
+                                   .chain(raw.lights.iter().map(|l| (&l.node, &l.actions))) {
+        if actions.is_empty() {
+            continue
         }
-    };
-    this.cameras.push(camera);
+        let node = match this.world.find_node(node_name) {
+            Some(n) => n,
+            None => return Err(Error::MissingNode(node_name.clone())),
+        };
+        try!(load_actions(this, actions, node, None, context));
+    }
 
     // read entities and materials
     let mut material_map: HashMap<String, cs::Material<R>> = HashMap::new();
@@ -80,41 +303,669 @@ pub fn load_into<'a, R: 'a + gfx::Resources, F: 'a + gfx::Factory<R>>(
             Some(n) => n,
             None => return Err(Error::MissingNode(ent.node.clone())),
         };
-        let (mesh, mut slice) = match context.request_mesh(&ent.mesh) {
+        let ranges: Vec<(u32, u32)> = ent.fragments.iter().map(|f| f.slice).collect();
+        let ((mesh, mut slice), frag_bounds) = match context.request_mesh_sliced(&ent.mesh, &ranges) {
             Ok(success) => success,
             Err(e) => return Err(Error::Mesh(ent.mesh.clone(), e)),
         };
-        let (vmin, vmax) = ent.bounds;
+        let exported = read_bound(&ent.bounds);
+        if ent.bounds.is_some() && exported.is_none() {
+            warn!("Entity {} has a broken bound {:?}", ent.node, ent.bounds);
+        }
+        let bound = if exported.is_none() || context.bound_mode != super::BoundMode::Trust {
+            let mut computed: Option<cgmath::Aabb3<Scalar>> = None;
+            let full = [(slice.start as u32, slice.end as u32)];
+            let bound_ranges = if ranges.is_empty() {&full[..]} else {&ranges[..]};
+            for (&(start, end), fb) in bound_ranges.iter().zip(frag_bounds.iter()) {
+                use cgmath::Aabb;
+                let frag_bound = match *fb {
+                    Some(ref b) => b.clone(),
+                    None => {
+                        warn!("Unable to compute the bound of {} slice {:?}",
+                            ent.mesh, (start, end));
+                        continue
+                    },
+                };
+                if let (Some(b), super::BoundMode::Check) = (exported.as_ref(), context.bound_mode) {
+                    if !b.contains(&frag_bound.min) || !b.contains(&frag_bound.max) {
+                        warn!("Entity {} slice {:?} exceeds the exported bound",
+                            ent.node, (start, end));
+                    }
+                }
+                computed = Some(match computed {
+                    Some(b) => b.grow(&frag_bound.min).grow(&frag_bound.max),
+                    None => frag_bound,
+                });
+            }
+            match (exported, computed, context.bound_mode) {
+                (Some(e), Some(c), super::BoundMode::Check) => {
+                    if !bounds_agree(&e, &c) {
+                        warn!("Entity {} exported bound {:?} disagrees with computed {:?}",
+                            ent.node, e, c);
+                    }
+                    e
+                },
+                (_, Some(c), _) => c,
+                (Some(e), None, _) => e,
+                (None, None, _) => return Err(Error::NoBound(ent.node.clone())),
+            }
+        } else {
+            exported.unwrap()
+        };
+        let skeleton = if ent.armature.is_empty() {
+            None
+        } else {
+            match this.world.find_skeleton(&ent.armature) {
+                Some(sid) => Some(sid),
+                None => return Err(Error::MissingArmature(ent.armature.clone())),
+            }
+        };
         let mut entity = cs::Entity {
             name: ent.node.clone(),
             visible: true,
             mesh: mesh,
             node: node,
-            skeleton: None, //TODO
-            bound: cgmath::Aabb3::new(
-                cgmath::Point3::new(vmin.0, vmin.1, vmin.2),
-                cgmath::Point3::new(vmax.0, vmax.1, vmax.2),
-            ),
+            skeleton: skeleton,
+            bound: bound,
             fragments: Vec::new(),
+            lods: Vec::new(),
+            source: Some(make_source(&ent.mesh, &ent.fragments)),
         };
-        for frag in ent.fragments.into_iter() {
+        try!(load_actions(this, &ent.actions, node, None, context));
+        for frag in ent.fragments.iter() {
             slice.start = frag.slice.0 as gfx::VertexCount;
             slice.end   = frag.slice.1 as gfx::VertexCount;
-            let material = match material_map.entry(frag.material.clone()) {
-                Entry::Occupied(m) => m.get().clone(),
-                Entry::Vacant(v) => match raw.materials.iter().find(|r| r.name == frag.material) {
-                    Some(raw_mat) => match super::mat::load(&raw_mat, context) {
-                        Ok(m) => v.insert(m).clone(),
-                        Err(e) => return Err(Error::Material(frag.material, e)),
-                    },
-                    None => return Err(Error::Material(
-                        frag.material, super::mat::Error::NotFound)),
+            let material = try!(get_material(&frag.material, &mut material_map,
+                                             &mut this.materials, &raw.materials,
+                                             &assets, context));
+            entity.add_fragment(material, slice.clone());
+        }
+        for lod in ent.lods.unwrap_or(Vec::new()).into_iter() {
+            let threshold = match (lod.distance, lod.screen_size) {
+                (Some(d), None) => cs::LodThreshold::Distance(d),
+                (None, Some(s)) => cs::LodThreshold::ScreenSize(s),
+                _ => return Err(Error::LodThreshold(ent.node.clone())),
+            };
+            let source = make_source(match lod.mesh {
+                Some(ref path) => path,
+                None => &ent.mesh,
+            }, &lod.fragments);
+            let (mesh, mut lod_slice) = match lod.mesh {
+                Some(ref path) => {
+                    let lod_ranges: Vec<(u32, u32)> =
+                        lod.fragments.iter().map(|f| f.slice).collect();
+                    match context.request_mesh_sliced(path, &lod_ranges) {
+                        Ok((success, _)) => success,
+                        Err(e) => return Err(Error::Mesh(path.clone(), e)),
+                    }
                 },
+                None => (entity.mesh.clone(), slice.clone()),
             };
-            entity.add_fragment(material, slice.clone());
+            let mut fragments = Vec::with_capacity(lod.fragments.len());
+            for frag in lod.fragments.iter() {
+                lod_slice.start = frag.slice.0 as gfx::VertexCount;
+                lod_slice.end   = frag.slice.1 as gfx::VertexCount;
+                let material = try!(get_material(&frag.material, &mut material_map,
+                                                 &mut this.materials, &raw.materials,
+                                                 &assets, context));
+                fragments.push(cs::Fragment {
+                    material: material,
+                    slice: lod_slice.clone(),
+                });
+            }
+            entity.lods.push(cs::Lod {
+                threshold: threshold,
+                mesh: mesh,
+                fragments: fragments,
+                source: Some(source),
+            });
         }
         this.entities.push(entity);
     }
     // done
     Ok(())
 }
+
+fn write_space(t: &cs::Transform<Scalar>) -> json::Space<Scalar> {
+    json::Space {
+        pos: (t.disp.x, t.disp.y, t.disp.z),
+        rot: (t.rot.v.x, t.rot.v.y, t.rot.v.z, t.rot.s),
+        scale: t.scale,
+    }
+}
+
+/// Number the base name until it is neither used nor reserved.
+fn make_unique_name(base: &str, start: usize, used: &::std::collections::HashSet<String>,
+                    reserved: &::std::collections::HashSet<String>) -> String {
+    (start..).map(|i| format!("{}#{}", base, i))
+             .find(|name| !used.contains(name) && !reserved.contains(name))
+             .unwrap()
+}
+
+fn write_fragments<R: gfx::Resources>(fragments: &[cs::Fragment<R>], materials: &[String])
+                   -> Vec<json::Fragment> {
+    fragments.iter().zip(materials.iter()).map(|(frag, name)| json::Fragment {
+        material: name.clone(),
+        slice: (frag.slice.start as u32, frag.slice.end as u32),
+    }).collect()
+}
+
+/// Describe the scene in the JSON schema, so that `load_into` can read
+/// it back. Only entities and clips that know their source are written,
+/// and the instance groups become separate entities. The material
+/// descriptions are taken from the cache of the loading context.
+pub fn save<R: gfx::Resources>(this: &cs::Scene<R, Scalar>, cache: &super::Cache<R>)
+            -> Result<json::Scene, Error>
+{
+    use std::collections::{HashMap, HashSet};
+    type NodePtr = *const cs::Node<Scalar>;
+    // give the nodes unique names, the generated ones
+    // must not take the names of the following nodes
+    let reserved: HashSet<String> = this.world.iter_nodes().map(|n| n.name.clone()).collect();
+    let mut indices: HashMap<NodePtr, usize> = HashMap::new();
+    let mut names = Vec::new();
+    let mut used_names = HashSet::new();
+    let mut raw_nodes: Vec<json::Node> = Vec::new();
+    let mut parents = Vec::new();
+    for (i, node) in this.world.iter_nodes().enumerate() {
+        indices.insert(node as NodePtr, i);
+        let name = if node.name.is_empty() || used_names.contains(&node.name) {
+            make_unique_name(&node.name, i, &used_names, &reserved)
+        } else {
+            node.name.clone()
+        };
+        used_names.insert(name.clone());
+        names.push(name.clone());
+        raw_nodes.push(json::Node {
+            name: name,
+            space: write_space(&node.local),
+            children: Vec::new(),
+            actions: Vec::new(),
+        });
+        parents.push(match node.parent {
+            cs::space::Parent::None => None,
+            cs::space::Parent::Domestic(pid) => {
+                let ptr = this.world.get_node(pid) as NodePtr;
+                indices.get(&ptr).cloned()
+            },
+            cs::space::Parent::Foreign(_, _) => {
+                warn!("Node {} is attached to a bone, saving as a root", node.name);
+                None
+            },
+        });
+    }
+    let node_name = |nid: cs::NodeId<Scalar>| -> String {
+        let ptr = this.world.get_node(nid) as NodePtr;
+        names[indices[&ptr]].clone()
+    };
+    // actions of the nodes, the skeleton ones are written with the armatures
+    for anim in this.animations.iter() {
+        match (&anim.source, anim.skeleton) {
+            (&Some(ref action), None) => {
+                let ptr = this.world.get_node(anim.node) as NodePtr;
+                raw_nodes[indices[&ptr]].actions.push(action.clone());
+            },
+            (&Some(_), Some(_)) => (),
+            (&None, _) => warn!("Skipping clip {} without a source", anim.clip.name),
+        }
+    }
+    // collect entities, all referring to the same assets,
+    // so that the material names are unique
+    let mut assets: Option<String> = None;
+    let mut material_names: Vec<String> = Vec::new();
+    let mut entities = Vec::new();
+    {
+        let mut use_source = |source: &cs::Source| -> Result<(), Error> {
+            match assets {
+                Some(ref a) if *a != source.assets =>
+                    return Err(Error::MixedAssets(a.clone(), source.assets.clone())),
+                Some(_) => (),
+                None => assets = Some(source.assets.clone()),
+            }
+            for m in source.materials.iter() {
+                if !material_names.contains(m) {
+                    material_names.push(m.clone());
+                }
+            }
+            Ok(())
+        };
+        for ent in this.entities.iter() {
+            let source = match ent.source {
+                Some(ref s) => s,
+                None => {
+                    warn!("Skipping entity {} without a source", ent.name);
+                    continue
+                },
+            };
+            try!(use_source(source));
+            let mut lods = Vec::new();
+            for lod in ent.lods.iter() {
+                let lod_source = match lod.source {
+                    Some(ref s) => s,
+                    None => continue,
+                };
+                try!(use_source(lod_source));
+                let (distance, screen_size) = match lod.threshold {
+                    cs::LodThreshold::Distance(d) => (Some(d), None),
+                    cs::LodThreshold::ScreenSize(s) => (None, Some(s)),
+                };
+                lods.push(json::Lod {
+                    mesh: if lod_source.mesh != source.mesh {
+                        Some(lod_source.mesh.clone())
+                    } else {
+                        None
+                    },
+                    distance: distance,
+                    screen_size: screen_size,
+                    fragments: write_fragments(&lod.fragments, &lod_source.materials),
+                });
+            }
+            entities.push(json::Entity {
+                node: node_name(ent.node),
+                mesh: source.mesh.clone(),
+                armature: match ent.skeleton {
+                    Some(sid) => this.world.get_skeleton(sid).name.clone(),
+                    None => String::new(),
+                },
+                bounds: Some((
+                    (ent.bound.min.x, ent.bound.min.y, ent.bound.min.z),
+                    (ent.bound.max.x, ent.bound.max.y, ent.bound.max.z),
+                )),
+                fragments: write_fragments(&ent.fragments, &source.materials),
+                lods: if lods.is_empty() {None} else {Some(lods)},
+                actions: Vec::new(),
+            });
+        }
+        for group in this.instances.iter() {
+            let source = match group.source {
+                Some(ref s) => s,
+                None => {
+                    warn!("Skipping instances {} without a source", group.name);
+                    continue
+                },
+            };
+            try!(use_source(source));
+            let parent = indices[&(this.world.get_node(group.node) as NodePtr)];
+            for (i, t) in group.transforms.iter().enumerate() {
+                let name = make_unique_name(&group.name, i, &used_names, &reserved);
+                used_names.insert(name.clone());
+                parents.push(Some(parent));
+                raw_nodes.push(json::Node {
+                    name: name.clone(),
+                    space: write_space(t),
+                    children: Vec::new(),
+                    actions: Vec::new(),
+                });
+                entities.push(json::Entity {
+                    node: name,
+                    mesh: source.mesh.clone(),
+                    armature: String::new(),
+                    bounds: Some((
+                        (group.bound.min.x, group.bound.min.y, group.bound.min.z),
+                        (group.bound.max.x, group.bound.max.y, group.bound.max.z),
+                    )),
+                    fragments: write_fragments(&group.fragments, &source.materials),
+                    lods: None,
+                    actions: Vec::new(),
+                });
+            }
+        }
+    }
+    // build the hierarchy, children always follow their parents
+    let mut roots = Vec::new();
+    while let Some(node) = raw_nodes.pop() {
+        match parents.pop().unwrap() {
+            Some(p) => raw_nodes[p].children.insert(0, node),
+            None => roots.insert(0, node),
+        }
+    }
+    // armatures, bones always follow their parents
+    let armatures: Vec<_> = this.world.iter_skeletons().map(|skel| {
+        let actions = this.animations.iter().filter_map(|anim| match anim.skeleton {
+            Some(sid) if this.world.get_skeleton(sid) as *const _ == skel as *const _ =>
+                anim.source.clone(),
+            _ => None,
+        }).collect();
+        type BonePtr = *const cs::Bone<Scalar>;
+        let mut bone_indices: HashMap<BonePtr, usize> = HashMap::new();
+        let mut raw_bones = Vec::new();
+        let mut bone_parents = Vec::new();
+        for (i, bone) in skel.iter_bones().enumerate() {
+            bone_indices.insert(bone as BonePtr, i);
+            raw_bones.push(json::Bone {
+                name: bone.name.clone(),
+                space: write_space(bone.get_bind_pose()),
+                children: Vec::new(),
+            });
+            bone_parents.push(bone.get_parent().map(|pid|
+                bone_indices[&(skel.get_bone(pid) as BonePtr)]
+            ));
+        }
+        let mut roots = Vec::new();
+        while let Some(bone) = raw_bones.pop() {
+            match bone_parents.pop().unwrap() {
+                Some(p) => raw_bones[p].children.insert(0, bone),
+                None => roots.insert(0, bone),
+            }
+        }
+        json::Armature {
+            name: skel.name.clone(),
+            node: node_name(skel.get_node()),
+            dual_quat: false,
+            bones: roots,
+            actions: actions,
+        }
+    }).collect();
+    // cameras
+    // inverse of `read_projection`
+    let cameras = this.cameras.iter().map(|cam| {
+        let (kind, fovy, aspect, range, scale, shift) = match cam.projection {
+            cs::Projection::Perspective(ref p) =>
+                ("PERSP", p.fovy.s, p.aspect, (p.near, p.far), None, None),
+            cs::Projection::Frustum(ref p) => {
+                let (right, top) = ((p.right - p.left) * 0.5, (p.top - p.bottom) * 0.5);
+                let size = 2.0 * top.max(right);
+                let shift = ((p.right + p.left) * 0.5 / size, (p.top + p.bottom) * 0.5 / size);
+                ("PERSP", 2.0 * (top / p.near).atan(), right / top,
+                    (p.near, p.far), None, Some(shift))
+            },
+            cs::Projection::Ortho(ref p) => {
+                let (width, height) = (p.right - p.left, p.top - p.bottom);
+                let scale = width.max(height);
+                let shift = ((p.right + p.left) * 0.5 / scale, (p.top + p.bottom) * 0.5 / scale);
+                // the angles only carry the aspect ratio here
+                ("ORTHO", ::std::f32::consts::FRAC_PI_4, width / height,
+                    (p.near, p.far), Some(scale), Some(shift))
+            },
+        };
+        let fovx = (aspect * fovy.tan()).atan();
+        json::Camera {
+            name: cam.name.clone(),
+            node: node_name(cam.node),
+            kind: Some(kind.to_string()),
+            angle: (fovx, fovy),
+            range: range,
+            ortho_scale: scale,
+            shift: shift,
+            actions: Vec::new(),
+        }
+    }).collect();
+    // lights
+    let lights = this.lights.iter().map(|light| {
+        let (kind, parameters) = match light.kind {
+            cs::LightKind::Point => ("POINT", Vec::new()),
+            cs::LightKind::Directional => ("SUN", Vec::new()),
+            cs::LightKind::Hemisphere => ("HEMI", Vec::new()),
+            cs::LightKind::Spot(angle, blend) => ("SPOT", vec![angle.s, blend, 0.1]),
+            cs::LightKind::Area(x, y) => ("AREA", vec![x, y, 0.1]),
+        };
+        json::Light {
+            name: light.name.clone(),
+            node: node_name(light.node),
+            kind: kind.to_string(),
+            color: (light.color[0], light.color[1], light.color[2]),
+            energy: light.energy,
+            distance: light.distance,
+            attenuation: light.attenuation,
+            spherical: light.spherical,
+            parameters: parameters,
+            actions: Vec::new(),
+        }
+    }).collect();
+    // materials
+    let mut materials = Vec::with_capacity(material_names.len());
+    for name in material_names.into_iter() {
+        let key = (assets.clone().unwrap_or(String::new()), name);
+        match cache.materials.get(&key) {
+            Some(m) => materials.push(m.clone()),
+            None => return Err(Error::Material(key.1, super::mat::Error::NotFound)),
+        }
+    }
+    let gravity = this.gravity.unwrap_or(cgmath::Vector3::new(0.0, 0.0, -9.81));
+    Ok(json::Scene {
+        global: json::Global {
+            gravity: (gravity.x, gravity.y, gravity.z),
+        },
+        nodes: roots,
+        materials: materials,
+        entities: entities,
+        cameras: cameras,
+        lights: lights,
+        armatures: if armatures.is_empty() {None} else {Some(armatures)},
+        assets: assets,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use cgmath;
+    use cgmath::{Decomposed, Point3, Quaternion, Vector3};
+    use gfx;
+    use gfx::device::dummy::DummyResources as R;
+    use rustc_serialize::json as serial;
+    use claymore_scene as cs;
+    use super::super::Cache;
+    use super::super::reflect as json;
+    use super::*;
+    use super::{populate_world, read_light_kind, read_projection};
+
+    type Parent = cs::space::Parent<cs::Transform<Scalar>>;
+
+    fn translation(x: Scalar, y: Scalar, z: Scalar) -> cs::Transform<Scalar> {
+        Decomposed {
+            scale: 1.0,
+            rot: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            disp: Vector3::new(x, y, z),
+        }
+    }
+
+    fn make_entity(node: cs::NodeId<Scalar>, assets: &str, mesh: &str, material: &str)
+                   -> cs::Entity<R, Scalar> {
+        let bound = cgmath::Aabb3::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+        let mut entity = cs::Entity::new(gfx::Mesh::new(3), node, bound);
+        entity.add_fragment(cs::Material {
+            color: [1.0, 1.0, 1.0, 1.0],
+            texture: None,
+            transparency: cs::Transparency::Opaque,
+        }, gfx::Slice {
+            start: 0,
+            end: 3,
+            prim_type: gfx::PrimitiveType::TriangleList,
+            kind: gfx::SliceKind::Vertex,
+        });
+        entity.source = Some(cs::Source {
+            assets: assets.to_string(),
+            mesh: mesh.to_string(),
+            materials: vec![material.to_string()],
+        });
+        entity
+    }
+
+    fn make_material(name: &str) -> json::Material {
+        json::Material {
+            name: name.to_string(),
+            shader: "phong".to_string(),
+            transparent: false,
+            data: HashMap::new(),
+            textures: Vec::new(),
+        }
+    }
+
+    /// Scene with the duplicate, generated-looking and empty node names,
+    /// entities sharing a material, a camera, a light and a node clip.
+    fn make_scene() -> (cs::Scene<R, Scalar>, Cache<R>) {
+        let mut scene = cs::Scene::new();
+        scene.gravity = Some(Vector3::new(0.0, 1.0, -5.0));
+        let root = scene.world.add_node("root".to_string(), Parent::None,
+                                        translation(1.0, 2.0, 3.0));
+        let a = scene.world.add_node("a".to_string(), Parent::Domestic(root),
+                                     translation(0.0, 0.0, 1.0));
+        let dup = scene.world.add_node("a".to_string(), Parent::Domestic(root),
+                                       translation(0.0, 0.0, 2.0));
+        let taken = scene.world.add_node("a#2".to_string(), Parent::None,
+                                         translation(0.0, 0.0, 3.0));
+        let unnamed = scene.world.add_node(String::new(), Parent::Domestic(a),
+                                           translation(0.0, 0.0, 4.0));
+        scene.entities.push(make_entity(a, "assets", "cube@meshes", "red"));
+        scene.entities.push(make_entity(dup, "assets", "cube@meshes", "red"));
+        scene.entities.push(make_entity(unnamed, "assets", "sphere@meshes", "blue"));
+        scene.cameras.push(cs::Camera {
+            name: "cam".to_string(),
+            node: root,
+            projection: cs::Projection::Perspective(cgmath::PerspectiveFov {
+                fovy: cgmath::rad(1.0),
+                aspect: 1.5,
+                near: 0.1,
+                far: 100.0,
+            }),
+        });
+        scene.lights.push(cs::Light {
+            name: "spot".to_string(),
+            node: taken,
+            kind: cs::LightKind::Spot(cgmath::rad(0.5), 0.2),
+            color: [1.0, 0.5, 0.0],
+            energy: 2.0,
+            distance: 10.0,
+            attenuation: (0.5, 0.25),
+            spherical: true,
+        });
+        for source in [Some("walk@actions".to_string()), None].iter() {
+            scene.animations.push(cs::anim::Animation {
+                clip: cs::anim::AnimationClip {
+                    name: "walk".to_string(),
+                    duration: 1.0,
+                    channels: Vec::new(),
+                },
+                node: a,
+                skeleton: None,
+                source: source.clone(),
+            });
+        }
+        let mut cache = Cache::new();
+        for &(assets, name) in [("assets", "red"), ("assets", "blue"), ("other", "red")].iter() {
+            cache.materials.insert((assets.to_string(), name.to_string()), make_material(name));
+        }
+        (scene, cache)
+    }
+
+    fn assert_near(a: Scalar, b: Scalar) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn round_trip() {
+        let (scene, cache) = make_scene();
+        let text = serial::encode(&save(&scene, &cache).unwrap()).unwrap();
+        let raw: json::Scene = serial::decode(&text).unwrap();
+        assert_eq!(raw.global.gravity, (0.0, 1.0, -5.0));
+        assert_eq!(raw.assets, Some("assets".to_string()));
+        // nodes keep the hierarchy and get unique names
+        let mut world = cs::World::new();
+        populate_world(&mut world, &raw.nodes, Parent::None);
+        let names: Vec<_> = world.iter_nodes().map(|n| &n.name[..]).collect();
+        assert_eq!(names, vec!["root", "a", "#4", "a#3", "a#2"]);
+        for &(name, z) in [("a", 1.0), ("a#3", 2.0), ("a#2", 3.0), ("#4", 4.0)].iter() {
+            let node = world.get_node(world.find_node(name).unwrap());
+            assert_near(node.local.disp.z, z);
+        }
+        let root = &raw.nodes[0];
+        assert_eq!(root.children.iter().map(|n| &n.name[..]).collect::<Vec<_>>(),
+                   vec!["a", "a#3"]);
+        assert_eq!(root.children[0].children[0].name, "#4");
+        assert_eq!(root.children[0].actions, vec!["walk@actions".to_string()]);
+        // entities and their materials
+        let entities: Vec<_> = raw.entities.iter().map(|e|
+            (&e.node[..], &e.mesh[..], &e.fragments[0].material[..], e.fragments[0].slice)
+        ).collect();
+        assert_eq!(entities, vec![
+            ("a", "cube@meshes", "red", (0, 3)),
+            ("a#3", "cube@meshes", "red", (0, 3)),
+            ("#4", "sphere@meshes", "blue", (0, 3)),
+        ]);
+        assert_eq!(raw.entities[0].bounds, Some(((-1.0, -1.0, -1.0), (1.0, 1.0, 1.0))));
+        let materials: Vec<_> = raw.materials.iter().map(|m| &m.name[..]).collect();
+        assert_eq!(materials, vec!["red", "blue"]);
+        // camera
+        assert_eq!(raw.cameras.len(), 1);
+        assert_eq!(raw.cameras[0].node, "root");
+        match read_projection(&raw.cameras[0]) {
+            cs::Projection::Perspective(p) => {
+                assert_near(p.fovy.s, 1.0);
+                assert_near(p.aspect, 1.5);
+                assert_near(p.near, 0.1);
+                assert_near(p.far, 100.0);
+            },
+            _ => panic!("the camera is not a perspective one"),
+        }
+        // light
+        assert_eq!(raw.lights.len(), 1);
+        let light = &raw.lights[0];
+        assert_eq!((&light.name[..], &light.node[..]), ("spot", "a#2"));
+        assert_eq!(light.color, (1.0, 0.5, 0.0));
+        assert_eq!((light.energy, light.distance), (2.0, 10.0));
+        assert_eq!((light.attenuation, light.spherical), ((0.5, 0.25), true));
+        match read_light_kind(light).unwrap() {
+            cs::LightKind::Spot(angle, blend) => {
+                assert_near(angle.s, 0.5);
+                assert_near(blend, 0.2);
+            },
+            _ => panic!("the light is not a spot"),
+        }
+    }
+
+    #[test]
+    fn camera_fallback() {
+        let mut cam = json::Camera {
+            name: "cam".to_string(),
+            node: "root".to_string(),
+            kind: Some("PANO".to_string()),
+            angle: (1.0, 1.0),
+            range: (0.1, 100.0),
+            ortho_scale: None,
+            shift: None,
+            actions: Vec::new(),
+        };
+        for kind in ["PANO", "ORTHO"].iter() {
+            cam.kind = Some(kind.to_string());
+            match read_projection(&cam) {
+                cs::Projection::Perspective(p) => assert_near(p.fovy.s, 1.0),
+                _ => panic!("camera of kind {} is not a perspective one", kind),
+            }
+        }
+        cam.ortho_scale = Some(4.0);
+        match read_projection(&cam) {
+            cs::Projection::Ortho(p) => assert_near(p.right - p.left, 4.0),
+            _ => panic!("the camera is not an orthographic one"),
+        }
+    }
+
+    #[test]
+    fn default_gravity() {
+        let (mut scene, cache) = make_scene();
+        scene.gravity = None;
+        assert_eq!(save(&scene, &cache).unwrap().global.gravity, (0.0, 0.0, -9.81));
+    }
+
+    #[test]
+    fn mixed_assets() {
+        let (mut scene, cache) = make_scene();
+        let node = scene.entities[0].node;
+        scene.entities.push(make_entity(node, "other", "cube@meshes", "red"));
+        match save(&scene, &cache) {
+            Err(Error::MixedAssets(ref a, ref b)) if a == "assets" && b == "other" => (),
+            other => panic!("unexpected {:?}", other.map(|s| s.assets)),
+        }
+    }
+
+    #[test]
+    fn missing_material() {
+        let (mut scene, cache) = make_scene();
+        let node = scene.entities[0].node;
+        scene.entities.push(make_entity(node, "assets", "cube@meshes", "green"));
+        match save(&scene, &cache) {
+            Err(Error::Material(ref name, _)) if name == "green" => (),
+            other => panic!("unexpected {:?}", other.map(|s| s.assets)),
+        }
+    }
+}
@@ -1,3 +1,4 @@
+extern crate clock_ticks;
 extern crate env_logger;
 extern crate glutin;
 extern crate gfx;
@@ -24,7 +25,10 @@ pub fn main() {
 
     println!("Rendering...");
     let (mut mouse_x, mut mouse_y) = (0, 0);
+    let mut last_moment = clock_ticks::precise_time_ns();
     'main: loop {
+        let delta = clock_ticks::precise_time_ns() - last_moment;
+        last_moment += delta;
         // quit when Esc is pressed.
         for event in stream.out.window.poll_events() {
             use glutin::{ElementState, Event, MouseButton, VirtualKeyCode};
@@ -44,6 +48,7 @@ pub fn main() {
             }
         }
 
+        app.update((delta/1000000) as f32 / 1000.0);
         app.render(&mut stream);
         stream.present(&mut device);
     }
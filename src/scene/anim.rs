@@ -0,0 +1,461 @@
+//! Animation clips, sampled into the nodes and bones of the `World`.
+
+use cgmath::{BaseFloat, Quaternion, Transform, Vector, Vector3};
+use super::{NodeId, SkeletonId, World};
+
+/// Transform component animated by a channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Property {
+    /// Three components of the displacement.
+    Position,
+    /// Quaternion in (w, x, y, z) order.
+    Rotation,
+    /// Euler angles in XYZ order.
+    RotationEuler,
+    /// Three components, averaged into the uniform scale.
+    Scale,
+}
+
+#[derive(Clone, Debug)]
+pub struct Key<S> {
+    pub time: S,
+    pub value: Vec<S>,
+    /// Bezier handle values, preceding and following the key.
+    pub handles: Option<(Vec<S>, Vec<S>)>,
+}
+
+/// Multi-dimensional curve, holding the keys in time order.
+#[derive(Clone, Debug)]
+pub struct Curve<S> {
+    pub keys: Vec<Key<S>>,
+    /// Continue the slope past the end keys, instead of holding the values.
+    pub extrapolate: bool,
+}
+
+impl<S: BaseFloat> Curve<S> {
+    /// Evaluate the curve at time `t` into `out`.
+    pub fn sample(&self, t: S, out: &mut [S]) {
+        let keys = &self.keys;
+        if keys.is_empty() {
+            return
+        }
+        let last = keys.len() - 1;
+        // find the segment containing `t`
+        let (k0, k1) = if t <= keys[0].time {
+            if !self.extrapolate || last == 0 {
+                for (o, v) in out.iter_mut().zip(keys[0].value.iter()) {
+                    *o = *v;
+                }
+                return
+            }
+            (&keys[0], &keys[1])
+        } else if t >= keys[last].time {
+            if !self.extrapolate || last == 0 {
+                for (o, v) in out.iter_mut().zip(keys[last].value.iter()) {
+                    *o = *v;
+                }
+                return
+            }
+            (&keys[last-1], &keys[last])
+        } else {
+            let i = keys.iter().position(|k| k.time > t).unwrap();
+            (&keys[i-1], &keys[i])
+        };
+        let span = k1.time - k0.time;
+        let u = if span > S::zero() {(t - k0.time) / span} else {S::zero()};
+        match (&k0.handles, &k1.handles) {
+            (&Some((_, ref right)), &Some((ref left, _)))
+                    if u >= S::zero() && u <= S::one() => {
+                let three = S::one() + S::one() + S::one();
+                let v = S::one() - u;
+                let (w0, w1, w2, w3) = (v*v*v, three*v*v*u, three*v*u*u, u*u*u);
+                for (i, o) in out.iter_mut().enumerate() {
+                    if i < k0.value.len() {
+                        *o = w0 * k0.value[i] + w1 * right[i] +
+                             w2 * left[i] + w3 * k1.value[i];
+                    }
+                }
+            },
+            // linear interpolation, also used for extrapolation
+            _ => {
+                for (o, (a, b)) in out.iter_mut().zip(k0.value.iter().zip(k1.value.iter())) {
+                    *o = *a + (*b - *a) * u;
+                }
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Channel<S> {
+    /// Name of the animated bone, or empty for the node itself.
+    pub bone: String,
+    pub property: Property,
+    pub curve: Curve<S>,
+}
+
+/// Transform components of a single target, as sampled from a clip.
+/// Components that are not animated are left as `None`.
+#[derive(Clone, Debug)]
+pub struct Sample<S> {
+    pub disp: Option<Vector3<S>>,
+    pub rot: Option<Quaternion<S>>,
+    pub scale: Option<S>,
+}
+
+impl<S: BaseFloat> Sample<S> {
+    pub fn new() -> Sample<S> {
+        Sample {
+            disp: None,
+            rot: None,
+            scale: None,
+        }
+    }
+
+    /// Replace the animated components of a transform.
+    pub fn apply_to(&self, base: &super::Transform<S>) -> super::Transform<S> {
+        let mut t = base.clone();
+        if let Some(d) = self.disp {
+            t.disp = d;
+        }
+        if let Some(r) = self.rot {
+            t.rot = r;
+        }
+        if let Some(s) = self.scale {
+            t.scale = s;
+        }
+        t
+    }
+}
+
+/// Normalized linear interpolation between rotations, taking the shorter arc.
+fn nlerp<S: BaseFloat>(a: &Quaternion<S>, b: &Quaternion<S>, w: S) -> Quaternion<S> {
+    let dot = a.s * b.s + a.v.dot(&b.v);
+    let wb = if dot < S::zero() {-w} else {w};
+    let wa = S::one() - w;
+    Quaternion::from_sv(a.s * wa + b.s * wb,
+                        a.v.mul_s(wa).add_v(&b.v.mul_s(wb))).normalize()
+}
+
+fn mix_option<T: Clone, F: Fn(&T, &T) -> T>(a: &Option<T>, b: &Option<T>, fun: F) -> Option<T> {
+    match (a, b) {
+        (&Some(ref x), &Some(ref y)) => Some(fun(x, y)),
+        (&Some(ref x), &None) => Some(x.clone()),
+        (&None, _) => b.clone(),
+    }
+}
+
+/// Blend two sets of samples, going from `a` at zero weight to `b` at one.
+/// Components animated by only one of the sets are taken as is.
+pub fn blend<S: BaseFloat>(a: &[(String, Sample<S>)], b: &[(String, Sample<S>)], weight: S)
+             -> Vec<(String, Sample<S>)> {
+    let mut out = a.to_vec();
+    for &(ref name, ref sb) in b.iter() {
+        match out.iter().position(|s| s.0 == *name) {
+            Some(i) => {
+                let sa = out[i].1.clone();
+                out[i].1 = Sample {
+                    disp: mix_option(&sa.disp, &sb.disp, |x, y|
+                        x.mul_s(S::one() - weight).add_v(&y.mul_s(weight))),
+                    rot: mix_option(&sa.rot, &sb.rot, |x, y| nlerp(x, y, weight)),
+                    scale: mix_option(&sa.scale, &sb.scale, |x, y|
+                        *x + (*y - *x) * weight),
+                };
+            },
+            None => out.push((name.clone(), sb.clone())),
+        }
+    }
+    out
+}
+
+/// Apply an additive layer on top of the base samples, scaling its effect
+/// by the weight. Layer components are treated as offsets from the rest.
+/// Only the bones have a rest pose, so the node samples of the layer,
+/// which are absolute, are skipped.
+pub fn add<S: BaseFloat>(base: &[(String, Sample<S>)], layer: &[(String, Sample<S>)], weight: S)
+           -> Vec<(String, Sample<S>)> {
+    let identity = Quaternion::new(S::one(), S::zero(), S::zero(), S::zero());
+    let mut out = base.to_vec();
+    for &(ref name, ref sl) in layer.iter().filter(|s| !s.0.is_empty()) {
+        let id = match out.iter().position(|s| s.0 == *name) {
+            Some(i) => i,
+            None => {
+                out.push((name.clone(), Sample::new()));
+                out.len() - 1
+            },
+        };
+        let sample = &mut out[id].1;
+        if let Some(d) = sl.disp {
+            let offset = d.mul_s(weight);
+            sample.disp = Some(match sample.disp {
+                Some(v) => v.add_v(&offset),
+                None => offset,
+            });
+        }
+        if let Some(r) = sl.rot {
+            let delta = nlerp(&identity, &r, weight);
+            sample.rot = Some(match sample.rot {
+                Some(q) => q.mul_q(&delta),
+                None => delta,
+            });
+        }
+        if let Some(s) = sl.scale {
+            let factor = S::one() + (s - S::one()) * weight;
+            sample.scale = Some(sample.scale.unwrap_or(S::one()) * factor);
+        }
+    }
+    out
+}
+
+/// Build a rotation from Euler angles in XYZ order,
+/// so X is applied first, like Blender does.
+fn from_euler<S: BaseFloat>(x: S, y: S, z: S) -> Quaternion<S> {
+    let two = S::one() + S::one();
+    let (sx, cx) = ((x / two).sin(), (x / two).cos());
+    let (sy, cy) = ((y / two).sin(), (y / two).cos());
+    let (sz, cz) = ((z / two).sin(), (z / two).cos());
+    Quaternion::new(
+        cx*cy*cz + sx*sy*sz,
+        sx*cy*cz - cx*sy*sz,
+        cx*sy*cz + sx*cy*sz,
+        cx*cy*sz - sx*sy*cz,
+    )
+}
+
+#[derive(Clone, Debug)]
+pub struct AnimationClip<S> {
+    pub name: String,
+    pub duration: S,
+    pub channels: Vec<Channel<S>>,
+}
+
+impl<S: BaseFloat> AnimationClip<S> {
+    /// Sample all the channels at time `t`, grouped by the bone name.
+    pub fn sample(&self, t: S) -> Vec<(String, Sample<S>)> {
+        let mut samples: Vec<(String, Sample<S>)> = Vec::new();
+        for chan in self.channels.iter() {
+            let id = match samples.iter().position(|s| s.0 == chan.bone) {
+                Some(i) => i,
+                None => {
+                    samples.push((chan.bone.clone(), Sample::new()));
+                    samples.len() - 1
+                },
+            };
+            let sample = &mut samples[id].1;
+            let mut v = [S::zero(); 4];
+            chan.curve.sample(t, &mut v);
+            match chan.property {
+                Property::Position =>
+                    sample.disp = Some(Vector3::new(v[0], v[1], v[2])),
+                Property::Rotation =>
+                    sample.rot = Some(Quaternion::new(v[0], v[1], v[2], v[3]).normalize()),
+                Property::RotationEuler =>
+                    sample.rot = Some(from_euler(v[0], v[1], v[2])),
+                Property::Scale => {
+                    let three = S::one() + S::one() + S::one();
+                    sample.scale = Some((v[0] + v[1] + v[2]) / three);
+                },
+            }
+        }
+        samples
+    }
+}
+
+/// Clip bound to the node and the skeleton it animates.
+#[derive(Clone, Debug)]
+pub struct Animation<S> {
+    pub clip: AnimationClip<S>,
+    pub node: NodeId<S>,
+    pub skeleton: Option<SkeletonId<S>>,
+    /// Action reference the clip was loaded from, allowing to save it back.
+    pub source: Option<String>,
+}
+
+impl<S: BaseFloat> Animation<S> {
+    /// Sample the clip at time `t` into the local transforms.
+    pub fn apply(&self, t: S, world: &mut World<S>) {
+        self.apply_samples(&self.clip.sample(t), world)
+    }
+
+    /// Write the samples into the local transforms of the targets.
+    /// Bone samples are relative to the bind pose.
+    pub fn apply_samples(&self, samples: &[(String, Sample<S>)], world: &mut World<S>) {
+        for &(ref bone, ref sample) in samples.iter() {
+            if bone.is_empty() {
+                let node = world.mut_node(self.node);
+                node.local = sample.apply_to(&node.local);
+                continue
+            }
+            let skel = match self.skeleton {
+                Some(sid) => world.mut_skeleton(sid),
+                None => continue,
+            };
+            match skel.find_bone(bone) {
+                Some(bid) => {
+                    let b = skel.mut_bone(bid);
+                    let pose = sample.apply_to(&Transform::identity());
+                    b.local = b.get_bind_pose().concat(&pose);
+                },
+                None => warn!("Bone {} is not found for clip {}", bone, self.clip.name),
+            }
+        }
+    }
+}
+
+/// Playback state of an animation.
+#[derive(Clone, Debug)]
+pub struct Player<S> {
+    pub time: S,
+    pub speed: S,
+    pub looped: bool,
+}
+
+impl<S: BaseFloat> Player<S> {
+    pub fn new(looped: bool) -> Player<S> {
+        Player {
+            time: S::zero(),
+            speed: S::one(),
+            looped: looped,
+        }
+    }
+
+    /// Advance the time, wrapping or clamping it to the duration.
+    pub fn advance(&mut self, delta: S, duration: S) {
+        self.time = self.time + delta * self.speed;
+        if duration <= S::zero() {
+            self.time = S::zero();
+        } else if self.looped {
+            self.time = self.time % duration;
+            if self.time < S::zero() {
+                self.time = self.time + duration;
+            }
+        } else {
+            self.time = self.time.max(S::zero()).min(duration);
+        }
+    }
+
+    pub fn is_done(&self, duration: S) -> bool {
+        !self.looped && self.time >= duration
+    }
+
+    /// Advance and apply the animation to the world.
+    pub fn update(&mut self, anim: &Animation<S>, delta: S, world: &mut World<S>) {
+        self.advance(delta, anim.clip.duration);
+        anim.apply(self.time, world);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath::Vector3;
+    use super::*;
+
+    fn key(time: f32, value: &[f32]) -> Key<f32> {
+        Key {
+            time: time,
+            value: value.to_vec(),
+            handles: None,
+        }
+    }
+
+    fn bezier_key(time: f32, value: f32, left: f32, right: f32) -> Key<f32> {
+        Key {
+            time: time,
+            value: vec![value],
+            handles: Some((vec![left], vec![right])),
+        }
+    }
+
+    fn sample(curve: &Curve<f32>, t: f32) -> Vec<f32> {
+        let mut out = vec![0.0; curve.keys[0].value.len()];
+        curve.sample(t, &mut out);
+        out
+    }
+
+    fn assert_near(a: &[f32], b: &[f32]) {
+        assert_eq!(a.len(), b.len());
+        for (x, y) in a.iter().zip(b.iter()) {
+            assert!((x - y).abs() < 1e-5, "{:?} != {:?}", a, b);
+        }
+    }
+
+    fn make_linear(extrapolate: bool) -> Curve<f32> {
+        Curve {
+            keys: vec![key(0.0, &[0.0, 10.0]), key(1.0, &[1.0, 20.0]), key(3.0, &[5.0, 0.0])],
+            extrapolate: extrapolate,
+        }
+    }
+
+    #[test]
+    fn linear() {
+        let curve = make_linear(false);
+        assert_near(&sample(&curve, 0.0), &[0.0, 10.0]);
+        assert_near(&sample(&curve, 0.5), &[0.5, 15.0]);
+        assert_near(&sample(&curve, 1.0), &[1.0, 20.0]);
+        assert_near(&sample(&curve, 2.0), &[3.0, 10.0]);
+        assert_near(&sample(&curve, 3.0), &[5.0, 0.0]);
+    }
+
+    #[test]
+    fn clamp() {
+        let curve = make_linear(false);
+        assert_near(&sample(&curve, -1.0), &[0.0, 10.0]);
+        assert_near(&sample(&curve, 5.0), &[5.0, 0.0]);
+        let single = Curve {
+            keys: vec![key(1.0, &[2.0])],
+            extrapolate: true,
+        };
+        assert_near(&sample(&single, 0.0), &[2.0]);
+        assert_near(&sample(&single, 2.0), &[2.0]);
+        let empty = Curve { keys: Vec::new(), extrapolate: false };
+        let mut out = [7.0];
+        empty.sample(1.0, &mut out);
+        assert_eq!(out, [7.0]);
+    }
+
+    #[test]
+    fn extrapolate() {
+        let curve = make_linear(true);
+        assert_near(&sample(&curve, -1.0), &[-1.0, 0.0]);
+        assert_near(&sample(&curve, 5.0), &[9.0, -20.0]);
+    }
+
+    #[test]
+    fn bezier() {
+        // evenly spaced handles give a straight line
+        let straight = Curve {
+            keys: vec![bezier_key(0.0, 0.0, -1.0, 1.0), bezier_key(1.0, 3.0, 2.0, 4.0)],
+            extrapolate: false,
+        };
+        for &t in [0.0, 0.25, 0.5, 1.0].iter() {
+            assert_near(&sample(&straight, t), &[3.0 * t]);
+        }
+        // flat handles ease in and out
+        let eased = Curve {
+            keys: vec![bezier_key(0.0, 0.0, 0.0, 0.0), bezier_key(2.0, 1.0, 1.0, 1.0)],
+            extrapolate: true,
+        };
+        assert_near(&sample(&eased, 0.5), &[0.15625]);
+        assert_near(&sample(&eased, 1.0), &[0.5]);
+        assert_near(&sample(&eased, 2.0), &[1.0]);
+        // the extrapolation is linear
+        assert_near(&sample(&eased, 4.0), &[2.0]);
+    }
+
+    #[test]
+    fn add_bones_only() {
+        let offset = Sample {
+            disp: Some(Vector3::new(1.0, 0.0, 0.0)),
+            rot: None,
+            scale: Some(2.0),
+        };
+        let base = vec![(String::new(), offset.clone()), ("arm".to_string(), offset.clone())];
+        let layer = vec![(String::new(), offset.clone()), ("arm".to_string(), offset.clone())];
+        let out = add(&base, &layer, 0.5);
+        // the node sample is absolute, adding it would double the location
+        assert_eq!(out[0].1.disp, Some(Vector3::new(1.0, 0.0, 0.0)));
+        assert_eq!(out[0].1.scale, Some(2.0));
+        assert_eq!(out[1].1.disp, Some(Vector3::new(1.5, 0.0, 0.0)));
+        assert_eq!(out[1].1.scale, Some(3.0));
+    }
+}
@@ -6,6 +6,10 @@ extern crate gfx;
 extern crate gfx_scene;
 extern crate gfx_pipeline;
 
+use std::collections::HashMap;
+
+pub mod anim;
+pub mod param;
 pub mod space;
 pub use gfx_pipeline::{Material, Transparency, ViewInfo};
 pub use gfx_scene as base;
@@ -22,11 +26,113 @@ pub type World<S> = space::World<S, Transform<S>>;
 pub type Node<S> = space::Node<Transform<S>>;
 pub type NodeId<S> = id::Id<space::Node<Transform<S>>>;
 pub type Skeleton<S> = space::Skeleton<Transform<S>>;
-pub type Projection<S> = cgmath::PerspectiveFov<S, cgmath::Rad<S>>;
+pub type SkeletonId<S> = id::Id<Skeleton<S>>;
+pub type Bone<S> = space::Bone<Transform<S>>;
+pub type BoneId<S> = id::Id<Bone<S>>;
 pub type Fragment<R> = gfx_scene::Fragment<R, Material<R>>;
 
+/// Skinning palette of a skeleton as column-major matrices,
+/// ready to be uploaded as a uniform array.
+pub fn get_palette_matrices<S: cgmath::BaseFloat>(world: &World<S>, id: SkeletonId<S>)
+                            -> Vec<[[S; 4]; 4]> {
+    use cgmath::FixedArray;
+    world.get_skinning_palette(id).into_iter().map(|t| {
+        let mx: cgmath::Matrix4<S> = t.into();
+        mx.into_fixed()
+    }).collect()
+}
+
 pub struct Pair<A, B>(A, B);
 
+/// Bound of the transformed corners of a box.
+pub fn transform_bound<S: cgmath::BaseFloat>(bound: &cgmath::Aabb3<S>, transform: &Transform<S>)
+                       -> cgmath::Aabb3<S> {
+    use cgmath::{Aabb, Point3, Transform};
+    let (lo, hi) = (bound.min, bound.max);
+    let mut out: Option<cgmath::Aabb3<S>> = None;
+    for i in 0.. 8 {
+        let corner = Point3::new(
+            if i & 1 != 0 {hi.x} else {lo.x},
+            if i & 2 != 0 {hi.y} else {lo.y},
+            if i & 4 != 0 {hi.z} else {lo.z},
+        );
+        let p = transform.transform_point(&corner);
+        out = Some(match out {
+            Some(b) => b.grow(&p),
+            None => cgmath::Aabb3::new(p, p),
+        });
+    }
+    out.unwrap()
+}
+
+/// Camera projection, selectable per camera.
+#[derive(Clone, Debug)]
+pub enum Projection<S> {
+    /// Symmetric perspective, defined by the vertical field of view.
+    Perspective(cgmath::PerspectiveFov<S, cgmath::Rad<S>>),
+    /// Off-center perspective, defined by the view volume at the near plane.
+    Frustum(cgmath::Perspective<S>),
+    /// Orthographic view volume.
+    Ortho(cgmath::Ortho<S>),
+}
+
+impl<S: cgmath::BaseFloat> Projection<S> {
+    /// Adjust the horizontal extent to the aspect ratio of the target,
+    /// keeping the vertical one and the center.
+    pub fn set_aspect(&mut self, aspect: S) {
+        let two = S::one() + S::one();
+        match *self {
+            Projection::Perspective(ref mut p) => p.aspect = aspect,
+            Projection::Frustum(ref mut p) => {
+                let center = (p.left + p.right) / two;
+                let half = (p.top - p.bottom) * aspect / two;
+                p.left = center - half;
+                p.right = center + half;
+            },
+            Projection::Ortho(ref mut p) => {
+                let center = (p.left + p.right) / two;
+                let half = (p.top - p.bottom) * aspect / two;
+                p.left = center - half;
+                p.right = center + half;
+            },
+        }
+    }
+
+    /// Fraction of the view height covered by a sphere
+    /// of the given radius at the given distance.
+    pub fn get_screen_size(&self, radius: S, distance: S) -> S {
+        let two = S::one() + S::one();
+        match *self {
+            Projection::Perspective(ref p) =>
+                radius / (distance * (p.fovy.s / two).tan()),
+            Projection::Frustum(ref p) =>
+                radius * two * p.near / (distance * (p.top - p.bottom)),
+            Projection::Ortho(ref p) =>
+                radius * two / (p.top - p.bottom),
+        }
+    }
+}
+
+impl<S: cgmath::BaseFloat> From<Projection<S>> for cgmath::Matrix4<S> {
+    fn from(p: Projection<S>) -> cgmath::Matrix4<S> {
+        match p {
+            Projection::Perspective(p) => p.into(),
+            Projection::Frustum(p) => p.into(),
+            Projection::Ortho(p) => p.into(),
+        }
+    }
+}
+
+impl<S: cgmath::BaseFloat> cgmath::Projection<S> for Projection<S> {
+    fn to_frustum(&self) -> cgmath::Frustum<S> {
+        match *self {
+            Projection::Perspective(ref p) => p.to_frustum(),
+            Projection::Frustum(ref p) => p.to_frustum(),
+            Projection::Ortho(ref p) => p.to_frustum(),
+        }
+    }
+}
+
 /// A simple camera with generic projection and spatial relation.
 #[derive(Clone)]
 pub struct Camera<S> {
@@ -38,6 +144,61 @@ pub struct Camera<S> {
     pub node: NodeId<S>,
 }
 
+impl<S: cgmath::BaseFloat> Camera<S> {
+    /// World-to-view transformation matrix.
+    /// Returns `None` if the camera node transform is not invertible.
+    pub fn get_view(&self, world: &World<S>) -> Option<cgmath::Matrix4<S>> {
+        use cgmath::Transform;
+        world.get_node(self.node).world.invert().map(|t| t.into())
+    }
+
+    /// World-to-clip transformation matrix.
+    pub fn get_view_projection(&self, world: &World<S>) -> Option<cgmath::Matrix4<S>> {
+        use cgmath::Matrix;
+        let proj: cgmath::Matrix4<S> = self.projection.clone().into();
+        self.get_view(world).map(|view| proj.mul_m(&view))
+    }
+
+    /// World space ray going through the given point of the screen,
+    /// where (0, 0) is the top left corner and (1, 1) is the bottom right.
+    /// The ray starts at the near plane. Returns `None` if the
+    /// view-projection matrix is degenerate.
+    pub fn ray_from_screen(&self, x: S, y: S, world: &World<S>) -> Option<cgmath::Ray3<S>> {
+        use cgmath::{EuclideanVector, Matrix, Point};
+        let one = S::one();
+        let two = one + one;
+        let inv = match self.get_view_projection(world).and_then(|m| m.invert()) {
+            Some(m) => m,
+            None => return None,
+        };
+        let (nx, ny) = (x * two - one, one - y * two);
+        let near = cgmath::Point3::from_homogeneous(&inv.mul_v(
+            &cgmath::Point3::new(nx, ny, -one).to_homogeneous()));
+        let far = cgmath::Point3::from_homogeneous(&inv.mul_v(
+            &cgmath::Point3::new(nx, ny, one).to_homogeneous()));
+        Some(cgmath::Ray3::new(near, far.sub_p(&near).normalize()))
+    }
+
+    /// Project a world space point onto the screen, using the coordinates
+    /// of `ray_from_screen` for X and Y, and the normalized depth for Z.
+    /// Returns `None` for the points behind the camera.
+    pub fn project_point(&self, point: &cgmath::Point3<S>, world: &World<S>)
+                         -> Option<cgmath::Point3<S>> {
+        use cgmath::{Matrix, Point};
+        let one = S::one();
+        let two = one + one;
+        let clip = match self.get_view_projection(world) {
+            Some(m) => m.mul_v(&point.to_homogeneous()),
+            None => return None,
+        };
+        if clip.w <= S::zero() {
+            return None
+        }
+        let ndc = cgmath::Point3::from_homogeneous(&clip);
+        Some(cgmath::Point3::new((ndc.x + one) / two, (one - ndc.y) / two, ndc.z))
+    }
+}
+
 impl<'a, S: cgmath::BaseFloat> gfx_scene::Node for Pair<&'a Camera<S>, &'a World<S>> {
     type Transform = Transform<S>;
     fn get_transform(&self) -> Transform<S> {
@@ -50,15 +211,121 @@ impl<'a, S: cgmath::BaseFloat> gfx_scene::Camera<S> for Pair<&'a Camera<S>, &'a
     fn get_projection(&self) -> Projection<S> { self.0.projection.clone() }
 }
 
+/// Shape of the emitted light.
+#[derive(Clone, Debug)]
+pub enum LightKind<S> {
+    /// Omni-directional light at the node position.
+    Point,
+    /// Parallel rays along the negative Z of the node.
+    Directional,
+    /// Sky light, coming from the positive Z hemisphere.
+    Hemisphere,
+    /// Cone along the negative Z, with the full angle and the edge softness.
+    Spot(cgmath::Rad<S>, S),
+    /// Rectangle of the given size.
+    Area(S, S),
+}
+
+/// A light source, attached to a node.
+#[derive(Clone, Debug)]
+pub struct Light<S> {
+    pub name: String,
+    pub node: NodeId<S>,
+    pub kind: LightKind<S>,
+    pub color: [S; 3],
+    pub energy: S,
+    /// Distance at which the intensity is halved.
+    pub distance: S,
+    /// Linear and quadratic attenuation factors.
+    pub attenuation: (S, S),
+    /// If set, the light does not reach beyond the distance.
+    pub spherical: bool,
+}
+
+impl<S: cgmath::BaseFloat> Light<S> {
+    /// Check if the light can reach a bound, given its transformation.
+    pub fn affects(&self, bound: &cgmath::Aabb3<S>, transform: &Transform<S>,
+                   world: &World<S>) -> bool {
+        use cgmath::{Aabb, EuclideanVector, Point, Transform, Vector};
+        match self.kind {
+            LightKind::Directional | LightKind::Hemisphere => return true,
+            LightKind::Spot(..) => (),
+            _ if !self.spherical => return true,
+            _ => (),
+        }
+        let two = S::one() + S::one();
+        let center = transform.transform_point(&bound.center());
+        let radius = bound.max.sub_p(&bound.min).length() *
+                     transform.scale / two;
+        let light = &world.get_node(self.node).world;
+        let offset = center.to_vec().sub_v(&light.disp);
+        let dist = offset.length();
+        if self.spherical && dist > self.distance + radius {
+            return false
+        }
+        match self.kind {
+            LightKind::Spot(angle, _) => {
+                // distance from the bounding sphere center to the cone surface,
+                // or to the apex if the center is behind it
+                let axis = light.transform_vector(&cgmath::Vector3::new(
+                    S::zero(), S::zero(), -S::one())).normalize();
+                let along = offset.dot(&axis);
+                let across = (dist * dist - along * along).max(S::zero()).sqrt();
+                let (sin, cos) = ((angle.s / two).sin(), (angle.s / two).cos());
+                let gap = if along * cos + across * sin < S::zero() {
+                    dist
+                } else {
+                    across * cos - along * sin
+                };
+                gap <= radius
+            },
+            _ => true,
+        }
+    }
+}
+
+/// Asset references an entity was created from, allowing to save it back.
+#[derive(Clone, Debug)]
+pub struct Source {
+    /// Directory the references are relative to.
+    pub assets: String,
+    /// Mesh reference, in the "name@collection" form.
+    pub mesh: String,
+    /// Material name of each fragment.
+    pub materials: Vec<String>,
+}
+
+/// Condition for switching to a coarser level of detail.
+#[derive(Clone, Copy, Debug)]
+pub enum LodThreshold<S> {
+    /// Camera distance to the bound center, at which the level starts.
+    Distance(S),
+    /// Projected bound radius relative to the half-height of the screen,
+    /// below which the level starts.
+    ScreenSize(S),
+}
+
+/// A coarser level of detail of an entity.
+#[derive(Clone)]
+pub struct Lod<R: gfx::Resources, S> {
+    pub threshold: LodThreshold<S>,
+    pub mesh: gfx::Mesh<R>,
+    pub fragments: Vec<Fragment<R>>,
+    pub source: Option<Source>,
+}
+
 #[derive(Clone)]
 pub struct Entity<R: gfx::Resources, S: cgmath::BaseNum> {
     pub name: String,
     pub visible: bool,
     pub mesh: gfx::Mesh<R>,
     pub node: NodeId<S>,
-    pub skeleton: Option<id::Id<Skeleton<S>>>,
+    pub skeleton: Option<SkeletonId<S>>,
 	pub bound: cgmath::Aabb3<S>,
     pub fragments: Vec<Fragment<R>>,
+    /// Coarser levels of detail, from the finest to the coarsest.
+    pub lods: Vec<Lod<R, S>>,
+    pub source: Option<Source>,
 }
 
 impl<R: gfx::Resources, S: cgmath::BaseNum> Entity<R, S> {
@@ -74,6 +341,8 @@ impl<R: gfx::Resources, S: cgmath::BaseNum> Entity<R, S> {
             skeleton: None,
             bound: bound,
             fragments: Vec::new(),
+            lods: Vec::new(),
+            source: None,
         }
     }
 
@@ -86,6 +355,37 @@ impl<R: gfx::Resources, S: cgmath::BaseNum> Entity<R, S> {
     }
 }
 
+impl<R: gfx::Resources, S: cgmath::BaseFloat> Entity<R, S> {
+    /// Select the level of detail for the camera, given the world
+    /// transform of the entity. Level 0 is the entity itself,
+    /// level N is `lods[N-1]`.
+    pub fn select_lod(&self, transform: &Transform<S>, camera: &Camera<S>,
+                      world: &World<S>) -> usize {
+        use cgmath::{Aabb, EuclideanVector, Point, Transform, Vector};
+        if self.lods.is_empty() {
+            return 0
+        }
+        let two = S::one() + S::one();
+        let center = transform.transform_point(&self.bound.center());
+        let radius = self.bound.max.sub_p(&self.bound.min).length() *
+                     transform.scale / two;
+        let cam_pos = world.get_node(camera.node).world.disp;
+        let distance = center.to_vec().sub_v(&cam_pos).length();
+        let screen_size = camera.projection.get_screen_size(radius, distance);
+        let mut level = 0;
+        for (i, lod) in self.lods.iter().enumerate() {
+            let passed = match lod.threshold {
+                LodThreshold::Distance(d) => distance >= d,
+                LodThreshold::ScreenSize(s) => screen_size <= s,
+            };
+            if passed {
+                level = i + 1;
+            }
+        }
+        level
+    }
+}
+
 impl<'a,
     R: gfx::Resources,
     S: cgmath::BaseFloat,
@@ -107,10 +407,387 @@ impl<'a,
     fn get_fragments(&self) -> &[Fragment<R>] { &self.0.fragments }
 }
 
+/// Prefix of the per-instance world matrix columns,
+/// numbered from 0 to 3, as seen by the shader.
+pub static INSTANCE_ATTRIB: &'static str = "a_World";
+
+/// Mesh of a group with the instance buffer attached,
+/// and the bound of all the instances.
+pub type Uploaded<R, S> = (gfx::Mesh<R>, cgmath::Aabb3<S>);
+
+/// A group of entities sharing the mesh and fragments,
+/// placed with individual transforms relative to the group node.
+#[derive(Clone)]
+pub struct Instances<R: gfx::Resources, S: cgmath::BaseNum> {
+    pub name: String,
+    pub visible: bool,
+    pub mesh: gfx::Mesh<R>,
+    pub node: NodeId<S>,
+    /// Bound of a single instance.
+    pub bound: cgmath::Aabb3<S>,
+    pub fragments: Vec<Fragment<R>>,
+    pub transforms: Vec<Transform<S>>,
+    /// Set by `upload_instances`.
+    pub instanced: Option<Uploaded<R, S>>,
+    pub source: Option<Source>,
+}
+
+impl<R: gfx::Resources, S: cgmath::BaseNum> Instances<R, S> {
+    /// Create a new empty group.
+    pub fn new(mesh: gfx::Mesh<R>, node: NodeId<S>, bound: cgmath::Aabb3<S>)
+               -> Instances<R, S>
+    {
+        Instances {
+            name: String::new(),
+            visible: true,
+            mesh: mesh,
+            node: node,
+            bound: bound,
+            fragments: Vec::new(),
+            transforms: Vec::new(),
+            instanced: None,
+            source: None,
+        }
+    }
+
+    /// Add another instance with a given local transform.
+    pub fn add_instance(&mut self, transform: Transform<S>) {
+        self.transforms.push(transform);
+    }
+}
+
+impl<R: gfx::Resources, S: cgmath::BaseFloat> Instances<R, S> {
+    /// Compute the bound of all the instances, relative to the group node.
+    pub fn get_group_bound(&self) -> cgmath::Aabb3<S> {
+        use cgmath::Aabb;
+        let mut bound: Option<cgmath::Aabb3<S>> = None;
+        for t in self.transforms.iter() {
+            let b = transform_bound(&self.bound, t);
+            bound = Some(match bound {
+                Some(acc) => acc.grow(&b.min).grow(&b.max),
+                None => b,
+            });
+        }
+        bound.unwrap_or(self.bound.clone())
+    }
+}
+
+impl<R: gfx::Resources> Instances<R, f32> {
+    /// Upload the local matrices of all instances into an instance buffer,
+    /// returning the mesh extended with the instance-rate attributes.
+    /// Suitable for drawing all instances with a single instanced call.
+    pub fn make_instanced_mesh<F: gfx::Factory<R>>(&self, factory: &mut F) -> gfx::Mesh<R> {
+        use cgmath::{FixedArray, Matrix4};
+        let data: Vec<[[f32; 4]; 4]> = self.transforms.iter().map(|t| {
+            let mx: Matrix4<f32> = t.clone().into();
+            mx.into_fixed()
+        }).collect();
+        let buffer = factory.create_buffer_static(&data[..], gfx::BufferRole::Vertex);
+        let mut mesh = self.mesh.clone();
+        for i in 0..4 {
+            mesh.attributes.push(gfx::Attribute {
+                name: format!("{}{}", INSTANCE_ATTRIB, i),
+                buffer: buffer.raw().clone(),
+                format: gfx::attrib::Format {
+                    elem_count: 4,
+                    elem_type: gfx::attrib::Type::Float(
+                        gfx::attrib::FloatSubType::Default,
+                        gfx::attrib::FloatSize::F32),
+                    offset: (i * 16) as gfx::attrib::Offset,
+                    stride: 64,
+                    instance_rate: 1,
+                },
+            });
+        }
+        mesh
+    }
+
+    /// Build the instance buffer, so that the scene draws the group with
+    /// a single instanced call per fragment. The vertex shader is expected
+    /// to place each instance with the `INSTANCE_ATTRIB` columns, relative
+    /// to the group node. Needs to be called again after the transforms change.
+    pub fn upload_instances<F: gfx::Factory<R>>(&mut self, factory: &mut F) {
+        let mesh = self.make_instanced_mesh(factory);
+        self.instanced = Some((mesh, self.get_group_bound()));
+    }
+}
+
+impl<'a,
+    R: gfx::Resources,
+    S: cgmath::BaseFloat,
+> gfx_scene::Node for Pair<(&'a Instances<R, S>, &'a Uploaded<R, S>), &'a World<S>> {
+    type Transform = Transform<S>;
+    fn get_transform(&self) -> Transform<S> {
+        self.1.get_node((self.0).0.node).world.clone()
+    }
+}
+
+/// The whole uploaded group, drawn at once.
+impl<'a,
+    R: gfx::Resources,
+    S: cgmath::BaseFloat,
+> gfx_scene::Entity<R, Material<R>> for Pair<(&'a Instances<R, S>, &'a Uploaded<R, S>), &'a World<S>> {
+    type Bound = cgmath::Aabb3<S>;
+    fn is_visible(&self) -> bool { (self.0).0.visible }
+    fn get_bound(&self) -> cgmath::Aabb3<S> {
+        let (_, uploaded) = self.0;
+        uploaded.1.clone()
+    }
+    fn get_mesh(&self) -> &gfx::Mesh<R> {
+        let (_, uploaded) = self.0;
+        &uploaded.0
+    }
+    fn get_fragments(&self) -> &[Fragment<R>] { &(self.0).0.fragments }
+}
+
+/// Stream adapter, turning every draw call into an instanced one.
+struct InstancedStream<'a, X: 'a> {
+    stream: &'a mut X,
+    count: gfx::InstanceCount,
+}
+
+impl<'a, R: gfx::Resources, X: gfx::Stream<R>> gfx::Stream<R> for InstancedStream<'a, X> {
+    type CommandBuffer = X::CommandBuffer;
+    type Output = X::Output;
+    fn get_output(&self) -> &X::Output {
+        self.stream.get_output()
+    }
+    fn access(&mut self) -> (&mut gfx::Renderer<R, X::CommandBuffer>, &X::Output) {
+        self.stream.access()
+    }
+    fn draw<B: gfx::Batch<Resources = R>>(&mut self, batch: &B)
+            -> Result<(), gfx::DrawError<B::Error>> {
+        self.stream.draw_instanced(batch, self.count, 0)
+    }
+}
+
+impl<'a,
+    R: gfx::Resources,
+    S: cgmath::BaseFloat,
+> gfx_scene::Node for Pair<(&'a Instances<R, S>, usize), &'a World<S>> {
+    type Transform = Transform<S>;
+    fn get_transform(&self) -> Transform<S> {
+        use cgmath::Transform;
+        let (group, i) = self.0;
+        self.1.get_node(group.node).world.concat(&group.transforms[i])
+    }
+}
+
+impl<'a,
+    R: gfx::Resources,
+    S: cgmath::BaseFloat,
+> gfx_scene::Entity<R, Material<R>> for Pair<(&'a Instances<R, S>, usize), &'a World<S>> {
+    type Bound = cgmath::Aabb3<S>;
+    fn is_visible(&self) -> bool { (self.0).0.visible }
+    fn get_bound(&self) -> cgmath::Aabb3<S> { (self.0).0.bound.clone() }
+    fn get_mesh(&self) -> &gfx::Mesh<R> { &(self.0).0.mesh }
+    fn get_fragments(&self) -> &[Fragment<R>] { &(self.0).0.fragments }
+}
+
+/// Anything the scene draws: either a standalone entity
+/// or a single instance of a group.
+enum Item<'a, R: gfx::Resources + 'a, S: cgmath::BaseNum + 'a> {
+    Single(Pair<&'a Entity<R, S>, &'a World<S>>, usize),
+    Instance(Pair<(&'a Instances<R, S>, usize), &'a World<S>>),
+}
+
+impl<'a,
+    R: gfx::Resources,
+    S: cgmath::BaseFloat,
+> gfx_scene::Node for Item<'a, R, S> {
+    type Transform = Transform<S>;
+    fn get_transform(&self) -> Transform<S> {
+        match *self {
+            Item::Single(ref p, _) => gfx_scene::Node::get_transform(p),
+            Item::Instance(ref p) => gfx_scene::Node::get_transform(p),
+        }
+    }
+}
+
+impl<'a,
+    R: gfx::Resources,
+    S: cgmath::BaseFloat,
+> gfx_scene::Entity<R, Material<R>> for Item<'a, R, S> {
+    type Bound = cgmath::Aabb3<S>;
+    fn is_visible(&self) -> bool {
+        match *self {
+            Item::Single(ref p, _) => gfx_scene::Entity::is_visible(p),
+            Item::Instance(ref p) => gfx_scene::Entity::is_visible(p),
+        }
+    }
+    fn get_bound(&self) -> cgmath::Aabb3<S> {
+        match *self {
+            Item::Single(ref p, _) => gfx_scene::Entity::get_bound(p),
+            Item::Instance(ref p) => gfx_scene::Entity::get_bound(p),
+        }
+    }
+    fn get_mesh(&self) -> &gfx::Mesh<R> {
+        match *self {
+            Item::Single(ref p, 0) => gfx_scene::Entity::get_mesh(p),
+            Item::Single(ref p, lod) => &p.0.lods[lod-1].mesh,
+            Item::Instance(ref p) => gfx_scene::Entity::get_mesh(p),
+        }
+    }
+    fn get_fragments(&self) -> &[Fragment<R>] {
+        match *self {
+            Item::Single(ref p, 0) => gfx_scene::Entity::get_fragments(p),
+            Item::Single(ref p, lod) => &p.0.lods[lod-1].fragments,
+            Item::Instance(ref p) => gfx_scene::Entity::get_fragments(p),
+        }
+    }
+}
+
+impl<'a, R: gfx::Resources, S: cgmath::BaseNum> Item<'a, R, S> {
+    fn get_source(&self) -> Option<&Source> {
+        match *self {
+            Item::Single(ref p, 0) => p.0.source.as_ref(),
+            Item::Single(ref p, lod) => p.0.lods[lod-1].source.as_ref(),
+            Item::Instance(ref p) => (p.0).0.source.as_ref(),
+        }
+    }
+}
+
+/// Entity without the fragments that are drawn
+/// with the programs of their parameters.
+struct Remainder<E, R: gfx::Resources>(E, Option<Vec<Fragment<R>>>);
+
+impl<E: gfx_scene::Node, R: gfx::Resources> gfx_scene::Node for Remainder<E, R> {
+    type Transform = E::Transform;
+    fn get_transform(&self) -> E::Transform {
+        gfx_scene::Node::get_transform(&self.0)
+    }
+}
+
+impl<
+    R: gfx::Resources,
+    E: gfx_scene::Entity<R, Material<R>>,
+> gfx_scene::Entity<R, Material<R>> for Remainder<E, R> {
+    type Bound = E::Bound;
+    fn is_visible(&self) -> bool { gfx_scene::Entity::is_visible(&self.0) }
+    fn get_bound(&self) -> E::Bound { gfx_scene::Entity::get_bound(&self.0) }
+    fn get_mesh(&self) -> &gfx::Mesh<R> { gfx_scene::Entity::get_mesh(&self.0) }
+    fn get_fragments(&self) -> &[Fragment<R>] {
+        match self.1 {
+            Some(ref fragments) => fragments,
+            None => gfx_scene::Entity::get_fragments(&self.0),
+        }
+    }
+}
+
+/// Keep the fragments without parameters, returning `None` if these are all of them.
+fn split_fragments<R: gfx::Resources>(fragments: &[Fragment<R>],
+                   params: &[Option<&param::Parameters<R>>])
+                   -> Option<Vec<Fragment<R>>> {
+    if params.iter().all(|p| p.is_none()) {
+        return None
+    }
+    Some(fragments.iter().zip(params.iter())
+                  .filter(|&(_, p)| p.is_none())
+                  .map(|(f, _)| f.clone())
+                  .collect())
+}
+
+/// Model-view-projection matrix, as seen by the programs of the parameters.
+pub static UNIFORM_TRANSFORM: &'static str = "u_Transform";
+/// Model-to-world matrix, as seen by the programs of the parameters.
+pub static UNIFORM_WORLD: &'static str = "u_WorldTransform";
+
+/// Check if a bound is entirely outside of the view volume,
+/// given the model-view-projection matrix.
+fn is_outside(bound: &cgmath::Aabb3<f32>, mvp: &cgmath::Matrix4<f32>) -> bool {
+    use cgmath::{Matrix, Point, Point3};
+    let (lo, hi) = (bound.min, bound.max);
+    let corners: Vec<[f32; 4]> = (0.. 8).map(|i| {
+        let c = mvp.mul_v(&Point3::new(
+            if i & 1 != 0 {hi.x} else {lo.x},
+            if i & 2 != 0 {hi.y} else {lo.y},
+            if i & 4 != 0 {hi.z} else {lo.z},
+        ).to_homogeneous());
+        [c.x, c.y, c.z, c.w]
+    }).collect();
+    // all the corners are beyond the same clip plane
+    (0.. 3).any(|a| corners.iter().all(|c| c[a] > c[3]) ||
+                    corners.iter().all(|c| c[a] < -c[3]))
+}
+
+fn draw_fragment<R: gfx::Resources, X: gfx::Stream<R>>(
+                 mesh: &gfx::Mesh<R>, fragment: &Fragment<R>,
+                 params: &param::Parameters<R>, program: &gfx::handle::Program<R>,
+                 mvp: &cgmath::Matrix4<f32>, model: &cgmath::Matrix4<f32>,
+                 stream: &mut X) -> Result<(), String> {
+    use cgmath::FixedArray;
+    let info = program.get_info();
+    let mut dict = try!(params.bind_info(info).map_err(|e| format!("{:?}", e)));
+    for &(name, mx) in [(UNIFORM_TRANSFORM, mvp), (UNIFORM_WORLD, model)].iter() {
+        let value = gfx::shade::UniformValue::F32Matrix4(mx.clone().into_fixed());
+        try!(param::bind_uniform(&mut dict, info, name, value)
+                   .map_err(|e| format!("{:?}", e)));
+    }
+    let mut batch = try!(gfx::batch::Full::new(mesh.clone(), program.clone(), dict)
+                             .map_err(|e| format!("{:?}", e)));
+    batch.slice = fragment.slice.clone();
+    batch.state = batch.state.depth(gfx::state::Comparison::LessEqual, true);
+    if let Transparency::Blend(ref preset) = fragment.material.transparency {
+        batch.state = batch.state.blend(preset.clone());
+    }
+    stream.draw(&batch).map_err(|e| format!("{:?}", e))
+}
+
+/// Draw the fragments of an entity that have the programs of their
+/// parameters, binding the parameters for every call.
+fn draw_parameters<R, E, X>(entity: &E, params: &[Option<&param::Parameters<R>>],
+                            view_proj: &cgmath::Matrix4<f32>,
+                            report: &mut gfx_scene::Report, stream: &mut X) where
+    R: gfx::Resources,
+    E: gfx_scene::Entity<R, Material<R>, Bound = cgmath::Aabb3<f32>>
+     + gfx_scene::Node<Transform = Transform<f32>>,
+    X: gfx::Stream<R>,
+{
+    use cgmath::Matrix;
+    if params.iter().all(|p| p.is_none()) {
+        return
+    }
+    let model: cgmath::Matrix4<f32> = gfx_scene::Node::get_transform(entity).into();
+    let mvp = view_proj.mul_m(&model);
+    let visible = gfx_scene::Entity::is_visible(entity);
+    let culled = is_outside(&gfx_scene::Entity::get_bound(entity), &mvp);
+    let mesh = gfx_scene::Entity::get_mesh(entity);
+    for (fragment, p) in gfx_scene::Entity::get_fragments(entity).iter().zip(params.iter()) {
+        let (params, program) = match *p {
+            Some(par) => match par.program {
+                Some(ref program) => (par, program),
+                None => continue,
+            },
+            None => continue,
+        };
+        if !visible {
+            report.calls_invisible += 1;
+        } else if culled {
+            report.calls_culled += 1;
+        } else {
+            match draw_fragment(mesh, fragment, params, program, &mvp, &model, stream) {
+                Ok(()) => report.calls_passed += 1,
+                Err(e) => {
+                    error!("Unable to draw a fragment with shader {}: {}", params.shader, e);
+                    report.calls_failed += 1;
+                },
+            }
+        }
+    }
+}
+
 /// An example scene type.
 pub struct Scene<R: gfx::Resources, S: cgmath::BaseNum> {
     pub entities: Vec<Entity<R, S>>,
+    pub instances: Vec<Instances<R, S>>,
     pub cameras: Vec<Camera<S>>,
+    pub lights: Vec<Light<S>>,
+    pub animations: Vec<anim::Animation<S>>,
+    /// Generic parameters of the loaded materials, by assets directory
+    /// and material name, since different scenes may reuse the names.
+    pub materials: HashMap<(String, String), param::Parameters<R>>,
+    /// Gravity of the loaded description, if any.
+    pub gravity: Option<cgmath::Vector3<S>>,
     pub world: World<S>,
 }
 
@@ -122,31 +799,439 @@ impl<
     pub fn new() -> Scene<R, S> {
         Scene {
             entities: Vec::new(),
+            instances: Vec::new(),
             cameras: Vec::new(),
+            lights: Vec::new(),
+            animations: Vec::new(),
+            materials: HashMap::new(),
+            gravity: None,
             world: space::World::new(),
         }
     }
+
+    /// Parameters of each fragment described by the source,
+    /// if they come with a program.
+    fn get_parameters(&self, source: Option<&Source>, count: usize)
+                      -> Vec<Option<&param::Parameters<R>>> {
+        match source {
+            Some(s) if !self.materials.is_empty() => (0.. count).map(|i|
+                s.materials.get(i)
+                 .and_then(|name| self.materials.get(&(s.assets.clone(), name.clone())))
+                 .and_then(|p| if p.program.is_some() {Some(p)} else {None})
+            ).collect(),
+            _ => vec![None; count],
+        }
+    }
+
+    pub fn find_camera(&self, name: &str) -> Option<&Camera<S>> {
+        self.cameras.iter().find(|c| c.name == name)
+    }
+
+    /// Collect the lights reaching a bound, given its transformation.
+    pub fn find_lights(&self, bound: &cgmath::Aabb3<S>, transform: &Transform<S>)
+                       -> Vec<&Light<S>> {
+        self.lights.iter()
+            .filter(|l| l.affects(bound, transform, &self.world))
+            .collect()
+    }
+
+    /// Collect the lights reaching an entity, for forward shading.
+    pub fn get_entity_lights(&self, entity: &Entity<R, S>) -> Vec<&Light<S>> {
+        let transform = &self.world.get_node(entity.node).world;
+        self.find_lights(&entity.bound, transform)
+    }
 }
 
-impl<
-    R: gfx::Resources,
-    S: cgmath::BaseFloat,
-> gfx_scene::AbstractScene<R> for Scene<R, S> {
-    type ViewInfo = ViewInfo<S>;
+impl<R: gfx::Resources> Scene<R, f32> {
+    /// Add a camera framing all the entities, for the scenes that have none.
+    /// Returns the index of the new camera.
+    pub fn add_default_camera(&mut self) -> usize {
+        use cgmath::{Aabb, Aabb3, EuclideanVector, Point, Point3, Transform, Vector, Vector3};
+        self.world.update();
+        let mut bound: Option<Aabb3<f32>> = None;
+        for e in self.entities.iter() {
+            let b = transform_bound(&e.bound, &self.world.get_node(e.node).world);
+            bound = Some(match bound {
+                Some(acc) => acc.grow(&b.min).grow(&b.max),
+                None => b,
+            });
+        }
+        let (center, radius) = match bound {
+            Some(b) => (b.center(), (b.max.sub_p(&b.min).length() * 0.5).max(0.1)),
+            None => (Point3::new(0.0, 0.0, 0.0), 1.0),
+        };
+        let fovy = 45.0f32.to_radians();
+        // fit the bounding sphere, looking from the front and above
+        let distance = radius / (fovy * 0.5).sin();
+        let dir = Vector3::new(0.0, -1.0, 0.5).normalize();
+        let eye = center.add_v(&dir.mul_s(distance));
+        let view: ::Transform<f32> = Transform::look_at(&eye, &center, &Vector3::unit_z());
+        let node = self.world.add_node("DefaultCamera".to_string(),
+                                       space::Parent::None,
+                                       view.invert().unwrap());
+        self.cameras.push(Camera {
+            name: "Default".to_string(),
+            projection: Projection::Perspective(cgmath::PerspectiveFov {
+                fovy: cgmath::rad(fovy),
+                aspect: 1.0,
+                near: distance * 0.01,
+                far: distance + radius * 2.0,
+            }),
+            node: node,
+        });
+        self.cameras.len() - 1
+    }
+}
+
+/// Fragments of the materials that have a program in their parameters
+/// are drawn with it, the rest are left for the phase.
+impl<R: gfx::Resources> gfx_scene::AbstractScene<R> for Scene<R, f32> {
+    type ViewInfo = ViewInfo<f32>;
     type Material = Material<R>;
-    type Camera = Camera<S>;
+    type Camera = Camera<f32>;
     type Status = gfx_scene::Report;
 
-    fn draw<H, X>(&self, phase: &mut H, camera: &Camera<S>,
+    fn draw<H, X>(&self, phase: &mut H, camera: &Camera<f32>,
             stream: &mut X) -> Result<gfx_scene::Report, gfx_scene::Error> where
-        H: gfx_scene::AbstractPhase<R, Material<R>, ViewInfo<S>>,
+        H: gfx_scene::AbstractPhase<R, Material<R>, ViewInfo<f32>>,
         X: gfx::Stream<R>,
     {
         let mut culler = gfx_scene::Frustum::new();
-        let entities: Vec<_> = self.entities.iter() //TODO: avoid allocation
-                                            .map(|e| Pair(e, &self.world))
-                                            .collect();
-        gfx_scene::Context::new(&mut culler, &Pair(camera, &self.world))
-                           .draw(entities.iter(), phase, stream)
+        let cam = Pair(camera, &self.world);
+        let view_proj = camera.get_view_projection(&self.world);
+        let mut items: Vec<_> = self.entities.iter().map(|e| { //TODO: avoid allocation
+            let transform = &self.world.get_node(e.node).world;
+            let lod = e.select_lod(transform, camera, &self.world);
+            Item::Single(Pair(e, &self.world), lod)
+        }).collect();
+        // groups without the instance buffer are expanded
+        // into separate calls, which also culls each instance
+        for group in self.instances.iter().filter(|g| g.instanced.is_none()) {
+            items.extend((0..group.transforms.len()).map(|i|
+                Item::Instance(Pair((group, i), &self.world))
+            ));
+        }
+        let params: Vec<_> = items.iter().map(|item| self.get_parameters(
+            item.get_source(), gfx_scene::Entity::get_fragments(item).len()
+        )).collect();
+        let entities: Vec<_> = items.into_iter().zip(params.iter()).map(|(item, p)| {
+            let rest = split_fragments(gfx_scene::Entity::get_fragments(&item), p);
+            Remainder(item, rest)
+        }).collect();
+        let mut report = try!(gfx_scene::Context::new(&mut culler, &cam)
+                                                 .draw(entities.iter(), phase, stream));
+        if let Some(ref vp) = view_proj {
+            for (e, p) in entities.iter().zip(params.iter()) {
+                draw_parameters(&e.0, p, vp, &mut report, stream);
+            }
+        }
+        // the rest is culled as a whole, with one instanced call per fragment
+        for group in self.instances.iter() {
+            let uploaded = match group.instanced {
+                Some(ref up) if !group.transforms.is_empty() => up,
+                _ => continue,
+            };
+            let params = self.get_parameters(group.source.as_ref(), group.fragments.len());
+            let rest = split_fragments(&group.fragments, &params);
+            let item = [Remainder(Pair((group, uploaded), &self.world), rest)];
+            let mut instanced = InstancedStream {
+                stream: &mut *stream,
+                count: group.transforms.len() as gfx::InstanceCount,
+            };
+            let r = try!(gfx_scene::Context::new(&mut culler, &cam)
+                                            .draw(item.iter(), phase, &mut instanced));
+            report.calls_invisible += r.calls_invisible;
+            report.calls_culled += r.calls_culled;
+            report.calls_rejected += r.calls_rejected;
+            report.calls_failed += r.calls_failed;
+            report.calls_passed += r.calls_passed;
+            report.primitives_rendered += r.primitives_rendered;
+            if let Some(ref vp) = view_proj {
+                draw_parameters(&item[0].0, &params, vp, &mut report, &mut instanced);
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath;
+    use cgmath::{Decomposed, Point, Point3, Quaternion, Vector, Vector3};
+    use gfx;
+    use gfx::device::dummy::DummyResources;
+    use super::*;
+    use super::is_outside;
+
+    fn translation(x: f32, y: f32, z: f32) -> Transform<f32> {
+        Decomposed {
+            scale: 1.0,
+            rot: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            disp: Vector3::new(x, y, z),
+        }
+    }
+
+    fn apply(mx: &[[f32; 4]; 4], p: [f32; 3]) -> [f32; 3] {
+        let mut out = [0.0; 3];
+        for i in 0.. 3 {
+            out[i] = mx[0][i] * p[0] + mx[1][i] * p[1] + mx[2][i] * p[2] + mx[3][i];
+        }
+        out
+    }
+
+    fn assert_near(a: [f32; 3], b: [f32; 3]) {
+        for i in 0.. 3 {
+            assert!((a[i] - b[i]).abs() < 1e-5, "{:?} != {:?}", a, b);
+        }
+    }
+
+    fn assert_identity(mx: &[[f32; 4]; 4]) {
+        for i in 0.. 4 {
+            for j in 0.. 4 {
+                let expected = if i == j {1.0} else {0.0};
+                assert!((mx[i][j] - expected).abs() < 1e-5, "{:?} is not identity", mx);
+            }
+        }
+    }
+
+    /// Skeleton with a "root" bone at (0,0,1) and a "tip" bone one unit above it.
+    fn make_skeleton() -> (World<f32>, NodeId<f32>, SkeletonId<f32>, BoneId<f32>) {
+        let mut world = World::new();
+        let node = world.add_node("armature".to_string(), Parent::None,
+                                  translation(0.0, 0.0, 0.0));
+        let skel = world.add_skeleton("skeleton".to_string(), node);
+        let root = world.add_bone(skel, "root".to_string(), None,
+                                  translation(0.0, 0.0, 1.0));
+        let tip = world.add_bone(skel, "tip".to_string(), Some(root),
+                                 translation(0.0, 1.0, 0.0));
+        world.update();
+        (world, node, skel, tip)
+    }
+
+    #[test]
+    fn palette_bind_pose() {
+        let (world, _, skel, _) = make_skeleton();
+        let palette = get_palette_matrices(&world, skel);
+        assert_eq!(palette.len(), 3);
+        for mx in palette.iter() {
+            assert_identity(mx);
+        }
+    }
+
+    #[test]
+    fn palette_moved_bone() {
+        let (mut world, _, skel, tip) = make_skeleton();
+        // rotate the tip by 90 degrees around Z
+        let half = (0.5f32).sqrt();
+        world.mut_skeleton(skel).mut_bone(tip).local.rot = Quaternion::new(half, 0.0, 0.0, half);
+        world.update();
+        let palette = get_palette_matrices(&world, skel);
+        assert_identity(&palette[0]);
+        assert_identity(&palette[1]);
+        // the rotation pivots around the tip head at (0,1,1)
+        assert_near(apply(&palette[2], [0.0, 1.0, 1.0]), [0.0, 1.0, 1.0]);
+        assert_near(apply(&palette[2], [1.0, 1.0, 1.0]), [0.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn palette_index_base() {
+        let (mut world, node, skel, _) = make_skeleton();
+        world.mut_node(node).local = translation(5.0, 0.0, 0.0);
+        world.update();
+        let palette = get_palette_matrices(&world, skel);
+        // slot 0 is the skeleton node, bone N is at slot N+1
+        assert_near(apply(&palette[0], [0.0, 0.0, 0.0]), [5.0, 0.0, 0.0]);
+        assert_near(apply(&palette[1], [0.0, 0.0, 1.0]), [5.0, 0.0, 1.0]);
+        assert_near(apply(&palette[2], [0.0, 1.0, 1.0]), [5.0, 1.0, 1.0]);
+    }
+
+    fn make_camera(projection: Projection<f32>) -> (World<f32>, Camera<f32>) {
+        let mut world = World::new();
+        let mut transform = translation(1.0, 2.0, 10.0);
+        // look slightly to the side, around Z
+        transform.rot = Quaternion::new(0.9659258, 0.0, 0.0, 0.25881904);
+        let node = world.add_node("camera".to_string(), Parent::None, transform);
+        world.update();
+        (world, Camera {
+            name: "camera".to_string(),
+            projection: projection,
+            node: node,
+        })
+    }
+
+    fn check_round_trip(projection: Projection<f32>) {
+        let (world, camera) = make_camera(projection);
+        for &(x, y) in [(0.5, 0.5), (0.25, 0.75), (0.9, 0.1), (0.0, 1.0)].iter() {
+            let ray = camera.ray_from_screen(x, y, &world).unwrap();
+            for &t in [0.0, 1.0, 5.0].iter() {
+                let p = ray.origin.add_v(&ray.direction.mul_s(t));
+                let s = camera.project_point(&p, &world).unwrap();
+                assert!((s.x - x).abs() < 1e-4 && (s.y - y).abs() < 1e-4,
+                    "({}, {}) at distance {} is projected to {:?}", x, y, t, s);
+            }
+        }
+    }
+
+    #[test]
+    fn camera_round_trip_perspective() {
+        check_round_trip(Projection::Perspective(cgmath::PerspectiveFov {
+            fovy: cgmath::rad(1.0),
+            aspect: 1.5,
+            near: 0.5,
+            far: 100.0,
+        }));
+    }
+
+    #[test]
+    fn camera_round_trip_ortho() {
+        check_round_trip(Projection::Ortho(cgmath::Ortho {
+            left: -3.0, right: 3.0,
+            bottom: -2.0, top: 2.0,
+            near: 0.5, far: 100.0,
+        }));
+    }
+
+    #[test]
+    fn camera_behind() {
+        let (world, camera) = make_camera(Projection::Perspective(cgmath::PerspectiveFov {
+            fovy: cgmath::rad(1.0),
+            aspect: 1.0,
+            near: 0.5,
+            far: 100.0,
+        }));
+        assert!(camera.project_point(&Point3::new(1.0, 2.0, 20.0), &world).is_none());
+    }
+
+    #[test]
+    fn camera_degenerate() {
+        let (mut world, camera) = make_camera(Projection::Ortho(cgmath::Ortho {
+            left: -1.0, right: 1.0,
+            bottom: -1.0, top: 1.0,
+            near: 0.0, far: 1.0,
+        }));
+        world.mut_node(camera.node).local.scale = 0.0;
+        world.update();
+        assert!(camera.get_view(&world).is_none());
+        assert!(camera.ray_from_screen(0.5, 0.5, &world).is_none());
+        assert!(camera.project_point(&Point3::new(0.0, 0.0, 0.0), &world).is_none());
+    }
+
+    fn make_light(node: NodeId<f32>, kind: LightKind<f32>, spherical: bool) -> Light<f32> {
+        Light {
+            name: "light".to_string(),
+            node: node,
+            kind: kind,
+            color: [1.0, 1.0, 1.0],
+            energy: 1.0,
+            distance: 5.0,
+            attenuation: (0.0, 1.0),
+            spherical: spherical,
+        }
+    }
+
+    /// Box of the given half size, with the bounding sphere radius of `size * sqrt(3)`.
+    fn make_bound(size: f32) -> cgmath::Aabb3<f32> {
+        cgmath::Aabb3::new(Point3::new(-size, -size, -size), Point3::new(size, size, size))
+    }
+
+    /// Check if the light on a node with the given transform
+    /// reaches a box of the given half size at `pos`.
+    fn reaches(kind: LightKind<f32>, spherical: bool, light: Transform<f32>,
+               size: f32, pos: [f32; 3]) -> bool {
+        let mut world = World::new();
+        let node = world.add_node("light".to_string(), Parent::None, light);
+        world.update();
+        let light = make_light(node, kind, spherical);
+        light.affects(&make_bound(size), &translation(pos[0], pos[1], pos[2]), &world)
+    }
+
+    fn spot() -> LightKind<f32> {
+        // 30 degrees from the axis
+        LightKind::Spot(cgmath::rad(::std::f32::consts::PI / 3.0), 0.1)
+    }
+
+    #[test]
+    fn light_point() {
+        let origin = translation(0.0, 0.0, 0.0);
+        assert!(reaches(LightKind::Point, true, origin, 1.0, [0.0, 0.0, -6.0]));
+        assert!(!reaches(LightKind::Point, true, origin, 1.0, [0.0, 0.0, -10.0]));
+        assert!(reaches(LightKind::Point, false, origin, 1.0, [0.0, 0.0, -10.0]));
+        let moved = translation(0.0, 0.0, -8.0);
+        assert!(reaches(LightKind::Point, true, moved, 1.0, [0.0, 0.0, -10.0]));
+        assert!(reaches(LightKind::Directional, true, origin, 1.0, [0.0, 100.0, 0.0]));
+        assert!(reaches(LightKind::Hemisphere, true, origin, 1.0, [0.0, 100.0, 0.0]));
+    }
+
+    #[test]
+    fn light_spot_cone() {
+        let origin = translation(0.0, 0.0, 0.0);
+        // on the axis, inside the cone and outside of it
+        assert!(reaches(spot(), false, origin, 0.1, [0.0, 0.0, -10.0]));
+        assert!(reaches(spot(), false, origin, 0.1, [5.0, 0.0, -10.0]));
+        assert!(!reaches(spot(), false, origin, 0.1, [10.0, 0.0, -10.0]));
+        // the box is large enough to touch the cone
+        assert!(reaches(spot(), false, origin, 2.5, [10.0, 0.0, -10.0]));
+        // behind the light, unless the box contains it
+        assert!(!reaches(spot(), false, origin, 0.1, [0.0, 0.0, 10.0]));
+        assert!(!reaches(spot(), false, origin, 0.5, [0.0, 0.0, 1.0]));
+        assert!(reaches(spot(), false, origin, 1.0, [0.0, 0.0, 1.0]));
+        // limited by the distance
+        assert!(reaches(spot(), true, origin, 0.1, [0.0, 0.0, -4.0]));
+        assert!(!reaches(spot(), true, origin, 0.1, [0.0, 0.0, -10.0]));
+    }
+
+    #[test]
+    fn light_spot_rotated() {
+        // turned to shine along the positive X
+        let half = ::std::f32::consts::FRAC_1_SQRT_2;
+        let light = Decomposed {
+            scale: 1.0,
+            rot: Quaternion::new(half, 0.0, -half, 0.0),
+            disp: Vector3::new(0.0, 0.0, 0.0),
+        };
+        assert!(reaches(spot(), false, light, 0.1, [10.0, 0.0, 0.0]));
+        assert!(!reaches(spot(), false, light, 0.1, [0.0, 0.0, -10.0]));
+        assert!(!reaches(spot(), false, light, 0.1, [-10.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn entity_lights() {
+        let mut scene: Scene<DummyResources, f32> = Scene::new();
+        let origin = scene.world.add_node("origin".to_string(), Parent::None,
+                                          translation(0.0, 0.0, 0.0));
+        let far = scene.world.add_node("far".to_string(), Parent::None,
+                                       translation(0.0, 0.0, -20.0));
+        let node = scene.world.add_node("entity".to_string(), Parent::None,
+                                        translation(0.0, 0.0, -10.0));
+        scene.world.update();
+        scene.entities.push(Entity::new(gfx::Mesh::new(0), node, make_bound(1.0)));
+        let kinds = [
+            ("point", origin, LightKind::Point, true),
+            ("point far", far, LightKind::Point, false),
+            ("sun", origin, LightKind::Directional, true),
+            ("spot", origin, spot(), false),
+            ("spot short", origin, spot(), true),
+            ("spot away", far, spot(), false),
+        ];
+        for &(name, node, ref kind, spherical) in kinds.iter() {
+            let mut light = make_light(node, kind.clone(), spherical);
+            light.name = name.to_string();
+            scene.lights.push(light);
+        }
+        let names: Vec<_> = scene.get_entity_lights(&scene.entities[0])
+                                 .iter().map(|l| &l.name[..]).collect();
+        assert_eq!(names, vec!["point far", "sun", "spot"]);
+    }
+
+    #[test]
+    fn outside() {
+        let mvp = cgmath::Matrix4::identity();
+        let bound = |lo: [f32; 3], hi: [f32; 3]| cgmath::Aabb3::new(
+            Point3::new(lo[0], lo[1], lo[2]), Point3::new(hi[0], hi[1], hi[2]));
+        assert!(!is_outside(&bound([-0.5, -0.5, -0.5], [0.5, 0.5, 0.5]), &mvp));
+        assert!(!is_outside(&bound([-5.0, -5.0, -5.0], [5.0, 5.0, 5.0]), &mvp));
+        assert!(!is_outside(&bound([0.5, 0.5, 0.5], [2.0, 2.0, 2.0]), &mvp));
+        assert!(is_outside(&bound([2.0, -0.5, -0.5], [3.0, 0.5, 0.5]), &mvp));
+        assert!(is_outside(&bound([-0.5, -0.5, -3.0], [0.5, 0.5, -2.0]), &mvp));
     }
 }
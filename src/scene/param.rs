@@ -0,0 +1,158 @@
+//! Generic material parameters, for the shaders that need more
+//! than the fixed `Material` of the pipeline.
+//!
+//! When the parameters come with a program, the scene draws their
+//! fragments with it, binding the parameters for every call.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use gfx;
+use gfx::shade::{NamedCell, ParamDictionary, ProgramInfo, UniformValue};
+
+pub type TextureParam<R> = gfx::shade::TextureParam<R>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Value does not match the uniform type declared by the program.
+    Uniform(String, gfx::shade::CompatibilityError),
+}
+
+/// Named shader parameters of a material. The names include
+/// the prefixes used by the shaders, like "t_" for textures.
+#[derive(Clone)]
+pub struct Parameters<R: gfx::Resources> {
+    /// Name of the program the material is made for.
+    pub shader: String,
+    /// The program itself, if it is available.
+    pub program: Option<gfx::handle::Program<R>>,
+    pub uniforms: HashMap<String, UniformValue>,
+    pub textures: HashMap<String, TextureParam<R>>,
+}
+
+impl<R: gfx::Resources> Parameters<R> {
+    pub fn new(shader: String) -> Parameters<R> {
+        Parameters {
+            shader: shader,
+            program: None,
+            uniforms: HashMap::new(),
+            textures: HashMap::new(),
+        }
+    }
+
+    /// Collect the parameters used by the program into a dictionary,
+    /// checking the uniform types against its reflection. Program inputs
+    /// that are not known to the material are left for the caller to add,
+    /// see `bind_uniform`.
+    pub fn bind(&self, program: &gfx::handle::Program<R>)
+                -> Result<ParamDictionary<R>, Error> {
+        self.bind_info(program.get_info())
+    }
+
+    /// Collect the parameters used by a program with the given reflection.
+    pub fn bind_info(&self, info: &ProgramInfo) -> Result<ParamDictionary<R>, Error> {
+        let mut dict = ParamDictionary {
+            uniforms: Vec::new(),
+            blocks: Vec::new(),
+            textures: Vec::new(),
+        };
+        for var in info.uniforms.iter() {
+            if let Some(value) = self.uniforms.get(&var.name) {
+                try!(var.is_compatible(value).map_err(|e| Error::Uniform(var.name.clone(), e)));
+                dict.uniforms.push(NamedCell {
+                    name: var.name.clone(),
+                    value: RefCell::new(*value),
+                });
+            }
+        }
+        for var in info.textures.iter() {
+            if let Some(tex) = self.textures.get(&var.name) {
+                dict.textures.push(NamedCell {
+                    name: var.name.clone(),
+                    value: RefCell::new(tex.clone()),
+                });
+            }
+        }
+        Ok(dict)
+    }
+}
+
+/// Add a uniform to the dictionary if the program uses it, checking
+/// the value type. Returns true if the uniform was added.
+pub fn bind_uniform<R: gfx::Resources>(dict: &mut ParamDictionary<R>, info: &ProgramInfo,
+                    name: &str, value: UniformValue) -> Result<bool, Error> {
+    match info.uniforms.iter().find(|var| var.name == name) {
+        Some(var) => {
+            try!(var.is_compatible(&value).map_err(|e| Error::Uniform(var.name.clone(), e)));
+            dict.uniforms.push(NamedCell {
+                name: var.name.clone(),
+                value: RefCell::new(value),
+            });
+            Ok(true)
+        },
+        None => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gfx;
+    use gfx::device::dummy::DummyResources;
+    use gfx::shade::{BaseType, ContainerType, ParamDictionary, ProgramInfo,
+                     UniformValue, UniformVar};
+    use super::{bind_uniform, Parameters};
+
+    fn var(name: &str, location: usize, container: ContainerType) -> UniformVar {
+        UniformVar {
+            name: name.to_string(),
+            location: location as gfx::shade::Location,
+            count: 1,
+            base_type: BaseType::F32,
+            container: container,
+        }
+    }
+
+    fn info() -> ProgramInfo {
+        ProgramInfo {
+            attributes: Vec::new(),
+            uniforms: vec![
+                var("u_Color", 0, ContainerType::Vector(4)),
+                var("u_Gloss", 1, ContainerType::Single),
+            ],
+            blocks: Vec::new(),
+            textures: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn bind_known() {
+        let mut params: Parameters<DummyResources> = Parameters::new("test".to_string());
+        params.uniforms.insert("u_Gloss".to_string(), UniformValue::F32(0.5));
+        params.uniforms.insert("u_Color".to_string(),
+            UniformValue::F32Vector4([1.0, 0.0, 0.0, 1.0]));
+        params.uniforms.insert("u_Unused".to_string(), UniformValue::F32(1.0));
+        let dict = params.bind_info(&info()).unwrap();
+        let names: Vec<_> = dict.uniforms.iter().map(|c| &c.name[..]).collect();
+        assert_eq!(names, ["u_Color", "u_Gloss"]);
+    }
+
+    #[test]
+    fn bind_incompatible() {
+        let mut params: Parameters<DummyResources> = Parameters::new("test".to_string());
+        params.uniforms.insert("u_Gloss".to_string(), UniformValue::F32Vector2([0.5, 0.5]));
+        assert!(params.bind_info(&info()).is_err());
+    }
+
+    #[test]
+    fn bind_extra() {
+        let info = info();
+        let mut dict: ParamDictionary<DummyResources> = ParamDictionary {
+            uniforms: Vec::new(),
+            blocks: Vec::new(),
+            textures: Vec::new(),
+        };
+        assert!(bind_uniform(&mut dict, &info, "u_Gloss", UniformValue::F32(0.5)).unwrap());
+        assert!(!bind_uniform(&mut dict, &info, "u_Transform", UniformValue::F32(0.5)).unwrap());
+        assert!(bind_uniform(&mut dict, &info, "u_Color", UniformValue::F32(0.5)).is_err());
+        assert_eq!(dict.uniforms.len(), 1);
+    }
+}
@@ -35,6 +35,39 @@ pub struct Skeleton<T> {
     bones: Array<Bone<T>>,
 }
 
+impl<T> Bone<T> {
+    pub fn get_parent(&self) -> Option<Id<Bone<T>>> {
+        self.parent
+    }
+
+    /// Local transform in the rest position.
+    pub fn get_bind_pose(&self) -> &T {
+        &self.bind_pose
+    }
+}
+
+impl<T> Skeleton<T> {
+    pub fn get_node(&self) -> Id<Node<T>> {
+        self.node
+    }
+
+    pub fn get_bone(&self, id: Id<Bone<T>>) -> &Bone<T> {
+        self.bones.get(id)
+    }
+
+    pub fn mut_bone(&mut self, id: Id<Bone<T>>) -> &mut Bone<T> {
+        self.bones.get_mut(id)
+    }
+
+    pub fn find_bone(&self, name: &str) -> Option<Id<Bone<T>>> {
+        self.bones.find_id(|b| b.name == name)
+    }
+
+    pub fn iter_bones<'a>(&'a self) -> slice::Iter<'a, Bone<T>> {
+        self.bones.iter()
+    }
+}
+
 #[derive(Debug)]
 pub struct World<S, T> {
     nodes: Array<Node<T>>,
@@ -78,6 +111,71 @@ impl<S: BaseFloat, T: Transform3<S> + Clone> World<S, T> {
         self.nodes.iter()
     }
 
+    pub fn get_skeleton(&self, id: Id<Skeleton<T>>) -> &Skeleton<T> {
+        self.skeletons.get(id)
+    }
+
+    pub fn mut_skeleton(&mut self, id: Id<Skeleton<T>>) -> &mut Skeleton<T> {
+        self.skeletons.get_mut(id)
+    }
+
+    pub fn find_skeleton(&self, name: &str) -> Option<Id<Skeleton<T>>> {
+        self.skeletons.find_id(|s| s.name == name)
+    }
+
+    pub fn iter_skeletons<'a>(&'a self) -> slice::Iter<'a, Skeleton<T>> {
+        self.skeletons.iter()
+    }
+
+    /// Create an empty skeleton, attached to a node.
+    pub fn add_skeleton(&mut self, name: String, node: Id<Node<T>>)
+                        -> Id<Skeleton<T>> {
+        self.skeletons.add(Skeleton {
+            name: name,
+            node: node,
+            bones: Array::new(),
+        })
+    }
+
+    /// Add a bone to the skeleton, given its local transform in the
+    /// rest position. Parents have to be added before their children.
+    pub fn add_bone(&mut self, skeleton: Id<Skeleton<T>>, name: String,
+                    parent: Option<Id<Bone<T>>>, local: T) -> Id<Bone<T>> {
+        let skel = self.skeletons.get_mut(skeleton);
+        let bind_root = match parent {
+            Some(pid) => {
+                let inverse = &skel.bones.get(pid).bind_pose_root_inverse;
+                match inverse.invert() {
+                    Some(base) => base.concat(&local),
+                    None => local.clone(),
+                }
+            },
+            None => local.clone(),
+        };
+        skel.bones.add(Bone {
+            name: name,
+            parent: parent,
+            local: local.clone(),
+            world: Transform::identity(),
+            bind_pose: local,
+            bind_pose_root_inverse: bind_root.invert()
+                                             .unwrap_or(Transform::identity()),
+        })
+    }
+
+    /// Skinning transforms of a skeleton, valid after `update`.
+    /// The first one moves the vertices with the skeleton node, followed
+    /// by the world transform times the inverse bind pose of each bone,
+    /// matching the bone indices of the exporter.
+    pub fn get_skinning_palette(&self, id: Id<Skeleton<T>>) -> Vec<T> {
+        let skel = self.skeletons.get(id);
+        let mut palette = vec![self.nodes.get(skel.node).world.clone()];
+        palette.extend(skel.bones.iter().map(|b|
+            b.world.concat(&b.bind_pose_root_inverse)
+        ));
+        palette
+    }
+
     pub fn update(&mut self) {
         let skeletons = &mut self.skeletons;
         self.nodes.walk_looking_back(|left, n| {